@@ -0,0 +1 @@
+pub mod cli;
\ No newline at end of file
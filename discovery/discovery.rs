@@ -0,0 +1,276 @@
+use std::collections::{HashSet, VecDeque};
+
+use anyhow::anyhow;
+use futures::stream::{self, StreamExt};
+use regex::Regex;
+use scraper::{Html, Selector};
+use url::Url;
+
+use crate::report::report::{Finding, CONFIDENCE, SEVERITY};
+use crate::sender::client::client;
+use crate::sender::crawler::parse_file;
+use crate::sender::fingerprint::not_found;
+use crate::sender::limiter::limiter;
+use crate::sender::session::is_logout;
+
+// Directory levels brute-forced below the start url, --brute-depth overrides it
+pub const DEFAULT_BRUTE_DEPTH: usize = 2;
+
+// Directories brute-forced per run, recursion into a large tree stops here
+const MAX_DIRECTORIES: usize = 50;
+
+// Hits in one directory past which it is taken for a wildcard the soft-404 check missed
+const MAX_HITS: usize = 100;
+
+// Used when --discover is given without --wordlist
+const WORDLIST: &[&str] = &[
+    "admin/", "administrator/", "admin.php", "login", "login.php", "wp-admin/", "wp-login.php", "phpmyadmin/",
+    "adminer.php", "manager/html", "dashboard/", "console/", "api/", "api/v1/", "graphql", "swagger.json",
+    "swagger-ui.html", "openapi.json", "v2/api-docs", "actuator", "actuator/env", "server-status", "server-info",
+    "phpinfo.php", "info.php", "test.php", ".git/HEAD", ".git/config", ".svn/entries", ".hg/store", ".env",
+    ".env.local", ".env.backup", ".htaccess", ".htpasswd", ".DS_Store", "config.php.bak", "wp-config.php.bak",
+    "web.config", "backup/", "backups/", "backup.zip", "backup.sql", "dump.sql", "db.sql", "database.sql",
+    "site.tar.gz", "www.zip", "old/", "dev/", "staging/", "tmp/", "uploads/", "files/", "private/", "logs/",
+    "debug/", "config/", "includes/", "cgi-bin/", "crossdomain.xml", "composer.json", "package.json",
+];
+
+// Paths that say more than "it exists": pattern on the path, title, severity and CWE
+const INTERESTING: &[(&str, &str, SEVERITY, u32)] = &[
+    (r"(?i)/\.(git|svn|hg|bzr)(/|$)", "Exposed version control metadata", SEVERITY::HIGH, 527),
+    (r"(?i)/\.env(\.[a-z]+)?$|/\.htpasswd$", "Exposed credentials file", SEVERITY::HIGH, 538),
+    (r"(?i)\.(sql|sql\.gz|bak|old|orig|save|swp|backup)$|~$", "Exposed backup or database dump", SEVERITY::HIGH, 530),
+    (r"(?i)/(backups?|www|site|web)\.(zip|tar|tar\.gz|tgz|rar|7z)$", "Exposed source archive", SEVERITY::HIGH, 530),
+    (r"(?i)/(php)?info\.php$|/actuator/env$|/server-(status|info)$", "Exposed server diagnostics", SEVERITY::MEDIUM, 200),
+    (
+        r"(?i)/(admin|administrator|wp-admin|phpmyadmin|adminer|manager|dashboard|console)(/|\.php|/html)?$",
+        "Exposed admin panel",
+        SEVERITY::MEDIUM,
+        284,
+    ),
+    (r"(?i)/(swagger(-ui)?\.(json|html)|openapi\.json|v2/api-docs|graphql)$", "Exposed API description", SEVERITY::LOW, 200),
+];
+
+// Forced browsing, set from --discover, --wordlist, --extensions and --brute-depth
+#[derive(Debug, Clone)]
+pub struct DiscoveryOptions {
+    // Word list files, the built-in list when empty
+    pub wordlists: Vec<String>,
+    // Tried on every word without one, "php" asks for /word and /word.php
+    pub extensions: Vec<String>,
+    pub max_depth: usize,
+}
+
+impl Default for DiscoveryOptions {
+    fn default() -> Self {
+        DiscoveryOptions {
+            wordlists: vec![],
+            extensions: vec![],
+            max_depth: DEFAULT_BRUTE_DEPTH,
+        }
+    }
+}
+
+// A path that answered with something other than the server's not-found page
+#[derive(Debug, Clone)]
+pub struct Hit {
+    pub url: String,
+    pub status: u16,
+    pub length: usize,
+    pub title: Option<String>,
+}
+
+// Words of the lists in options, comments and blank lines dropped, each word once
+pub async fn load_words(options: &DiscoveryOptions) -> Result<Vec<String>, anyhow::Error> {
+    let mut words: Vec<String> = vec![];
+    if options.wordlists.is_empty() {
+        words.extend(WORDLIST.iter().map(|word| word.to_string()));
+    }
+    for file in &options.wordlists {
+        let lines = parse_file(file).await.map_err(|err| anyhow!("can't read wordlist {}: {}", file, err))?;
+        words.extend(
+            lines
+                .iter()
+                .map(|line| line.trim().trim_start_matches('/'))
+                .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                .map(String::from),
+        );
+    }
+    let mut seen = HashSet::new();
+    words.retain(|word| seen.insert(word.clone()));
+    Ok(words)
+}
+
+fn title(body: &str) -> Option<String> {
+    let document = Html::parse_document(body);
+    let selector = Selector::parse("title").unwrap();
+    let title = document.select(&selector).next()?.text().collect::<String>();
+    Some(title.split_whitespace().collect::<Vec<_>>().join(" ")).filter(|title| !title.is_empty())
+}
+
+pub struct DIRBUSTER<'a> {
+    pub base: &'a str,
+    pub words: &'a [String],
+    pub options: &'a DiscoveryOptions,
+}
+
+impl DIRBUSTER<'_> {
+    // Every word, and every word with each extension, in base's directory, then again in each directory found
+    // until options.max_depth. Requests go through the shared client, so rate limits and scope hold
+    pub async fn run(self) -> Vec<Hit> {
+        let Ok(root) = Url::parse(&format!("{}/", self.base.trim_end_matches('/'))) else { return vec![] };
+        let mut hits = vec![];
+        let mut directories = VecDeque::from([(root, 0)]);
+        let mut done = HashSet::new();
+
+        while let Some((directory, depth)) = directories.pop_front() {
+            if !done.insert(directory.to_string()) || done.len() > MAX_DIRECTORIES {
+                continue;
+            }
+            let found = self.directory(&directory).await;
+            if found.len() > MAX_HITS {
+                eprintln!("{} answers {} paths, looks like a wildcard, skipped", directory, found.len());
+                continue;
+            }
+            for (hit, is_directory) in found {
+                if is_directory && depth + 1 < self.options.max_depth {
+                    if let Ok(url) = Url::parse(&hit.url) {
+                        directories.push_back((url, depth + 1));
+                    }
+                }
+                hits.push(hit);
+            }
+        }
+        hits
+    }
+
+    fn candidates(&self, directory: &Url) -> Vec<Url> {
+        let mut paths = vec![];
+        for word in self.words {
+            paths.push(word.clone());
+            if !word.ends_with('/') && !word.contains('.') {
+                paths.extend(self.options.extensions.iter().map(|ext| format!("{}.{}", word, ext.trim_start_matches('.'))));
+            }
+        }
+        paths
+            .iter()
+            .filter_map(|path| directory.join(path).ok())
+            .filter(|url| !is_logout(url.as_str()))
+            .collect()
+    }
+
+    // Hits in one directory, each with whether it is a directory to descend into
+    async fn directory(&self, directory: &Url) -> Vec<(Hit, bool)> {
+        stream::iter(self.candidates(directory))
+            .map(|url| async move {
+                let response = client().get(url.as_str()).await.ok()?;
+                if !matches!(response.status, 200..=399 | 401 | 403) || !not_found().exists(&url, &response).await {
+                    return None;
+                }
+                // /word redirected to /word/, or asked for with the slash to begin with
+                let is_directory = url.path().ends_with('/')
+                    || Url::parse(&response.url).is_ok_and(|landed| landed.path() == format!("{}/", url.path()));
+                let hit = Hit {
+                    url: if is_directory && !url.path().ends_with('/') { format!("{}/", url) } else { url.to_string() },
+                    status: response.status,
+                    length: response.body.len(),
+                    title: title(&response.body),
+                };
+                Some((hit, is_directory))
+            })
+            .buffer_unordered(limiter().profile.max_in_flight)
+            .filter_map(|hit| async { hit })
+            .collect()
+            .await
+    }
+}
+
+// One finding per hit, the paths INTERESTING knows about with its title and severity. Behind 401 or 403
+// they are only INFO, that the path exists is all we know
+pub fn findings(hits: &[Hit]) -> Vec<Finding> {
+    let patterns: Vec<(Regex, &str, SEVERITY, u32)> = INTERESTING
+        .iter()
+        .map(|(pattern, title, severity, cwe)| (Regex::new(pattern).unwrap(), *title, *severity, *cwe))
+        .collect();
+    hits.iter()
+        .map(|hit| {
+            let path = Url::parse(&hit.url).map(|url| url.path().trim_end_matches('/').to_string()).unwrap_or_default();
+            let denied = matches!(hit.status, 401 | 403);
+            let mut detail = format!("HTTP {}, {} bytes", hit.status, hit.length);
+            if let Some(title) = &hit.title {
+                detail.push_str(&format!(", title \"{}\"", title));
+            }
+            let (title, severity, cwe) = match patterns.iter().find(|(re, ..)| re.is_match(&path)) {
+                Some((_, title, _, cwe)) if denied => (format!("{} (access denied)", title), SEVERITY::INFO, Some(*cwe)),
+                Some((_, title, severity, cwe)) => (title.to_string(), *severity, Some(*cwe)),
+                None => ("Content discovered".to_string(), SEVERITY::INFO, None),
+            };
+            Finding {
+                url: hit.url.clone(),
+                method: "GET".to_string(),
+                detail,
+                cwe,
+                ..Finding::new("discovery", &title, severity, CONFIDENCE::FIRM)
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hit(url: &str, status: u16, title: Option<&str>) -> Hit {
+        Hit { url: url.to_string(), status, length: 120, title: title.map(String::from) }
+    }
+
+    #[test]
+    fn interesting_paths_get_their_title_and_severity() {
+        let found = findings(&[
+            hit("http://example.com/.git/HEAD", 200, None),
+            hit("http://example.com/backup.sql", 200, None),
+            hit("http://example.com/www.zip", 200, None),
+            hit("http://example.com/admin/", 200, Some("Admin")),
+            hit("http://example.com/swagger.json", 200, None),
+        ]);
+        let summary: Vec<(&str, SEVERITY, Option<u32>)> =
+            found.iter().map(|finding| (finding.title.as_str(), finding.severity, finding.cwe)).collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("Exposed version control metadata", SEVERITY::HIGH, Some(527)),
+                ("Exposed backup or database dump", SEVERITY::HIGH, Some(530)),
+                ("Exposed source archive", SEVERITY::HIGH, Some(530)),
+                ("Exposed admin panel", SEVERITY::MEDIUM, Some(284)),
+                ("Exposed API description", SEVERITY::LOW, Some(200)),
+            ]
+        );
+        assert_eq!(found[3].detail, "HTTP 200, 120 bytes, title \"Admin\"");
+        assert_eq!(found[3].method, "GET");
+        assert_eq!(found[3].url, "http://example.com/admin/");
+    }
+
+    #[test]
+    fn denied_paths_are_info() {
+        let found = findings(&[hit("http://example.com/.env", 403, None), hit("http://example.com/server-status", 401, None)]);
+        assert_eq!(found[0].title, "Exposed credentials file (access denied)");
+        assert_eq!(found[0].severity, SEVERITY::INFO);
+        assert_eq!(found[0].cwe, Some(538));
+        assert_eq!(found[1].title, "Exposed server diagnostics (access denied)");
+        assert_eq!(found[1].detail, "HTTP 401, 120 bytes");
+    }
+
+    #[test]
+    fn other_paths_are_content_discovered() {
+        let found = findings(&[hit("http://example.com/about-us", 200, None)]);
+        assert_eq!(found[0].title, "Content discovered");
+        assert_eq!(found[0].severity, SEVERITY::INFO);
+        assert_eq!(found[0].cwe, None);
+    }
+
+    #[test]
+    fn title_is_collapsed_and_optional() {
+        assert_eq!(title("<html><head><title>\n  Index of   /backup\n</title></head></html>").as_deref(), Some("Index of /backup"));
+        assert_eq!(title("<html><head><title> </title></head></html>"), None);
+        assert_eq!(title("plain text"), None);
+    }
+}
@@ -0,0 +1 @@
+pub mod discovery;
\ No newline at end of file
@@ -1,24 +1,35 @@
-use std::sync::mpsc;
-use std::thread;
 use std::sync::Arc;
-use sender::crawler::CrawlLink;
-use sender::suraw::smuggle;
-use futures::future::join_all;
 use crate::sqli::sqli::sql_scanner;
 use crate::xss::xss::XSSCAN;
-use crate::sender::crawler;
-use crate::sender::suraw;
+use crate::smuggling::smuggling::SMUGGLER;
+use crate::sender::crawler::{Crawler, Surface};
 use std::time::Duration;
 use crate::method_parser::method_parser::get_methods;
 use url::Url;
-use crate::suraw::jiber;
-use tokio::sync::Semaphore;
+use crate::sender::client::{self, client};
+use crate::sender::session::{self, is_logout};
+use crate::sender::scope::{self, Scope, ScopeOptions};
+use crate::sender::seeds::{Seed, SOURCE};
+use crate::discovery::discovery::{DiscoveryOptions, DIRBUSTER};
+use crate::cli::cli::{parse_args, COMMAND, USAGE};
+use crate::portscanner::{tcpscan, udpscan};
+use crate::method_parser::method_parser::Method;
+use crate::report::report::{Finding, ScanReport, SEVERITY, CONFIDENCE};
+use crate::report::export::write_report;
+use crate::sender::limiter;
 
 
 mod sqli;
 mod xss;
 mod sender;
 mod method_parser;
+mod payload_builder;
+mod portscanner;
+mod cli;
+mod report;
+mod oob;
+mod smuggling;
+mod discovery;
 
 #[derive(Debug,Clone)]
 
@@ -28,43 +39,73 @@ pub enum CUSTOMER {
     IP(String),
 }
 
+impl CUSTOMER {
+    // Base url every module builds its requests from, without trailing slash
+    pub fn base_url(&self) -> String {
+        match self {
+            CUSTOMER::URL(url) => url.trim_end_matches('/').to_string(),
+            CUSTOMER::DOMAIN(domain) => format!("https://{}", domain),
+            CUSTOMER::IP(ip) => format!("http://{}", ip),
+        }
+    }
+}
+
 #[derive(Clone)]
 struct SCANER {
     id: i32,
     options: OPTIONS,
     customer: CUSTOMER,
     speed: RATE,
+    extract: Option<usize>,
+    // crawled once in main and shared by every scan
+    surface: Arc<Surface>,
 }
 
 impl SCANER {
-    fn new(id: i32, options: OPTIONS, customer: CUSTOMER, speed: RATE) -> Self {
+    fn new(
+        id: i32,
+        options: OPTIONS,
+        customer: CUSTOMER,
+        speed: RATE,
+        extract: Option<usize>,
+        surface: Arc<Surface>,
+    ) -> Self {
         SCANER {
             id,
             options,
             customer,
             speed,
+            extract,
+            surface,
         }
     }
 
-    async fn start_scan(self: Arc<Self> ) {
+    async fn start_scan(self: Arc<Self> ) -> ScanReport {
         let mut tasks = vec![];
+        let mut report = ScanReport::new(self.id, &self.customer.base_url());
+        let surface = Arc::clone(&self.surface);
 
         match &self.options {
             OPTIONS::FULLSCAN(rate) => {
                 let modules = vec![
                     MODULES::HOSTINGER,
+                    MODULES::SQLI(SQL::ERROR),
                     MODULES::SQLI(SQL::UNION),
                     MODULES::SQLI(SQL::BLIND),
+                    MODULES::SQLI(SQL::XML),
                     MODULES::XSS,
+                    MODULES::SMUGGLING,
+                    MODULES::OOB,
                 ];
                 let rate_c = rate.clone();
                 for module in modules.iter() {
                     let rate = rate_c.clone(); 
                     let self_clone = Arc::clone(&self);
+                    let surface = Arc::clone(&surface);
                     let task = tokio::spawn({
                         let module = module.clone();
                         async move {
-                            self_clone.scan_module(&module, &rate).await;
+                            self_clone.scan_module(&module, &rate, &surface).await
                         }
                     });
 
@@ -75,9 +116,10 @@ impl SCANER {
                 let module = module.clone();  // Clone the module if needed (based on your enum types)
                 let rate = rate.clone();
                 let self_clone = Arc::clone(&self); 
+                let surface = Arc::clone(&surface);
                 let task = tokio::spawn({
                     async move {
-                        self_clone.scan_module(&module, &rate).await;
+                        self_clone.scan_module(&module, &rate, &surface).await
                     }
                 });
                 tasks.push(task);
@@ -85,27 +127,44 @@ impl SCANER {
         }
 
         for task in tasks {
-            task.await.unwrap();
+            match task.await {
+                Ok(findings) => report.extend(findings),
+                Err(err) => eprintln!("Module task failed: {}", err),
+            }
         }
+        report.finish();
+        report
     }
 
-    async fn scan_module(&self, module: &MODULES, rate: &RATE) {
+    async fn scan_module(&self, module: &MODULES, rate: &RATE, surface: &Surface) -> Vec<Finding> {
+        // --header lines and auth from the shared client in the raw form suraw expects, cookies get added per request
+        let headers = client().raw_headers();
         match module {
             MODULES::XSS => {
-                XSSCAN { target: &self.customer }.run().await;
+                XSSCAN { target: &self.customer, surface, headers }.run().await
             }
             MODULES::SQLI(sql) => match sql {
                 SQL::BLIND => {
-                    sql_scanner { technique: SQL::BLIND, depth: rate }.run().await;
+                    sql_scanner { technique: SQL::BLIND, depth: rate, target: &self.customer, surface, headers, extract: self.extract }.run().await
                 }
                 SQL::UNION => {
-                    sql_scanner { technique: SQL::UNION, depth: rate }.run().await;
+                    sql_scanner { technique: SQL::UNION, depth: rate, target: &self.customer, surface, headers, extract: self.extract }.run().await
                 }
                 SQL::XML => {
-                    sql_scanner { technique: SQL::XML, depth: rate}.run().await;
+                    sql_scanner { technique: SQL::XML, depth: rate, target: &self.customer, surface, headers, extract: self.extract }.run().await
+                }
+                SQL::ERROR => {
+                    sql_scanner { technique: SQL::ERROR, depth: rate, target: &self.customer, surface, headers, extract: self.extract }.run().await
                 }
             },
-            _=> {}
+            MODULES::SMUGGLING => {
+                SMUGGLER { target: &self.customer, surface, headers }.run().await
+            }
+            MODULES::OOB => {
+                oob::probe::run(&self.customer.base_url(), surface, &headers).await;
+                vec![]
+            }
+            _=> vec![]
         }
     }
 }
@@ -122,6 +181,7 @@ enum MODULES {
     XSS,
     SQLI(SQL),
     SMUGGLING,
+    OOB,
     HOSTINGER,
     WORDPRESS,
     OSI,
@@ -133,6 +193,7 @@ enum SQL {
     BLIND,
     UNION,
     XML,
+    ERROR,
 }
 
 #[derive(Debug,Clone)]
@@ -141,96 +202,206 @@ enum RATE {
     FAST,
     MODERATE,
     SLOW,
+    // requests per second per host, max in-flight requests
+    CUSTOM(u32, usize),
+}
+
+async fn jalap(url: String,endpoint: &str, crawler: &Crawler) -> Vec<Finding> {
+    let surface = discover(url.clone(), endpoint, crawler).await;
+
+    let seeds = surface.seeds.into_iter().map(|(page, source)| Finding {
+        url: page,
+        method: "GET".to_string(),
+        detail: format!("Listed in {}", source),
+        ..Finding::new("crawler", "Page discovered outside the link graph", SEVERITY::INFO, CONFIDENCE::CONFIRMED)
+    });
+    surface
+        .methods
+        .into_iter()
+        .map(|method| Finding {
+            url: if method.url.starts_with("http") {
+                method.url.clone()
+            } else {
+                format!("{}/{}", url, method.url.trim_start_matches('/'))
+            },
+            method: method.name.clone(),
+            parameter: method.parameters.join("&"),
+            ..Finding::new("crawler", "Form endpoint discovered", SEVERITY::INFO, CONFIDENCE::CONFIRMED)
+        })
+        .chain(seeds)
+        .collect()
 }
 
-async fn jalap(url: String,endpoint: &str, cookie: Option<String>) {
-    
-    let res = jiber(&format!("{}/{}", url,endpoint)).await.unwrap();
-    let crawldar = crawler::parse(&res.0);
-
-    let semaphore = Arc::new(Semaphore::new(100)); 
-    let mut tasks = vec![];
-    let mut another_tasks = vec![];
-    for data in crawldar.inner.into_iter() {
-        let url_c = url.clone();
-        let cookie_c = cookie.clone();
-        let permit = semaphore.clone().acquire_owned().await.unwrap();
-        let task = tokio::spawn(async move {
-            let res = jiber(&format!("{}/{}", url_c,data)).await.unwrap();
-            drop(permit);
-            println!("{:?}",res);
-            res
-        });
-        tasks.push(task);
+async fn discover(url: String,endpoint: &str, crawler: &Crawler) -> Surface {
+    let root = format!("{}/{}", url, endpoint.trim_start_matches('/'));
+    let pages = crawler.crawl(&[root]).await;
+
+    let mut urls = vec![];
+    let mut methods: Vec<Method> = vec![];
+    let mut seeds = vec![];
+    let mut bodies = vec![];
+    for page in pages {
+        if (page.is_html() || page.is_script()) && page.status < 400 {
+            bodies.push((page.url.clone(), page.body.clone()));
+        }
+        if matches!(page.source, SOURCE::ROBOTS | SOURCE::SITEMAP(_) | SOURCE::SCRIPT) && page.status < 400 {
+            seeds.push((page.url.clone(), page.source.clone()));
+        }
+        let page_url = Url::parse(&page.url).ok();
+        // Script calls come with absolute urls already, joining leaves them as they are
+        for mut method in get_methods(page.body).into_iter().chain(page.scripted.methods) {
+            // Form actions resolve against the page they were found on, an empty action is the page itself
+            if method.url != "Found in JavaScript" {
+                if let Some(action) = page_url.as_ref().and_then(|page_url| page_url.join(&method.url).ok()) {
+                    method.url = action.to_string();
+                }
+            }
+            // Submitting a logout form would end the session the scan runs under
+            if is_logout(&method.url) {
+                continue;
+            }
+            // Same form in a shared header or footer shows up on every page
+            if !methods.iter().any(|m| m.name == method.name && m.url == method.url && m.parameters == method.parameters) {
+                methods.push(method);
+            }
+        }
+        urls.push(page.url);
     }
+    urls.sort();
+    urls.dedup();
+    Surface { urls, methods, seeds, pages: bodies }
+}
 
-    let responses: Vec<_> = join_all(tasks).await; // Wait for all tasks concurrently
-    println!("SHIT {:?}",responses);
-    let mut overall_crawls = vec![];
-    let mut crawl_bays = vec![];
-    for resp in responses {
-        let resp = resp.unwrap(); // Handle any potential errors here
-        let copy_res = &resp.0.to_string();
-        let methods = get_methods(resp.0);
-        println!("{:?}",methods);
-        overall_crawls.push(methods);
-        let cr = crawler::parse(copy_res);
-        crawl_bays.push(cr)
+// Target host is in scope unless --include-host says otherwise
+fn install_scope(command: &COMMAND, options: &ScopeOptions) {
+    let host = match command {
+        COMMAND::CRAWL { customer, .. } | COMMAND::SCAN { customer, .. } => Url::parse(&customer.base_url())
+            .ok()
+            .and_then(|url| url.host_str().map(String::from))
+            .unwrap_or_default(),
+        COMMAND::TCP { target, .. } => target.to_string(),
+        COMMAND::UDP { target, .. } => target.to_string(),
+    };
+    match Scope::new(&host, options) {
+        Ok(scope) => scope::install(scope),
+        Err(err) => {
+            eprintln!("Invalid scope: {}", err);
+            std::process::exit(2);
+        }
     }
-     
-    let combined = CrawlLink::combine(crawl_bays);
-    for data in combined.inner.into_iter() {
-        let url_c = url.clone();
-        let permit = semaphore.clone().acquire_owned().await.unwrap();
-        let cookie_c = cookie.clone();
-        let task = tokio::spawn(async move {
-            let res = jiber(&format!("{}/{}", url_c,data)).await.unwrap();
-            drop(permit);
-            println!("{:?}",res);
-            res
-        });
-        another_tasks.push(task);
+}
+
+// Forced browsing ahead of the crawl, what it finds goes into the report and gets crawled
+async fn bruteforce(customer: &CUSTOMER, options: &DiscoveryOptions, crawler: &mut Crawler) -> Vec<Finding> {
+    let words = match discovery::discovery::load_words(options).await {
+        Ok(words) => words,
+        Err(err) => {
+            eprintln!("Invalid discovery options: {}", err);
+            std::process::exit(2);
+        }
+    };
+    let hits = DIRBUSTER { base: &customer.base_url(), words: &words, options }.run().await;
+    crawler.seeds.extend(
+        hits.iter()
+            .filter(|hit| hit.status < 400)
+            .filter_map(|hit| Url::parse(&hit.url).ok())
+            .map(|url| Seed { url, source: SOURCE::WORDLIST }),
+    );
+    discovery::discovery::findings(&hits)
+}
+
+async fn install_client(customer: &CUSTOMER, options: client::ClientOptions) {
+    let login = options.login.clone();
+    if let Err(err) = client::install(&customer.base_url(), options) {
+        eprintln!("Invalid HTTP options: {}", err);
+        std::process::exit(2);
+    }
+    if let Some(login) = login {
+        if let Err(err) = session::install(&customer.base_url(), login).await {
+            eprintln!("Login failed: {}", err);
+            std::process::exit(1);
+        }
     }
-    let responses: Vec<_> = join_all(another_tasks).await;
-    for request in responses {
-          let resp = request.unwrap().0;
-          let method = get_methods(resp);
-          println!(
-            "Parsed method is {:?}",method
-          );
-          overall_crawls.push(method);;
-
-    } 
-    let suzgilenmic_methodlar: Vec<_> = overall_crawls.iter().flatten().collect();
-    println!("{:?}",suzgilenmic_methodlar);
-
-    //println!("ALL CRAWLED DATA {:?}", overall_crawls);
-    //println!("CRAWL {:?}",crawl_bays);
 }
 
-#[tokio::main(flavor = "multi_thread", worker_threads = 100)]
+#[tokio::main(flavor = "multi_thread")]
 async fn main() {
-    let (sender, receiver) = mpsc::channel();
+    let (command, globals) = match parse_args(std::env::args().collect()) {
+        Ok(parsed) => parsed,
+        Err(err) => {
+            eprintln!("{}\n\n{}", err, USAGE);
+            std::process::exit(2);
+        }
+    };
+    limiter::install(&globals.rate);
+    install_scope(&command, &globals.scope);
 
-    thread::spawn(move || {
-        sender.send(5).unwrap();
-    });
-    
-    let url = "https://sozdaniesajta.ru".to_string();
-    println!("Received: {}", receiver.recv().unwrap());
-    //get all links 
-    //let res = smuggle(url.clone(),"/htmli_post.php","GET",Some("Cookie: security_level=0; PHPSESSID=vnk0b7acs3q4mku80jk1d3bq36".to_string()),Some("".to_string())).await.unwrap_or(("".to_string(),"".to_string()));
-    //let crawldar = crawler::parse(&res.1);
-    let all_shit = jalap(url.to_string(),"/",Some("Cookie: security_level=0; PHPSESSID=d7eplpene3o78evvqgtnbf21p0".to_string())).await;
-    println!("QOTAQ {:?}",all_shit);
-    //println!("{:?}",suraw::jiber(&url).await);
-    // Example usage: perform a selective scan of SQLI Blind at BLAZE speed
-    let scan = SCANER::new(
-        1,
-        OPTIONS::SELECTIVE(MODULES::SQLI(SQL::BLIND), RATE::BLAZE),  // Correct usage
-        CUSTOMER::URL("https://middlecomm.kz".to_string()),
-        RATE::BLAZE,
-    );
+    let report = match command {
+        COMMAND::CRAWL { customer, client: http, mut crawler, discovery } => {
+            install_client(&customer, http).await;
+            let mut report = ScanReport::new(0, &customer.base_url());
+            if let Some(discovery) = &discovery {
+                report.extend(bruteforce(&customer, discovery, &mut crawler).await);
+            }
+            report.extend(jalap(customer.base_url(), "/", &crawler).await);
+            report.finish();
+            report
+        }
+        COMMAND::SCAN { customer, options, client: http, mut crawler, discovery, extract, callback } => {
+            install_client(&customer, http).await;
+            let mut report = ScanReport::new(1, &customer.base_url());
+            // Once ahead of every scan, they all crawl what it found
+            if let Some(discovery) = &discovery {
+                report.extend(bruteforce(&customer, discovery, &mut crawler).await);
+            }
+            if let Some(callback) = &callback {
+                if let Err(err) = oob::oob::install(callback.bind, callback.public.clone()).await {
+                    eprintln!("Failed to start callback server on {}: {}", callback.bind, err);
+                    std::process::exit(1);
+                }
+            }
+            let surface = Arc::new(discover(customer.base_url(), "/", &crawler).await);
+            for (id, option) in options.into_iter().enumerate() {
+                let scan = SCANER::new(
+                    id as i32 + 1,
+                    option,
+                    customer.clone(),
+                    globals.rate.clone(),
+                    extract,
+                    Arc::clone(&surface),
+                );
+                report.merge(Arc::new(scan).start_scan().await);
+            }
+            // Blind payloads fire late, give them a moment before collecting callbacks
+            if let (Some(callback), Some(server)) = (&callback, oob::oob::callback()) {
+                tokio::time::sleep(Duration::from_secs(callback.wait)).await;
+                report.extend(server.findings());
+            }
+            report.finish();
+            report
+        }
+        COMMAND::TCP { target, full, concurrency, timeout } => {
+            let mut report = ScanReport::new(0, &target.to_string());
+            report.extend(tcpscan::scan(target, full, concurrency, timeout).await);
+            report.finish();
+            report
+        }
+        COMMAND::UDP { target, concurrency } => {
+            let mut report = ScanReport::new(0, &target.to_string());
+            report.extend(udpscan::scan_ports(target, concurrency).await);
+            report.finish();
+            report
+        }
+    };
 
-    Arc::new(scan).start_scan();
+    println!("{}", report.summary());
+    if let Some(output) = globals.output {
+        match write_report(&report, &output.path, output.format) {
+            Ok(()) => println!("Report written to {} ({:?})", output.path, output.format),
+            Err(err) => {
+                eprintln!("Failed to write report to {}: {}", output.path, err);
+                std::process::exit(1);
+            }
+        }
+    }
 }
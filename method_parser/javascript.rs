@@ -0,0 +1,528 @@
+use std::sync::OnceLock;
+
+use regex::Regex;
+use scraper::{Html, Selector};
+use serde_json::{Map, Value};
+
+use crate::method_parser::method_parser::Method;
+
+// Paths a string literal can hold that are never an endpoint
+const STATIC: &str = r"(?i)\.(png|jpe?g|gif|svg|ico|webp|bmp|css|woff2?|ttf|eot|otf|mp[34]|webm|avi|mov|pdf|zip|gz|map)(\?|#|$)";
+
+// Longest string literal taken for a url, minified bundles are full of long strings that merely start with a slash
+const MAX_LITERAL: usize = 200;
+
+// How far past a call's opening parenthesis its arguments are looked for
+const MAX_CALL: usize = 4096;
+
+// What the scripts of a page talk to, urls as written in the script
+#[derive(Debug, Clone, Default)]
+pub struct Endpoints {
+    // fetch, axios, jQuery and XMLHttpRequest calls with their method and body shape
+    pub methods: Vec<Method>,
+    // Every url-looking string literal, route placeholders such as :id and {id} filled in
+    pub urls: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ENCODING {
+    JSON,
+    FORM,
+}
+
+// Text between the bracket at open and its partner, string literals and nested brackets skipped
+fn enclosed(src: &str, open: usize) -> Option<&str> {
+    let bytes = src.as_bytes();
+    let mut depth = 0;
+    let mut quote: Option<u8> = None;
+    let mut i = open;
+    while i < bytes.len().min(open + MAX_CALL) {
+        let b = bytes[i];
+        match quote {
+            Some(_) if b == b'\\' => i += 1,
+            Some(q) if b == q => quote = None,
+            Some(_) => {}
+            None => match b {
+                b'"' | b'\'' | b'`' => quote = Some(b),
+                b'(' | b'[' | b'{' => depth += 1,
+                b')' | b']' | b'}' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return src.get(open + 1..i);
+                    }
+                }
+                _ => {}
+            },
+        }
+        i += 1;
+    }
+    None
+}
+
+// src split on the commas that aren't inside a string or brackets
+fn split_top(src: &str, separator: u8) -> Vec<&str> {
+    let bytes = src.as_bytes();
+    let mut parts = vec![];
+    let mut depth = 0i32;
+    let mut quote: Option<u8> = None;
+    let mut start = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        let b = bytes[i];
+        match quote {
+            Some(_) if b == b'\\' => i += 1,
+            Some(q) if b == q => quote = None,
+            Some(_) => {}
+            None => match b {
+                b'"' | b'\'' | b'`' => quote = Some(b),
+                b'(' | b'[' | b'{' => depth += 1,
+                b')' | b']' | b'}' => depth -= 1,
+                _ if b == separator && depth == 0 => {
+                    parts.push(src[start..i].trim());
+                    start = i + 1;
+                }
+                _ => {}
+            },
+        }
+        i += 1;
+    }
+    parts.push(src[start..].trim());
+    parts.retain(|part| !part.is_empty());
+    parts
+}
+
+// Arguments of the call whose opening parenthesis is at open
+fn arguments(src: &str, open: usize) -> Vec<&str> {
+    enclosed(src, open).map(|inner| split_top(inner, b',')).unwrap_or_default()
+}
+
+// ${...} inside a template literal
+fn template() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| Regex::new(r"\$\{[^}]*\}").unwrap())
+}
+
+// Value of a string literal, template ${...} parts and concatenated variables become 1. None when expr
+// doesn't start with a literal
+fn literal(expr: &str) -> Option<String> {
+    let template = template();
+    let parts = split_top(expr, b'+');
+    let mut value = String::new();
+    for (index, part) in parts.iter().enumerate() {
+        let quoted = part.len() >= 2
+            && matches!(part.as_bytes()[0], b'"' | b'\'' | b'`')
+            && part.as_bytes()[part.len() - 1] == part.as_bytes()[0];
+        match quoted {
+            true => value.push_str(&template.replace_all(&part[1..part.len() - 1], "1")),
+            false if index == 0 => return None,
+            false => value.push('1'),
+        }
+    }
+    Some(value)
+}
+
+// Something to send as a parameter value: the literal itself, else a placeholder
+fn scalar(expr: &str) -> String {
+    let expr = expr.trim();
+    if let Some(value) = literal(expr) {
+        return value;
+    }
+    if expr.parse::<f64>().is_ok() || expr == "true" || expr == "false" {
+        return expr.to_string();
+    }
+    "1".to_string()
+}
+
+// Keys and value expressions of an object literal, shorthand { user } gives ("user", "user")
+fn entries(expr: &str) -> Option<Vec<(String, &str)>> {
+    let expr = expr.trim();
+    if !expr.starts_with('{') {
+        return None;
+    }
+    let key = Regex::new(r#"^(?:"([^"]*)"|'([^']*)'|([A-Za-z_$][\w$]*))\s*(:)?"#).unwrap();
+    let mut found = vec![];
+    for entry in split_top(enclosed(expr, 0)?, b',') {
+        let Some(caps) = key.captures(entry) else { continue };
+        let name = caps.get(1).or(caps.get(2)).or(caps.get(3)).map_or("", |m| m.as_str()).to_string();
+        let rest = &entry[caps.get(0).map_or(0, |m| m.end())..];
+        match caps.get(4) {
+            Some(_) => found.push((name, rest.trim())),
+            // Shorthand property, methods and spreads are left out
+            None if rest.trim().is_empty() => found.push((name, entry)),
+            None => {}
+        }
+    }
+    Some(found)
+}
+
+fn option<'a>(entries: &[(String, &'a str)], names: &[&str]) -> Option<&'a str> {
+    entries.iter().find(|(key, _)| names.contains(&key.as_str())).map(|(_, value)| *value)
+}
+
+// Parameters the way the rest of the scanner keeps them: name=value pairs for forms, one JSON object for JSON
+fn params(fields: Vec<(String, String)>, encoding: ENCODING) -> Vec<String> {
+    match encoding {
+        ENCODING::FORM => fields.into_iter().map(|(name, value)| format!("{}={}", name, value)).collect(),
+        ENCODING::JSON if fields.is_empty() => vec![],
+        ENCODING::JSON => {
+            let object: Map<String, Value> = fields.into_iter().map(|(name, value)| (name, Value::String(value))).collect();
+            vec![Value::Object(object).to_string()]
+        }
+    }
+}
+
+fn fields(entries: &[(String, &str)]) -> Vec<(String, String)> {
+    entries.iter().map(|(name, value)| (name.clone(), scalar(value))).collect()
+}
+
+// Shape of a request body expression, encoding is what an object literal means to the library at hand
+fn body(expr: &str, encoding: ENCODING) -> Vec<String> {
+    let expr = expr.trim();
+    for (wrapper, wrapped) in [("JSON.stringify(", ENCODING::JSON), ("new URLSearchParams(", ENCODING::FORM)] {
+        if expr.starts_with(wrapper) {
+            return arguments(expr, wrapper.len() - 1)
+                .first()
+                .and_then(|object| entries(object))
+                .map(|found| params(fields(&found), wrapped))
+                .unwrap_or_default();
+        }
+    }
+    if let Some(found) = entries(expr) {
+        return params(fields(&found), encoding);
+    }
+    match literal(expr) {
+        Some(text) if text.trim_start().starts_with('{') => match serde_json::from_str::<Value>(&text) {
+            Ok(Value::Object(_)) => vec![text],
+            _ => vec![],
+        },
+        Some(text) if text.contains('=') && !text.trim_start().starts_with('<') => {
+            text.split('&').filter(|pair| !pair.is_empty()).map(String::from).collect()
+        }
+        _ => vec![],
+    }
+}
+
+fn method(name: &str, url: String, parameters: Vec<String>) -> Method {
+    let name = name.to_uppercase();
+    // GET parameters travel in the url, the modules expect them in parameters
+    if name == "GET" {
+        if let Some((path, query)) = url.split_once('?') {
+            let mut parameters = parameters;
+            parameters.extend(query.split('&').filter(|pair| !pair.is_empty()).map(String::from));
+            return Method { name, parameters, url: path.to_string() };
+        }
+    }
+    Method { name, parameters, url }
+}
+
+// fetch(url, { method, body })
+fn fetch_calls(script: &str, found: &mut Vec<Method>) {
+    let call = Regex::new(r"\bfetch\s*\(").unwrap();
+    for m in call.find_iter(script) {
+        let args = arguments(script, m.end() - 1);
+        let Some(url) = args.first().and_then(|arg| literal(arg)) else { continue };
+        let options = args.get(1).and_then(|arg| entries(arg)).unwrap_or_default();
+        let verb = option(&options, &["method"]).and_then(literal).unwrap_or_else(|| "GET".to_string());
+        let parameters = option(&options, &["body"]).map(|expr| body(expr, ENCODING::JSON)).unwrap_or_default();
+        found.push(method(&verb, url, parameters));
+    }
+}
+
+// axios(config), axios.request(config), $.ajax(config) and $.ajax(url, config)
+fn config_calls(script: &str, found: &mut Vec<Method>) {
+    let call = Regex::new(r"(\baxios(?:\s*\.\s*request)?|(?:\$|\bjQuery)\s*\.\s*ajax)\s*\(").unwrap();
+    for caps in call.captures_iter(script) {
+        let jquery = caps[1].contains("ajax");
+        let open = caps.get(0).map_or(0, |m| m.end() - 1);
+        let args = arguments(script, open);
+        let (url, config) = match args.as_slice() {
+            [first, rest @ ..] if literal(first).is_some() => (literal(first), rest.first().and_then(|arg| entries(arg))),
+            [first, ..] => (None, entries(first)),
+            [] => continue,
+        };
+        let config = config.unwrap_or_default();
+        let Some(url) = url.or_else(|| option(&config, &["url"]).and_then(literal)) else { continue };
+        let verb = option(&config, &["method", "type"]).and_then(literal).unwrap_or_else(|| "GET".to_string());
+
+        // jQuery form-encodes an object unless told it's JSON, axios always sends JSON
+        let json = !jquery || option(&config, &["contentType"]).and_then(literal).is_some_and(|ct| ct.contains("json"));
+        let encoding = if json { ENCODING::JSON } else { ENCODING::FORM };
+        let mut parameters = vec![];
+        match option(&config, &["data"]) {
+            // Data on a jQuery GET goes into the query string
+            Some(data) if jquery && verb.eq_ignore_ascii_case("GET") => parameters.extend(body(data, ENCODING::FORM)),
+            Some(data) => parameters.extend(body(data, encoding)),
+            None => {}
+        }
+        if let Some(query) = option(&config, &["params"]).and_then(entries) {
+            if verb.eq_ignore_ascii_case("GET") {
+                parameters.extend(params(fields(&query), ENCODING::FORM));
+            }
+        }
+        found.push(method(&verb, url, parameters));
+    }
+}
+
+// client.get(url, ...), client.post(url, data) and friends: axios, Angular's HttpClient, $.get/$.post and
+// hand-rolled api wrappers. Only taken when the url is a literal that looks like one
+fn verb_calls(script: &str, found: &mut Vec<Method>) {
+    let call = Regex::new(r"([\w$]+)\s*\.\s*(get|post|put|patch|delete|head|getJSON)\s*\(").unwrap();
+    for caps in call.captures_iter(script) {
+        let open = caps.get(0).map_or(0, |m| m.end() - 1);
+        let args = arguments(script, open);
+        let Some(url) = args.first().and_then(|arg| literal(arg)) else { continue };
+        if !looks_like_url(&url) {
+            continue;
+        }
+        let jquery = &caps[1] == "$" || &caps[1] == "jQuery";
+        let verb = if &caps[2] == "getJSON" { "GET".to_string() } else { caps[2].to_uppercase() };
+        let parameters = match (verb.as_str(), args.get(1)) {
+            ("POST" | "PUT" | "PATCH", Some(data)) => body(data, if jquery { ENCODING::FORM } else { ENCODING::JSON }),
+            // $.get(url, data), or a config object with params for the others
+            (_, Some(second)) if jquery => body(second, ENCODING::FORM),
+            (_, Some(config)) => entries(config)
+                .and_then(|config| option(&config, &["params"]).and_then(entries))
+                .map(|query| params(fields(&query), ENCODING::FORM))
+                .unwrap_or_default(),
+            _ => vec![],
+        };
+        found.push(method(&verb, url, parameters));
+    }
+}
+
+// xhr.open(method, url) with the body of the .send() that follows. XML bodies are method_parser's
+fn xhr_calls(script: &str, found: &mut Vec<Method>) {
+    let open_call = Regex::new(r"\.\s*open\s*\(").unwrap();
+    let send_call = Regex::new(r"\.\s*send\s*\(").unwrap();
+    let verbs = Regex::new(r"^(?i)(get|post|put|patch|delete|head|options)$").unwrap();
+    for m in open_call.find_iter(script) {
+        let args = arguments(script, m.end() - 1);
+        let (Some(verb), Some(url)) = (args.first().and_then(|a| literal(a)), args.get(1).and_then(|a| literal(a))) else {
+            continue;
+        };
+        if !verbs.is_match(&verb) {
+            continue;
+        }
+        let rest = &script[m.end()..];
+        let mut end = rest.len().min(MAX_CALL);
+        while !rest.is_char_boundary(end) {
+            end -= 1;
+        }
+        let rest = &rest[..end];
+        let parameters = send_call
+            .find(rest)
+            .and_then(|send| arguments(rest, send.end() - 1).first().map(|data| body(data, ENCODING::FORM)))
+            .unwrap_or_default();
+        found.push(method(&verb, url, parameters));
+    }
+}
+
+static STATIC_RE: OnceLock<Regex> = OnceLock::new();
+
+// Absolute http(s) urls, and paths from the site root
+fn looks_like_url(text: &str) -> bool {
+    let path = if let Some(rest) = text.strip_prefix("http://").or_else(|| text.strip_prefix("https://")) {
+        match rest.find('/') {
+            Some(slash) => &rest[slash..],
+            None => return !rest.is_empty() && !rest.contains(char::is_whitespace),
+        }
+    } else if text.starts_with('/') && !text.starts_with("//") {
+        text
+    } else {
+        return false;
+    };
+    text.len() <= MAX_LITERAL
+        && path.chars().any(|c| c.is_ascii_alphabetic())
+        && !text.contains(|c: char| c.is_whitespace() || matches!(c, '<' | '>' | '\\' | '"' | '\'' | '^' | '|'))
+        && !STATIC_RE.get_or_init(|| Regex::new(STATIC).unwrap()).is_match(path)
+}
+
+// /users/:id and /users/{id} become /users/1
+fn fill_route(url: &str) -> String {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    let placeholder = RE.get_or_init(|| Regex::new(r"/(?::[A-Za-z_]\w*\??|\{[^}/]+\})").unwrap());
+    placeholder.replace_all(url, "/1").to_string()
+}
+
+// Requests a script makes and the urls it mentions
+pub fn extract(script: &str) -> Endpoints {
+    let mut methods = vec![];
+    fetch_calls(script, &mut methods);
+    config_calls(script, &mut methods);
+    verb_calls(script, &mut methods);
+    xhr_calls(script, &mut methods);
+    for method in methods.iter_mut() {
+        method.url = fill_route(&method.url);
+    }
+    methods.retain(|method| !method.url.is_empty());
+
+    let string = Regex::new(r#""((?:[^"\\\n]|\\.)*)"|'((?:[^'\\\n]|\\.)*)'|`((?:[^`\\]|\\.)*)`"#).unwrap();
+    let mut urls: Vec<String> = vec![];
+    for caps in string.captures_iter(script) {
+        let Some(text) = caps.get(1).or(caps.get(2)).or(caps.get(3)) else { continue };
+        let text = template().replace_all(text.as_str().trim(), "1").replace("\\/", "/");
+        if looks_like_url(&text) && !text.contains('*') {
+            let url = fill_route(&text);
+            if !urls.contains(&url) {
+                urls.push(url);
+            }
+        }
+    }
+    Endpoints { methods, urls }
+}
+
+// Bodies of the page's <script> elements without a src, JSON data blocks included since they carry routes too
+pub fn inline_scripts(html: &str) -> Vec<String> {
+    let document = Html::parse_document(html);
+    let selector = Selector::parse("script:not([src])").unwrap();
+    document
+        .select(&selector)
+        .map(|script| script.text().collect::<String>())
+        .filter(|text| !text.trim().is_empty())
+        .collect()
+}
+
+// Where the script's source map is, from its last sourceMappingURL comment
+pub fn source_map_url(script: &str) -> Option<String> {
+    let comment = Regex::new(r"(?m)^\s*//[#@]\s*sourceMappingURL\s*=\s*(\S+)\s*$").unwrap();
+    comment.captures_iter(script).last().map(|caps| caps[1].to_string())
+}
+
+// Original sources a source map carries, third-party packages left out
+pub fn map_sources(map: &str) -> Vec<String> {
+    let Ok(map) = serde_json::from_str::<Value>(map) else { return vec![] };
+    let names = map["sources"].as_array().cloned().unwrap_or_default();
+    map["sourcesContent"]
+        .as_array()
+        .map(|contents| {
+            contents
+                .iter()
+                .enumerate()
+                .filter(|(index, _)| !names.get(*index).and_then(Value::as_str).is_some_and(|name| name.contains("node_modules")))
+                .filter_map(|(_, content)| content.as_str().map(String::from))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn find<'a>(endpoints: &'a Endpoints, url: &str) -> &'a Method {
+        endpoints.methods.iter().find(|method| method.url == url).unwrap()
+    }
+
+    fn json(parameter: &str) -> Value {
+        serde_json::from_str(parameter).unwrap()
+    }
+
+    #[test]
+    fn fetch_calls_with_bodies_and_queries() {
+        let found = extract(
+            r#"
+            fetch("/api/users/" + id, { method: "POST", body: JSON.stringify({ name: "bob", age: 3 }) });
+            fetch(`/search?q=${term}&page=2`);
+            fetch(url);
+            "#,
+        );
+        assert_eq!(found.methods.len(), 2);
+
+        let post = find(&found, "/api/users/1");
+        assert_eq!(post.name, "POST");
+        assert_eq!(post.parameters.len(), 1);
+        assert_eq!(json(&post.parameters[0]), serde_json::json!({ "name": "bob", "age": "3" }));
+
+        let get = find(&found, "/search");
+        assert_eq!(get.name, "GET");
+        assert_eq!(get.parameters, vec!["q=1", "page=2"]);
+    }
+
+    #[test]
+    fn config_calls_follow_the_library_encoding() {
+        let found = extract(
+            r#"
+            axios({ url: '/api/orders/:id', method: 'put', data: { status: 'paid' } });
+            $.ajax({ url: "/login", type: "POST", data: { user: u, pass: p } });
+            $.ajax("/items", { data: { page: 2 } });
+            "#,
+        );
+        assert_eq!(found.methods.len(), 3);
+
+        let axios = find(&found, "/api/orders/1");
+        assert_eq!(axios.name, "PUT");
+        assert_eq!(json(&axios.parameters[0]), serde_json::json!({ "status": "paid" }));
+
+        let login = find(&found, "/login");
+        assert_eq!(login.name, "POST");
+        assert_eq!(login.parameters, vec!["user=1", "pass=1"]);
+
+        let items = find(&found, "/items");
+        assert_eq!(items.name, "GET");
+        assert_eq!(items.parameters, vec!["page=2"]);
+    }
+
+    #[test]
+    fn verb_calls_need_a_url_literal() {
+        let found = extract(
+            r#"
+            $.post("/comment", { text: "hi" });
+            axios.get("/api/me", { params: { fields: "all" } });
+            http.post("/api/items", { name: n });
+            cache.get("session");
+            "#,
+        );
+        assert_eq!(found.methods.len(), 3);
+        assert_eq!(find(&found, "/comment").parameters, vec!["text=hi"]);
+
+        let me = find(&found, "/api/me");
+        assert_eq!(me.name, "GET");
+        assert_eq!(me.parameters, vec!["fields=all"]);
+
+        let items = find(&found, "/api/items");
+        assert_eq!(items.name, "POST");
+        assert_eq!(json(&items.parameters[0]), serde_json::json!({ "name": "1" }));
+    }
+
+    #[test]
+    fn xhr_open_takes_the_following_send() {
+        let found = extract(
+            r#"
+            var x = new XMLHttpRequest();
+            x.open("POST", "/upload");
+            x.send("a=1&b=2");
+            window.open("https://example.com/help");
+            "#,
+        );
+        assert_eq!(found.methods.len(), 1);
+        let upload = find(&found, "/upload");
+        assert_eq!(upload.name, "POST");
+        assert_eq!(upload.parameters, vec!["a=1", "b=2"]);
+    }
+
+    #[test]
+    fn url_literals_are_filled_and_filtered() {
+        let found = extract(r#"var routes = ["/users/{id}/posts", "/logo.png", "https://cdn.example.com/app", "not a url", "//cdn", "/users/{id}/posts"];"#);
+        assert_eq!(found.urls, vec!["/users/1/posts", "https://cdn.example.com/app"]);
+        assert!(found.methods.is_empty());
+    }
+
+    #[test]
+    fn source_maps() {
+        assert_eq!(source_map_url("code();\n//# sourceMappingURL=old.map\n//# sourceMappingURL=app.js.map\n").as_deref(), Some("app.js.map"));
+        assert_eq!(source_map_url("code(); // sourceMappingURL is set later"), None);
+
+        let map = r#"{"sources":["webpack:///src/app.js","webpack:///node_modules/x/index.js"],"sourcesContent":["fetch('/a')","lib()"]}"#;
+        assert_eq!(map_sources(map), vec!["fetch('/a')"]);
+        assert!(map_sources("not json").is_empty());
+    }
+
+    #[test]
+    fn inline_scripts_skip_external_and_empty() {
+        let html = r#"<script src="x.js"></script><script>var a = 1;</script><script type="application/json">{"route":"/b"}</script><script> </script>"#;
+        let scripts = inline_scripts(html);
+        assert_eq!(scripts.len(), 2);
+        assert_eq!(scripts[0], "var a = 1;");
+        assert!(scripts[1].contains("/b"));
+    }
+}
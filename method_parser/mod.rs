@@ -1 +1,2 @@
-pub mod method_parser;
\ No newline at end of file
+pub mod method_parser;
+pub mod javascript;
\ No newline at end of file
@@ -0,0 +1,2 @@
+pub mod oob;
+pub mod probe;
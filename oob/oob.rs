@@ -0,0 +1,299 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Mutex, OnceLock};
+
+use anyhow::anyhow;
+use rand::Rng;
+use regex::Regex;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio_rustls::TlsConnector;
+
+use crate::report::report::{now, Finding, SEVERITY, CONFIDENCE};
+use crate::sqli::sqli::{send, InjectionPoint};
+
+// Largest callback request kept as evidence
+const MAX_HIT: usize = 8192;
+
+// What a token was sent with, so a callback can be traced back to the request that caused it
+#[derive(Debug, Clone)]
+pub struct Injection {
+    pub module: String,
+    pub title: String,
+    pub url: String,
+    pub method: String,
+    pub parameter: String,
+    pub payload: String,
+    pub request: String,
+    pub sent: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct Hit {
+    pub token: String,
+    pub from: SocketAddr,
+    pub received: u64,
+    pub request: String,
+}
+
+pub struct Callback {
+    // Base url payloads point at, reachable from the target
+    pub public: String,
+    injections: Mutex<HashMap<String, Injection>>,
+    hits: Mutex<Vec<Hit>>,
+}
+
+// How an out-of-band payload goes into the request
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DELIVERY {
+    // Appended to the parameter's original value
+    APPEND,
+    // Instead of the parameter's value
+    REPLACE,
+    // As the whole request body
+    BODY,
+}
+
+static CALLBACK: OnceLock<Callback> = OnceLock::new();
+
+// Bind the listener and start answering callbacks, only the first call wins. Without public the payloads
+// point at bind itself, which has to be an address the target can reach
+pub async fn install(bind: SocketAddr, public: Option<String>) -> Result<(), anyhow::Error> {
+    let public = match public {
+        Some(public) => public,
+        None if bind.ip().is_unspecified() || bind.ip().is_loopback() => {
+            return Err(anyhow!("callback bound to {} needs a public callback url", bind.ip()))
+        }
+        None => format!("http://{}", bind),
+    };
+    let listener = TcpListener::bind(bind).await?;
+    let callback = CALLBACK.get_or_init(|| Callback::new(&public));
+    tokio::spawn(serve(listener, callback));
+    Ok(())
+}
+
+// None unless --callback was given, modules skip their out-of-band payloads then
+pub fn callback() -> Option<&'static Callback> {
+    CALLBACK.get()
+}
+
+async fn serve(listener: TcpListener, callback: &'static Callback) {
+    let token_regex = Regex::new(r"krko[0-9a-f]{12}").unwrap();
+    loop {
+        let (mut stream, from) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(_) => continue,
+        };
+        let token_regex = token_regex.clone();
+        tokio::spawn(async move {
+            let mut buffer = vec![0u8; MAX_HIT];
+            let mut read = 0;
+            while read < MAX_HIT {
+                match tokio::time::timeout(std::time::Duration::from_secs(5), stream.read(&mut buffer[read..])).await {
+                    Ok(Ok(0)) | Ok(Err(_)) | Err(_) => break,
+                    Ok(Ok(n)) => read += n,
+                }
+                if buffer[..read].windows(4).any(|w| w == b"\r\n\r\n") {
+                    break;
+                }
+            }
+            let request = String::from_utf8_lossy(&buffer[..read]).to_string();
+            // Empty script body so a <script src> callback doesn't throw in the victim's browser
+            let _ = stream
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Type: application/javascript\r\nContent-Length: 0\r\nConnection: close\r\n\r\n")
+                .await;
+
+            if let Some(found) = token_regex.find(&request) {
+                callback.hits.lock().unwrap().push(Hit {
+                    token: found.as_str().to_string(),
+                    from,
+                    received: now(),
+                    request,
+                });
+            }
+        });
+    }
+}
+
+impl Callback {
+    fn new(public: &str) -> Self {
+        Callback {
+            public: public.trim_end_matches('/').to_string(),
+            injections: Mutex::new(HashMap::new()),
+            hits: Mutex::new(vec![]),
+        }
+    }
+
+    // Fresh token for injection, with the url that reports it
+    pub fn issue(&self, injection: Injection) -> (String, String) {
+        let token = format!("krko{:012x}", rand::thread_rng().gen::<u64>() & 0xffff_ffff_ffff);
+        let url = format!("{}/{}", self.public, token);
+        self.injections.lock().unwrap().insert(token.clone(), injection);
+        (token, url)
+    }
+
+    // Payload and request are only known once the token is in the payload, fill them in after sending
+    pub fn record(&self, token: &str, payload: &str, request: &str) {
+        if let Some(injection) = self.injections.lock().unwrap().get_mut(token) {
+            injection.payload = payload.to_string();
+            injection.request = request.to_string();
+            injection.sent = now();
+        }
+    }
+
+    // One finding per token that called back, the first hit is the evidence
+    pub fn findings(&self) -> Vec<Finding> {
+        let injections = self.injections.lock().unwrap();
+        let hits = self.hits.lock().unwrap();
+        let mut reported: Vec<&str> = vec![];
+        let mut findings = vec![];
+
+        for hit in hits.iter() {
+            if reported.contains(&hit.token.as_str()) {
+                continue;
+            }
+            let injection = match injections.get(&hit.token) {
+                Some(injection) => injection,
+                None => continue,
+            };
+            reported.push(&hit.token);
+            let count = hits.iter().filter(|other| other.token == hit.token).count();
+            findings.push(Finding {
+                url: injection.url.clone(),
+                method: injection.method.clone(),
+                parameter: injection.parameter.clone(),
+                payload: injection.payload.clone(),
+                detail: format!(
+                    "callback {} from {} {}s after injection, {} hit(s)",
+                    hit.token,
+                    hit.from,
+                    hit.received.saturating_sub(injection.sent),
+                    count
+                ),
+                request: injection.request.clone(),
+                response: hit.request.clone(),
+                cwe: cwe(&injection.module),
+                ..Finding::new(&injection.module, &injection.title, SEVERITY::HIGH, CONFIDENCE::CONFIRMED)
+            });
+        }
+        findings
+    }
+}
+
+fn cwe(module: &str) -> Option<u32> {
+    match module {
+        "xss" => Some(79),
+        "sqli" => Some(89),
+        "ssrf" => Some(918),
+        "xxe" => Some(611),
+        _ => None,
+    }
+}
+
+// Send the payload build makes around a fresh token's url through point. Nothing comes back here,
+// the finding shows up in Callback::findings once the target calls home
+pub async fn inject(
+    point: &InjectionPoint,
+    module: &str,
+    title: &str,
+    build: impl Fn(&str) -> String,
+    delivery: DELIVERY,
+    headers: &Option<String>,
+    tls: &TlsConnector,
+) {
+    let callback = match callback() {
+        Some(callback) => callback,
+        None => return,
+    };
+    let (token, url) = callback.issue(Injection {
+        module: module.to_string(),
+        title: title.to_string(),
+        url: point.url.clone(),
+        method: point.method.clone(),
+        parameter: point.name.clone(),
+        payload: String::new(),
+        request: String::new(),
+        sent: now(),
+    });
+    let payload = build(&url);
+    let response = match delivery {
+        DELIVERY::APPEND => send(point, &payload, headers, tls).await,
+        DELIVERY::REPLACE => send(&point.with_value(&payload), "", headers, tls).await,
+        DELIVERY::BODY => send(&point.with_body(&payload), "", headers, tls).await,
+    };
+    callback.record(&token, &payload, &response.map(|response| response.request).unwrap_or_default());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpStream;
+
+    fn injection() -> Injection {
+        Injection {
+            module: "sqli".to_string(),
+            title: "Out-of-band SQL injection".to_string(),
+            url: "http://target.example/item".to_string(),
+            method: "GET".to_string(),
+            parameter: "id".to_string(),
+            payload: String::new(),
+            request: String::new(),
+            sent: now(),
+        }
+    }
+
+    #[tokio::test]
+    async fn localhost_callback_becomes_a_finding() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let callback: &'static Callback = Box::leak(Box::new(Callback::new(&format!("http://{}/", address))));
+        tokio::spawn(serve(listener, callback));
+
+        let (token, url) = callback.issue(injection());
+        assert_eq!(url, format!("http://{}/{}", address, token));
+        callback.record(&token, "1' AND krk", "GET /item?id=1 HTTP/1.1");
+        assert!(callback.findings().is_empty());
+
+        let mut stream = TcpStream::connect(address).await.unwrap();
+        stream.write_all(format!("GET /{} HTTP/1.1\r\nHost: x\r\n\r\n", token).as_bytes()).await.unwrap();
+        let mut reply = vec![];
+        stream.read_to_end(&mut reply).await.unwrap();
+        assert!(reply.starts_with(b"HTTP/1.1 200 OK"));
+
+        // The hit is stored after the reply went out
+        for _ in 0..100 {
+            if !callback.findings().is_empty() {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        let findings = callback.findings();
+        assert_eq!(findings.len(), 1);
+        assert_eq!(findings[0].payload, "1' AND krk");
+        assert_eq!(findings[0].parameter, "id");
+        assert_eq!(findings[0].cwe, Some(89));
+        assert!(findings[0].response.contains(&token));
+    }
+
+    #[tokio::test]
+    async fn unknown_tokens_are_not_reported() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let callback: &'static Callback = Box::leak(Box::new(Callback::new(&format!("http://{}", address))));
+        tokio::spawn(serve(listener, callback));
+
+        let mut stream = TcpStream::connect(address).await.unwrap();
+        stream.write_all(b"GET /krko000000000000 HTTP/1.1\r\n\r\n").await.unwrap();
+        let mut reply = vec![];
+        stream.read_to_end(&mut reply).await.unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        assert_eq!(callback.hits.lock().unwrap().len(), 1);
+        assert!(callback.findings().is_empty());
+    }
+
+    #[tokio::test]
+    async fn unreachable_bind_needs_a_public_url() {
+        assert!(install("0.0.0.0:0".parse().unwrap(), None).await.is_err());
+        assert!(install("127.0.0.1:0".parse().unwrap(), None).await.is_err());
+    }
+}
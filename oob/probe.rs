@@ -0,0 +1,67 @@
+use futures::stream::{self, StreamExt};
+use regex::Regex;
+use tokio_rustls::TlsConnector;
+
+use crate::oob::oob::{callback, inject, DELIVERY};
+use crate::payload_builder::payload_builder::{oob_ssrf, oob_xxe};
+use crate::sender::crawler::Surface;
+use crate::sender::limiter::limiter;
+use crate::sender::suraw::create_tls_connector;
+use crate::sqli::sqli::{injection_points, xml_points, InjectionPoint};
+
+// Parameter names that usually hold something the server goes and fetches
+const URL_PARAMETERS: &str =
+    r"(?i)^(url|uri|link|href|src|source|dest|destination|redirect|return|next|callback|webhook|feed|host|site|domain|image|img|file|path|page|endpoint|proxy)(_?url)?$";
+
+// Root element of an XML body, what the XXE document has to keep so the endpoint still parses it
+fn root(xml: &str) -> String {
+    Regex::new(r"<([A-Za-z_][\w:.-]*)")
+        .unwrap()
+        .captures(xml)
+        .map(|caps| caps[1].to_string())
+        .unwrap_or_else(|| "root".to_string())
+}
+
+async fn ssrf(point: &InjectionPoint, headers: &Option<String>, tls: &TlsConnector) {
+    for index in 0..oob_ssrf("").len() {
+        let build = |url: &str| oob_ssrf(url)[index].clone();
+        inject(point, "ssrf", "Server-side request forgery (out-of-band)", build, DELIVERY::REPLACE, headers, tls).await;
+    }
+}
+
+async fn xxe(point: &InjectionPoint, headers: &Option<String>, tls: &TlsConnector) {
+    let root = root(&point.params);
+    for index in 0..oob_xxe("", "").len() {
+        let build = |url: &str| oob_xxe(url, &root)[index].clone();
+        inject(point, "xxe", "XML external entity injection (out-of-band)", build, DELIVERY::BODY, headers, tls).await;
+    }
+}
+
+// SSRF through url-like parameters and XXE through XML bodies, both only detectable out of band
+pub async fn run(base: &str, surface: &Surface, headers: &Option<String>) {
+    if callback().is_none() {
+        return;
+    }
+    let tls = create_tls_connector();
+    let url_parameter = Regex::new(URL_PARAMETERS).unwrap();
+    let concurrency = limiter().profile.max_in_flight;
+
+    let candidates: Vec<InjectionPoint> = injection_points(base, surface)
+        .into_iter()
+        .filter(|point| url_parameter.is_match(&point.name))
+        .collect();
+    stream::iter(candidates.iter())
+        .map(|point| ssrf(point, headers, &tls))
+        .buffer_unordered(concurrency)
+        .collect::<Vec<_>>()
+        .await;
+
+    // One document per endpoint, the points of one body only differ in the element they target
+    let mut documents = xml_points(base, surface);
+    documents.dedup_by(|a, b| a.url == b.url && a.method == b.method);
+    stream::iter(documents.iter())
+        .map(|point| xxe(point, headers, &tls))
+        .buffer_unordered(concurrency)
+        .collect::<Vec<_>>()
+        .await;
+}
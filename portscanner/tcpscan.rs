@@ -7,6 +7,9 @@ use tokio::net::TcpStream;
 
 use indicatif::{ProgressBar, ProgressStyle};
 use std::sync::Arc;
+use crate::report::report::{Finding, SEVERITY, CONFIDENCE};
+use crate::sender::limiter::limiter;
+use crate::sender::scope::allows_socket;
 
 pub const MOST_COMMON_PORTS_1002: &[u16] = &[
     443, 6001, 5601, 9300, 80, 23, 443, 21, 22, 25, 3389, 110, 445, 139, 143, 53, 135, 3306, 8080,
@@ -23,13 +26,18 @@ pub fn get_ports(full: bool) -> Box<dyn Iterator<Item = u16>> {
     if full {
         Box::new((1..=u16::MAX).into_iter())
     } else {
-        Box::new(MOST_COMMON_PORTS_1002.to_owned().into_iter())
+        // The list repeats a few ports, each is scanned once
+        let mut ports = MOST_COMMON_PORTS_1002.to_vec();
+        ports.sort_unstable();
+        ports.dedup();
+        Box::new(ports.into_iter())
     }
 }
 
 pub async fn scan_port(target: IpAddr, port: u16, timeout: u64) -> Option<u16> {
     let timeout = Duration::from_secs(timeout);
     let socket_address = SocketAddr::new(target.clone(), port);
+    limiter().pace(&target.to_string()).await;
 
     match tokio::time::timeout(timeout, TcpStream::connect(&socket_address)).await {
         Ok(Ok(_)) => Some(port),
@@ -46,9 +54,21 @@ pub async fn scan_port(target: IpAddr, port: u16, timeout: u64) -> Option<u16> {
   }
 */
 
-pub async fn scan(target: IpAddr, full: bool, concurrency: usize, timeout: u64) -> Vec<u16> {
+pub fn port_finding(target: IpAddr, port: u16) -> Finding {
+    Finding {
+        url: format!("tcp://{}", SocketAddr::new(target, port)),
+        method: "CONNECT".to_string(),
+        parameter: port.to_string(),
+        ..Finding::new("tcpscan", "Open TCP port", SEVERITY::INFO, CONFIDENCE::CONFIRMED)
+    }
+}
+
+pub async fn scan(target: IpAddr, full: bool, concurrency: usize, timeout: u64) -> Vec<Finding> {
     let mut open_ports = Vec::new();
-    let ports = get_ports(full).collect::<Vec<_>>();
+    let (ports, skipped): (Vec<u16>, Vec<u16>) = get_ports(full).partition(|port| allows_socket(target, *port));
+    if !skipped.is_empty() {
+        eprintln!("Out of scope, not sent: {} ports on {}", skipped.len(), target);
+    }
     let total_ports = ports.len() as u64;
 
     let pb = Arc::new(ProgressBar::new(total_ports));
@@ -85,4 +105,7 @@ pub async fn scan(target: IpAddr, full: bool, concurrency: usize, timeout: u64)
     pb.finish_with_message("Scan completed");
 
     open_ports
+        .into_iter()
+        .map(|port| port_finding(target, port))
+        .collect()
 }
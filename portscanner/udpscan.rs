@@ -1,12 +1,23 @@
 use futures::StreamExt;
-use std::net::Ipv4Addr;
+use std::net::{IpAddr, Ipv4Addr};
 use std::sync::{Arc, Mutex};
 use tokio::net::UdpSocket;
 use tokio::time::{timeout, Duration};
 
 use indicatif::{ProgressBar, ProgressStyle};
+use crate::report::report::{Finding, SEVERITY, CONFIDENCE};
+use crate::sender::limiter::limiter;
+use crate::sender::scope::allows_socket;
+
+// How long a probe waits for a reply, silence is taken for a closed or filtered port
+const UDP_REPLY_TIMEOUT: Duration = Duration::from_secs(5);
 
 pub async fn udp_scan(target: Ipv4Addr, port: u16) -> Option<u16> {
+    Some(udp_probe(target, port).await.map(|(port, _)| port).unwrap_or(0))
+}
+
+// Same as udp_scan but keeps the reply so it can be reported as evidence, None when closed
+pub async fn udp_probe(target: Ipv4Addr, port: u16) -> Option<(u16, Vec<u8>)> {
     let payload = match port {
         53 => vec![
             0x00, 0x01, 0x01, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x03, 0x77,
@@ -346,39 +357,38 @@ pub async fn udp_scan(target: Ipv4Addr, port: u16) -> Option<u16> {
 
         _ => vec![0x00],
     };
+    limiter().pace(&target.to_string()).await;
     if let Ok(socket) = UdpSocket::bind("0.0.0.0:0").await {
         if let Err(e) = socket.send_to(&payload, format!("{}:{}", target, port)).await {
-            println!("Error sending packet: {}", e);
-            return Some(0);
+            eprintln!("Error sending packet: {}", e);
+            return None;
         }
 
         let mut buffer = [0u8; 1024];
-        if let Ok(response) = timeout(Duration::from_secs(5), socket.recv_from(&mut buffer)).await {
+        if let Ok(response) = timeout(UDP_REPLY_TIMEOUT, socket.recv_from(&mut buffer)).await {
             match response {
                 Ok((response_size, _)) => {
                     let response = &buffer[..response_size];
                     if !response.is_empty() {
-                        //let capture: String = response.iter().map(|byte| *byte as char).collect();
-                        println!("{:?}", std::str::from_utf8(response));
-                        return Some(port);
+                        return Some((port, response.to_vec()));
                     } else {
-                        return Some(0);
+                        return None;
                     }
                 }
                 Err(_) => {
                     // Handle timeout error if needed
                     // For now, returning None as a closed port
-                    return Some(0);
+                    return None;
                 }
             }
         }
     }
 
-    Some(0)
+    None
 }
 
-pub async fn scan_ports(target: Ipv4Addr, concorrency: usize) -> Vec<u16> {
-    let open_ports = Arc::new(Mutex::new(Vec::<u16>::new()));
+pub async fn scan_ports(target: Ipv4Addr, concorrency: usize) -> Vec<Finding> {
+    let open_ports = Arc::new(Mutex::new(Vec::<Finding>::new()));
 
     let ports = &[
         28017, 53, 30718, 88, 67, 554, 19, 427, 177, 135, 4045, 64738, 26000, 27444, 31337, 9987,
@@ -391,6 +401,13 @@ pub async fn scan_ports(target: Ipv4Addr, concorrency: usize) -> Vec<u16> {
         27914, 27015, 27016, 27017, 27018, 27019, 27020, 27021, 27022, 27023, 27024, 27025, 27026,
         27027, 27028, 27029, 27030,
     ];
+    let mut ports = ports.to_vec();
+    ports.sort_unstable();
+    ports.dedup();
+    let (ports, skipped): (Vec<u16>, Vec<u16>) = ports.into_iter().partition(|port| allows_socket(IpAddr::V4(target), *port));
+    if !skipped.is_empty() {
+        eprintln!("Out of scope, not sent: {} ports on {}", skipped.len(), target);
+    }
     let pb = Arc::new(ProgressBar::new(ports.len() as u64));
     pb.set_style(
         ProgressStyle::default_bar()
@@ -405,12 +422,16 @@ pub async fn scan_ports(target: Ipv4Addr, concorrency: usize) -> Vec<u16> {
             let target = target;
             let open_ports = Arc::clone(&open_ports);
             tokio::spawn(async move {
-                let result = udp_scan(target, port).await;
+                let result = udp_probe(target, port).await;
                 pb.inc(1);
-                if let Some(open_port) = result {
-                    if open_port != 0 {
-                        open_ports.lock().unwrap().push(open_port);
-                    }
+                if let Some((open_port, response)) = result {
+                    open_ports.lock().unwrap().push(Finding {
+                        url: format!("udp://{}:{}", target, open_port),
+                        method: "PROBE".to_string(),
+                        parameter: open_port.to_string(),
+                        response: String::from_utf8_lossy(&response).to_string(),
+                        ..Finding::new("udpscan", "Open UDP port", SEVERITY::INFO, CONFIDENCE::CONFIRMED)
+                    });
                 }
             })
         })
@@ -419,10 +440,10 @@ pub async fn scan_ports(target: Ipv4Addr, concorrency: usize) -> Vec<u16> {
         .await;
 
     for result in tasks.drain(..) {
-        if let Ok(open_port) = result {
-            println!("{:?}", open_port);
+        if let Err(err) = result {
+            eprintln!("UDP probe task failed: {}", err);
         }
     }
-    println!("UDP SHT {:?}", open_ports);
+    pb.finish_with_message("Scan completed");
     Arc::try_unwrap(open_ports).ok().unwrap().into_inner().unwrap()
 }
@@ -0,0 +1,504 @@
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+
+use anyhow::anyhow;
+use html_escape::{encode_double_quoted_attribute, encode_text};
+use serde_json::{json, Value};
+
+use crate::report::report::{Finding, ScanReport, SEVERITY};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FORMAT {
+    JSON,
+    JSONL,
+    SARIF,
+    HTML,
+}
+
+impl FORMAT {
+    pub fn parse(format: &str) -> Result<FORMAT, anyhow::Error> {
+        match format.to_lowercase().as_str() {
+            "json" => Ok(FORMAT::JSON),
+            "jsonl" => Ok(FORMAT::JSONL),
+            "sarif" => Ok(FORMAT::SARIF),
+            "html" | "htm" => Ok(FORMAT::HTML),
+            other => Err(anyhow!("unknown report format '{}'", other)),
+        }
+    }
+
+    // Guess the format from the output file extension, JSON when there is none
+    pub fn from_path(path: &str) -> Result<FORMAT, anyhow::Error> {
+        match Path::new(path).extension().and_then(|ext| ext.to_str()) {
+            Some(ext) => FORMAT::parse(ext),
+            None => Ok(FORMAT::JSON),
+        }
+    }
+}
+
+fn extracted_json(finding: &Finding) -> Value {
+    Value::Array(
+        finding
+            .extracted
+            .iter()
+            .map(|(name, value)| json!({ "name": name, "value": value }))
+            .collect(),
+    )
+}
+
+pub fn finding_json(finding: &Finding) -> Value {
+    json!({
+        "module": finding.module,
+        "title": finding.title,
+        "severity": format!("{:?}", finding.severity),
+        "confidence": format!("{:?}", finding.confidence),
+        "url": finding.url,
+        "method": finding.method,
+        "parameter": finding.parameter,
+        "payload": finding.payload,
+        "detail": finding.detail,
+        "dbms": finding.dbms,
+        "extracted": extracted_json(finding),
+        "cwe": finding.cwe,
+        "evidence": {
+            "request": finding.request,
+            "response": finding.response,
+        },
+    })
+}
+
+pub fn report_json(report: &ScanReport) -> Value {
+    json!({
+        "id": report.id,
+        "target": report.target,
+        "started": report.started,
+        "finished": report.finished,
+        "findings": report.findings.iter().map(finding_json).collect::<Vec<_>>(),
+    })
+}
+
+// One finding per line, tagged with the scan it came from so several runs can share a file
+pub fn report_jsonl(report: &ScanReport) -> String {
+    report
+        .findings
+        .iter()
+        .map(|finding| {
+            let mut line = finding_json(finding);
+            line["scan_id"] = json!(report.id);
+            line["target"] = json!(report.target);
+            line["finished"] = json!(report.finished);
+            line.to_string()
+        })
+        .map(|line| format!("{}\n", line))
+        .collect()
+}
+
+fn sarif_level(severity: SEVERITY) -> &'static str {
+    match severity {
+        SEVERITY::CRITICAL | SEVERITY::HIGH => "error",
+        SEVERITY::MEDIUM => "warning",
+        SEVERITY::LOW | SEVERITY::INFO => "note",
+    }
+}
+
+// Numeric score GitHub code scanning uses to bucket security results
+fn security_severity(severity: SEVERITY) -> &'static str {
+    match severity {
+        SEVERITY::CRITICAL => "9.5",
+        SEVERITY::HIGH => "8.0",
+        SEVERITY::MEDIUM => "5.5",
+        SEVERITY::LOW => "3.0",
+        SEVERITY::INFO => "0.0",
+    }
+}
+
+fn rule_id(finding: &Finding) -> String {
+    match finding.cwe {
+        Some(cwe) => format!("{}/CWE-{}", finding.module, cwe),
+        None => finding.module.clone(),
+    }
+}
+
+pub fn report_sarif(report: &ScanReport) -> Value {
+    let mut rules: BTreeMap<String, Value> = BTreeMap::new();
+    for finding in &report.findings {
+        let id = rule_id(finding);
+        let mut tags = vec![json!("security"), json!(finding.module)];
+        if let Some(cwe) = finding.cwe {
+            tags.push(json!(format!("external/cwe/cwe-{}", cwe)));
+        }
+        rules.entry(id.clone()).or_insert_with(|| {
+            let mut rule = json!({
+                "id": id,
+                "name": finding.title,
+                "shortDescription": { "text": finding.title },
+                "defaultConfiguration": { "level": sarif_level(finding.severity) },
+                "properties": {
+                    "tags": tags,
+                    "security-severity": security_severity(finding.severity),
+                },
+            });
+            // SARIF wants a uri or nothing, not null
+            if let Some(cwe) = finding.cwe {
+                rule["helpUri"] = json!(format!("https://cwe.mitre.org/data/definitions/{}.html", cwe));
+            }
+            rule
+        });
+    }
+
+    let results: Vec<Value> = report
+        .findings
+        .iter()
+        .map(|finding| {
+            let text = if finding.detail.is_empty() {
+                finding.summary()
+            } else {
+                format!("{}\n{}", finding.summary(), finding.detail)
+            };
+            json!({
+                "ruleId": rule_id(finding),
+                "level": sarif_level(finding.severity),
+                "message": { "text": text },
+                "locations": [{
+                    "physicalLocation": {
+                        "artifactLocation": { "uri": finding.url },
+                    },
+                }],
+                "partialFingerprints": {
+                    "primaryLocationLineHash": format!(
+                        "{}|{}|{}|{}",
+                        rule_id(finding),
+                        finding.method,
+                        finding.url,
+                        finding.parameter
+                    ),
+                },
+                "properties": {
+                    "confidence": format!("{:?}", finding.confidence),
+                    "method": finding.method,
+                    "parameter": finding.parameter,
+                    "payload": finding.payload,
+                    "dbms": finding.dbms,
+                    "extracted": extracted_json(finding),
+                },
+            })
+        })
+        .collect();
+
+    json!({
+        "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": "KORIK_SCANNER",
+                    "informationUri": "https://github.com/Abu-Sagidolla/KORIK_SCANNER",
+                    "rules": rules.into_values().collect::<Vec<_>>(),
+                },
+            },
+            "automationDetails": { "id": format!("korik/{}/{}", report.target, report.id) },
+            "results": results,
+        }],
+    })
+}
+
+fn severity_color(severity: SEVERITY) -> &'static str {
+    match severity {
+        SEVERITY::CRITICAL => "#7b0000",
+        SEVERITY::HIGH => "#c0392b",
+        SEVERITY::MEDIUM => "#e67e22",
+        SEVERITY::LOW => "#2980b9",
+        SEVERITY::INFO => "#7f8c8d",
+    }
+}
+
+fn html_finding(finding: &Finding) -> String {
+    let mut rows = vec![
+        ("URL", finding.url.clone()),
+        ("Method", finding.method.clone()),
+        ("Parameter", finding.parameter.clone()),
+        ("Payload", finding.payload.clone()),
+        ("Detail", finding.detail.clone()),
+        ("DBMS", finding.dbms.clone().unwrap_or_default()),
+        ("Confidence", format!("{:?}", finding.confidence)),
+    ];
+    if let Some(cwe) = finding.cwe {
+        rows.push(("CWE", format!("CWE-{}", cwe)));
+    }
+
+    let table: String = rows
+        .iter()
+        .filter(|(_, value)| !value.is_empty())
+        .map(|(name, value)| format!("<tr><th>{}</th><td><code>{}</code></td></tr>", name, encode_text(value)))
+        .collect();
+
+    let extracted: String = finding
+        .extracted
+        .iter()
+        .map(|(name, value)| format!("<tr><th>{}</th><td><code>{}</code></td></tr>", encode_text(name), encode_text(value)))
+        .collect();
+    let extracted = if extracted.is_empty() {
+        extracted
+    } else {
+        format!("<h5>Extracted data</h5><table>{}</table>", extracted)
+    };
+
+    let mut evidence = String::new();
+    if !finding.request.is_empty() {
+        evidence.push_str(&format!(
+            "<details><summary>Request</summary><pre>{}</pre></details>",
+            encode_text(&finding.request)
+        ));
+    }
+    if !finding.response.is_empty() {
+        evidence.push_str(&format!(
+            "<details><summary>Response</summary><pre>{}</pre></details>",
+            encode_text(&finding.response)
+        ));
+    }
+
+    format!(
+        "<div class=\"finding\"><h4><span class=\"sev\" style=\"background:{}\">{:?}</span> {}</h4><table>{}</table>{}{}</div>",
+        severity_color(finding.severity),
+        finding.severity,
+        encode_text(&finding.title),
+        table,
+        extracted,
+        evidence
+    )
+}
+
+// Self-contained page, findings grouped by host and then by module
+pub fn report_html(report: &ScanReport) -> String {
+    let mut hosts: BTreeMap<String, BTreeMap<String, Vec<&Finding>>> = BTreeMap::new();
+    for finding in &report.findings {
+        hosts
+            .entry(finding.host())
+            .or_default()
+            .entry(finding.module.clone())
+            .or_default()
+            .push(finding);
+    }
+
+    let mut body = String::new();
+    for (host, modules) in &hosts {
+        body.push_str(&format!(
+            "<section id=\"{}\"><h2>{}</h2>",
+            encode_double_quoted_attribute(host),
+            encode_text(host)
+        ));
+        for (module, findings) in modules {
+            body.push_str(&format!("<h3>{} ({})</h3>", encode_text(module), findings.len()));
+            for finding in findings {
+                body.push_str(&html_finding(finding));
+            }
+        }
+        body.push_str("</section>");
+    }
+
+    let counts: String = [
+        SEVERITY::CRITICAL,
+        SEVERITY::HIGH,
+        SEVERITY::MEDIUM,
+        SEVERITY::LOW,
+        SEVERITY::INFO,
+    ]
+    .iter()
+    .map(|severity| {
+        format!(
+            "<span class=\"sev\" style=\"background:{}\">{:?}: {}</span> ",
+            severity_color(*severity),
+            severity,
+            report.count(*severity)
+        )
+    })
+    .collect();
+
+    format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>KORIK_SCANNER report - {target}</title>
+<style>
+body {{ font-family: sans-serif; margin: 2em; color: #222; }}
+section {{ border-top: 2px solid #ccc; margin-top: 2em; }}
+.finding {{ border: 1px solid #ddd; border-radius: 4px; padding: 0.5em 1em; margin: 1em 0; }}
+.sev {{ color: #fff; padding: 2px 6px; border-radius: 3px; font-size: 0.85em; }}
+th {{ text-align: left; padding-right: 1em; vertical-align: top; }}
+pre {{ background: #f5f5f5; padding: 1em; overflow-x: auto; white-space: pre-wrap; word-break: break-all; }}
+</style>
+</head>
+<body>
+<h1>Scan #{id} of {target}</h1>
+<p>Started {started}, finished {finished} (unix time), {total} findings</p>
+<p>{counts}</p>
+{body}
+</body>
+</html>
+"#,
+        target = encode_text(&report.target),
+        id = report.id,
+        started = report.started,
+        finished = report.finished,
+        total = report.findings.len(),
+        counts = counts,
+        body = body
+    )
+}
+
+pub fn write_report(report: &ScanReport, path: &str, format: FORMAT) -> Result<(), anyhow::Error> {
+    match format {
+        FORMAT::JSONL => {
+            let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+            file.write_all(report_jsonl(report).as_bytes())?;
+        }
+        FORMAT::JSON => {
+            let mut file = File::create(path)?;
+            file.write_all(serde_json::to_string_pretty(&report_json(report))?.as_bytes())?;
+        }
+        FORMAT::SARIF => {
+            let mut file = File::create(path)?;
+            file.write_all(serde_json::to_string_pretty(&report_sarif(report))?.as_bytes())?;
+        }
+        FORMAT::HTML => {
+            let mut file = File::create(path)?;
+            file.write_all(report_html(report).as_bytes())?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::report::report::CONFIDENCE;
+
+    fn report() -> ScanReport {
+        let mut report = ScanReport::new(7, "https://target.example");
+        report.extend(vec![
+            Finding {
+                url: "https://target.example/item?id=1".to_string(),
+                method: "GET".to_string(),
+                parameter: "id".to_string(),
+                payload: "' AND 1=1-- -".to_string(),
+                cwe: Some(89),
+                ..Finding::new("sqli", "Boolean-based blind SQL injection", SEVERITY::HIGH, CONFIDENCE::CONFIRMED)
+            },
+            Finding {
+                url: "https://target.example/item?id=2".to_string(),
+                method: "GET".to_string(),
+                parameter: "id".to_string(),
+                cwe: Some(89),
+                ..Finding::new("sqli", "Error-based SQL injection", SEVERITY::HIGH, CONFIDENCE::FIRM)
+            },
+            Finding {
+                url: "https://cdn.target.example/admin/".to_string(),
+                method: "GET".to_string(),
+                detail: "<script>alert(1)</script>".to_string(),
+                ..Finding::new("discovery", "Content discovered", SEVERITY::INFO, CONFIDENCE::FIRM)
+            },
+        ]);
+        report
+    }
+
+    #[test]
+    fn sarif_has_one_rule_per_module_and_cwe() {
+        let sarif = report_sarif(&report());
+        let run = &sarif["runs"][0];
+        let rules = run["tool"]["driver"]["rules"].as_array().unwrap();
+        assert_eq!(rules.len(), 2);
+        let results = run["results"].as_array().unwrap();
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[0]["ruleId"], "sqli/CWE-89");
+        assert_eq!(results[0]["level"], "error");
+        assert_eq!(results[2]["ruleId"], "discovery");
+        assert_eq!(results[2]["level"], "note");
+        assert_eq!(results[0]["locations"][0]["physicalLocation"]["artifactLocation"]["uri"], "https://target.example/item?id=1");
+    }
+
+    #[test]
+    fn sarif_help_uri_only_with_a_cwe() {
+        let sarif = report_sarif(&report());
+        let rules = sarif["runs"][0]["tool"]["driver"]["rules"].as_array().unwrap();
+        let sqli = rules.iter().find(|rule| rule["id"] == "sqli/CWE-89").unwrap();
+        assert_eq!(sqli["helpUri"], "https://cwe.mitre.org/data/definitions/89.html");
+        let discovery = rules.iter().find(|rule| rule["id"] == "discovery").unwrap();
+        assert!(discovery.get("helpUri").is_none());
+    }
+
+    #[test]
+    fn html_groups_by_host_and_escapes() {
+        let html = report_html(&report());
+        assert!(html.contains("<section id=\"target.example\">"));
+        assert!(html.contains("<section id=\"cdn.target.example\">"));
+        assert!(html.contains("<h3>sqli (2)</h3>"));
+        assert!(html.contains("HIGH: 2"));
+        assert!(html.contains("&lt;script&gt;alert(1)&lt;/script&gt;"));
+        assert!(!html.contains("<script>alert(1)</script>"));
+    }
+
+    fn temp_path(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("scaner-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_file(&path);
+        path.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn json_round_trip() {
+        let path = temp_path("report.json");
+        let mut report = report();
+        report.findings[0].dbms = Some("MySQL".to_string());
+        report.findings[0].extracted = vec![("version()".to_string(), "8.0.36".to_string())];
+        report.findings[0].request = "GET /item?id=1 HTTP/1.1".to_string();
+        write_report(&report, &path, FORMAT::JSON).unwrap();
+        // a second JSON write replaces the file instead of appending
+        write_report(&report, &path, FORMAT::JSON).unwrap();
+        let parsed: Value = serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(parsed, report_json(&report));
+        assert_eq!(parsed["id"], 7);
+        assert_eq!(parsed["target"], "https://target.example");
+        let findings = parsed["findings"].as_array().unwrap();
+        assert_eq!(findings.len(), 3);
+        assert_eq!(findings[0]["severity"], "HIGH");
+        assert_eq!(findings[0]["confidence"], "CONFIRMED");
+        assert_eq!(findings[0]["payload"], "' AND 1=1-- -");
+        assert_eq!(findings[0]["dbms"], "MySQL");
+        assert_eq!(findings[0]["extracted"][0]["name"], "version()");
+        assert_eq!(findings[0]["extracted"][0]["value"], "8.0.36");
+        assert_eq!(findings[0]["evidence"]["request"], "GET /item?id=1 HTTP/1.1");
+        assert_eq!(findings[2]["cwe"], Value::Null);
+        assert_eq!(findings[2]["detail"], "<script>alert(1)</script>");
+    }
+
+    #[test]
+    fn jsonl_appends_across_writes() {
+        let path = temp_path("report.jsonl");
+        let first = report();
+        let mut second = ScanReport::new(8, "https://other.example");
+        second.extend(vec![Finding {
+            url: "https://other.example/".to_string(),
+            ..Finding::new("ports", "Open port", SEVERITY::INFO, CONFIDENCE::CONFIRMED)
+        }]);
+        second.finished = 1700000000;
+        write_report(&first, &path, FORMAT::JSONL).unwrap();
+        write_report(&second, &path, FORMAT::JSONL).unwrap();
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(contents.ends_with('\n'));
+        let lines: Vec<Value> = contents.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        assert_eq!(lines.len(), 4);
+        for (line, finding) in lines[..3].iter().zip(&first.findings) {
+            assert_eq!(line["scan_id"], 7);
+            assert_eq!(line["target"], "https://target.example");
+            assert_eq!(line["url"], finding.url);
+            assert_eq!(line["title"], finding.title);
+        }
+        assert_eq!(lines[3]["scan_id"], 8);
+        assert_eq!(lines[3]["target"], "https://other.example");
+        assert_eq!(lines[3]["finished"], 1700000000);
+        assert_eq!(lines[3]["module"], "ports");
+    }
+}
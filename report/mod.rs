@@ -0,0 +1,2 @@
+pub mod report;
+pub mod export;
\ No newline at end of file
@@ -0,0 +1,218 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+use url::Url;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Default)]
+pub enum SEVERITY {
+    CRITICAL,
+    HIGH,
+    MEDIUM,
+    LOW,
+    #[default]
+    INFO,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Default)]
+pub enum CONFIDENCE {
+    CONFIRMED,
+    FIRM,
+    #[default]
+    TENTATIVE,
+}
+
+// Single result produced by any scanner module
+#[derive(Debug, Clone, Default)]
+pub struct Finding {
+    pub module: String,
+    pub title: String,
+    pub severity: SEVERITY,
+    pub confidence: CONFIDENCE,
+    pub url: String,
+    pub method: String,
+    pub parameter: String,
+    pub payload: String,
+    pub detail: String,
+    pub dbms: Option<String>,
+    // (what, value) pairs read out of the target after confirmation, e.g. by sqli --extract
+    pub extracted: Vec<(String, String)>,
+    pub request: String,
+    pub response: String,
+    pub cwe: Option<u32>,
+}
+
+impl Finding {
+    pub fn new(module: &str, title: &str, severity: SEVERITY, confidence: CONFIDENCE) -> Self {
+        Finding {
+            module: module.to_string(),
+            title: title.to_string(),
+            severity,
+            confidence,
+            ..Default::default()
+        }
+    }
+
+    pub fn host(&self) -> String {
+        Url::parse(&self.url)
+            .ok()
+            .and_then(|url| url.host_str().map(String::from))
+            .unwrap_or_else(|| self.url.clone())
+    }
+
+    pub fn summary(&self) -> String {
+        format!(
+            "[{:?}/{:?}] {} - {} {} {}{}",
+            self.severity,
+            self.confidence,
+            self.module,
+            self.title,
+            self.method,
+            self.url,
+            if self.parameter.is_empty() {
+                "".to_string()
+            } else {
+                format!(" (param: {})", self.parameter)
+            }
+        )
+    }
+}
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+// Everything one SCANER run found
+#[derive(Debug, Clone)]
+pub struct ScanReport {
+    pub id: i32,
+    pub target: String,
+    pub started: u64,
+    pub finished: u64,
+    pub findings: Vec<Finding>,
+}
+
+impl ScanReport {
+    pub fn new(id: i32, target: &str) -> Self {
+        ScanReport {
+            id,
+            target: target.to_string(),
+            started: now(),
+            finished: 0,
+            findings: Vec::new(),
+        }
+    }
+
+    pub fn extend(&mut self, findings: Vec<Finding>) {
+        self.findings.extend(findings);
+    }
+
+    pub fn merge(&mut self, other: ScanReport) {
+        self.started = self.started.min(other.started);
+        self.finished = self.finished.max(other.finished);
+        self.findings.extend(other.findings);
+    }
+
+    pub fn finish(&mut self) {
+        self.findings.sort_by(|a, b| a.severity.cmp(&b.severity));
+        self.finished = now();
+    }
+
+    pub fn count(&self, severity: SEVERITY) -> usize {
+        self.findings.iter().filter(|f| f.severity == severity).count()
+    }
+
+    pub fn summary(&self) -> String {
+        let mut lines = vec![format!(
+            "Scan #{} of {}: {} findings (critical {}, high {}, medium {}, low {}, info {})",
+            self.id,
+            self.target,
+            self.findings.len(),
+            self.count(SEVERITY::CRITICAL),
+            self.count(SEVERITY::HIGH),
+            self.count(SEVERITY::MEDIUM),
+            self.count(SEVERITY::LOW),
+            self.count(SEVERITY::INFO),
+        )];
+        lines.extend(self.findings.iter().map(|f| format!("  {}", f.summary())));
+        lines.join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn finding(severity: SEVERITY, url: &str) -> Finding {
+        Finding {
+            url: url.to_string(),
+            method: "GET".to_string(),
+            ..Finding::new("sqli", "SQL injection", severity, CONFIDENCE::FIRM)
+        }
+    }
+
+    #[test]
+    fn new_finding_starts_empty() {
+        let finding = Finding::new("xss", "Reflected XSS", SEVERITY::HIGH, CONFIDENCE::CONFIRMED);
+        assert_eq!(finding.module, "xss");
+        assert_eq!(finding.title, "Reflected XSS");
+        assert_eq!(finding.severity, SEVERITY::HIGH);
+        assert_eq!(finding.confidence, CONFIDENCE::CONFIRMED);
+        assert!(finding.url.is_empty());
+        assert!(finding.extracted.is_empty());
+        assert_eq!(finding.dbms, None);
+        assert_eq!(finding.cwe, None);
+    }
+
+    #[test]
+    fn host_falls_back_to_the_raw_url() {
+        assert_eq!(finding(SEVERITY::INFO, "https://target.example:8443/a?b=c").host(), "target.example");
+        assert_eq!(finding(SEVERITY::INFO, "10.0.0.5:22/tcp").host(), "10.0.0.5:22/tcp");
+    }
+
+    #[test]
+    fn summary_names_the_parameter_only_when_set() {
+        let mut finding = finding(SEVERITY::HIGH, "https://target.example/item");
+        assert_eq!(finding.summary(), "[HIGH/FIRM] sqli - SQL injection GET https://target.example/item");
+        finding.parameter = "id".to_string();
+        assert!(finding.summary().ends_with(" (param: id)"));
+    }
+
+    #[test]
+    fn merge_keeps_the_widest_time_span() {
+        let mut first = ScanReport::new(1, "https://target.example");
+        first.started = 100;
+        first.finished = 200;
+        first.extend(vec![finding(SEVERITY::LOW, "https://target.example/a")]);
+        let mut second = ScanReport::new(1, "https://target.example");
+        second.started = 50;
+        second.finished = 300;
+        second.extend(vec![finding(SEVERITY::HIGH, "https://target.example/b")]);
+        first.merge(second);
+        assert_eq!(first.started, 50);
+        assert_eq!(first.finished, 300);
+        assert_eq!(first.findings.len(), 2);
+    }
+
+    #[test]
+    fn finish_sorts_by_severity_and_counts() {
+        let mut report = ScanReport::new(3, "https://target.example");
+        report.extend(vec![
+            finding(SEVERITY::INFO, "https://target.example/a"),
+            finding(SEVERITY::CRITICAL, "https://target.example/b"),
+            finding(SEVERITY::MEDIUM, "https://target.example/c"),
+            finding(SEVERITY::CRITICAL, "https://target.example/d"),
+        ]);
+        report.finish();
+        let order: Vec<SEVERITY> = report.findings.iter().map(|f| f.severity).collect();
+        assert_eq!(order, vec![SEVERITY::CRITICAL, SEVERITY::CRITICAL, SEVERITY::MEDIUM, SEVERITY::INFO]);
+        // stable sort, same severity keeps the order it was found in
+        assert_eq!(report.findings[0].url, "https://target.example/b");
+        assert!(report.finished >= report.started);
+        assert_eq!(report.count(SEVERITY::CRITICAL), 2);
+        assert_eq!(report.count(SEVERITY::HIGH), 0);
+        let summary = report.summary();
+        assert!(summary.starts_with("Scan #3 of https://target.example: 4 findings (critical 2, high 0, medium 1, low 0, info 1)"));
+        assert_eq!(summary.lines().count(), 5);
+    }
+}
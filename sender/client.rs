@@ -0,0 +1,378 @@
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
+
+use anyhow::anyhow;
+use base64::Engine;
+use reqwest::cookie::{CookieStore, Jar};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::redirect::Policy;
+use url::Url;
+
+use crate::sender::limiter::limiter;
+use crate::sender::scope::{check_url, scope, Scope};
+use crate::sender::session::{session, LoginOptions};
+
+pub const DEFAULT_USER_AGENT: &str = "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/119.0.0.0 Safari/537.36";
+
+// Redirects followed before giving up, --max-redirects overrides it and 0 turns following off
+pub const DEFAULT_REDIRECTS: usize = 5;
+
+#[derive(Debug, Clone)]
+pub enum AUTH {
+    BASIC(String, String),
+    BEARER(String),
+}
+
+impl AUTH {
+    pub fn header(&self) -> String {
+        match self {
+            AUTH::BASIC(user, password) => format!(
+                "Basic {}",
+                base64::engine::general_purpose::STANDARD.encode(format!("{}:{}", user, password))
+            ),
+            AUTH::BEARER(token) => format!("Bearer {}", token),
+        }
+    }
+}
+
+// Everything about how the scanner talks HTTP that the user gets a say in
+#[derive(Debug, Clone)]
+pub struct ClientOptions {
+    // Seeds the cookie jar for the target, "a=1; b=2"
+    pub cookie: Option<String>,
+    // "Name: value" lines sent with every request
+    pub headers: Vec<String>,
+    pub auth: Option<AUTH>,
+    pub proxy: Option<String>,
+    pub redirects: usize,
+    pub user_agent: String,
+    pub login: Option<LoginOptions>,
+    // Take any TLS certificate, for self-signed test hosts and intercepting proxies. Raw requests follow
+    // the same setting
+    pub insecure: bool,
+}
+
+impl Default for ClientOptions {
+    fn default() -> Self {
+        ClientOptions {
+            cookie: None,
+            headers: vec![],
+            auth: None,
+            proxy: None,
+            redirects: DEFAULT_REDIRECTS,
+            user_agent: DEFAULT_USER_AGENT.to_string(),
+            login: None,
+            insecure: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct HttpResponse {
+    // Where the response came from after redirects
+    pub url: String,
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+    pub elapsed: Duration,
+}
+
+impl HttpResponse {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+// Redirects count against the limit and never leave scope, hops is how many were followed already
+fn follows(hops: usize, limit: usize, url: &Url, scope: Option<&Scope>) -> bool {
+    hops < limit && scope.map_or(true, |scope| scope.check(url.as_str()).is_ok())
+}
+
+pub struct HttpClient {
+    inner: reqwest::Client,
+    jar: Arc<Jar>,
+    pub options: ClientOptions,
+}
+
+static CLIENT: OnceLock<HttpClient> = OnceLock::new();
+
+// Build the scanner-wide client, cookies from options are set for base. Only the first call wins
+pub fn install(base: &str, options: ClientOptions) -> Result<(), anyhow::Error> {
+    let client = HttpClient::new(base, options)?;
+    let _ = CLIENT.set(client);
+    Ok(())
+}
+
+pub fn client() -> &'static HttpClient {
+    CLIENT.get_or_init(|| HttpClient::new("http://localhost/", ClientOptions::default()).expect("default client"))
+}
+
+impl HttpClient {
+    pub fn new(base: &str, options: ClientOptions) -> Result<Self, anyhow::Error> {
+        let profile = &limiter().profile;
+        let jar = Arc::new(Jar::default());
+        if let Some(cookie) = &options.cookie {
+            let base = Url::parse(base)?;
+            for pair in cookie.split(';').map(str::trim).filter(|pair| !pair.is_empty()) {
+                jar.add_cookie_str(pair, &base);
+            }
+        }
+
+        let mut defaults = HeaderMap::new();
+        for line in &options.headers {
+            let (name, value) = line.split_once(':').ok_or_else(|| anyhow!("invalid header '{}'", line))?;
+            defaults.append(HeaderName::from_bytes(name.trim().as_bytes())?, HeaderValue::from_str(value.trim())?);
+        }
+        if let Some(auth) = &options.auth {
+            defaults.insert(reqwest::header::AUTHORIZATION, HeaderValue::from_str(&auth.header())?);
+        }
+
+        let limit = options.redirects;
+        let redirects = Policy::custom(move |attempt| match follows(attempt.previous().len(), limit, attempt.url(), scope()) {
+            true => attempt.follow(),
+            false => attempt.stop(),
+        });
+        let mut builder = reqwest::Client::builder()
+            .danger_accept_invalid_certs(options.insecure)
+            .timeout(profile.timeout)
+            .connect_timeout(profile.connect_timeout)
+            .pool_max_idle_per_host(profile.max_in_flight)
+            .redirect(redirects)
+            .user_agent(options.user_agent.clone())
+            .default_headers(defaults)
+            .cookie_provider(jar.clone());
+        if let Some(proxy) = &options.proxy {
+            builder = builder.proxy(reqwest::Proxy::all(proxy)?);
+        }
+
+        Ok(HttpClient {
+            inner: builder.build()?,
+            jar,
+            options,
+        })
+    }
+
+    // Any method with an optional body, extra headers go on top of the defaults. When the response says
+    // the login session is gone it is renewed and the request made once more
+    pub async fn request(
+        &self,
+        method: &str,
+        url: &str,
+        headers: &[(&str, &str)],
+        body: Option<String>,
+    ) -> Result<HttpResponse, anyhow::Error> {
+        let response = self.fetch(method, url, headers, body.clone()).await?;
+        match session() {
+            Some(session) if session.observe(response.status, Some(&response.url)).await => {
+                self.fetch(method, url, headers, body).await
+            }
+            _ => Ok(response),
+        }
+    }
+
+    // request without the session bookkeeping, what the login itself goes through
+    pub async fn fetch(
+        &self,
+        method: &str,
+        url: &str,
+        headers: &[(&str, &str)],
+        body: Option<String>,
+    ) -> Result<HttpResponse, anyhow::Error> {
+        check_url(url)?;
+        let method = reqwest::Method::from_bytes(method.to_uppercase().as_bytes())?;
+        let host = Url::parse(url)?.host_str().unwrap_or_default().to_string();
+        let _permit = limiter().acquire(&host).await;
+
+        let mut request = self.inner.request(method, url);
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+        if let Some(body) = body {
+            request = request.body(body);
+        }
+
+        let started = Instant::now();
+        let response = request.send().await?;
+        let url = response.url().to_string();
+        let status = response.status().as_u16();
+        let headers = response
+            .headers()
+            .iter()
+            .map(|(name, value)| (name.to_string(), String::from_utf8_lossy(value.as_bytes()).to_string()))
+            .collect();
+        let body = response.text().await?;
+
+        Ok(HttpResponse {
+            url,
+            status,
+            headers,
+            body,
+            elapsed: started.elapsed(),
+        })
+    }
+
+    pub async fn get(&self, url: &str) -> Result<HttpResponse, anyhow::Error> {
+        self.request("GET", url, &[], None).await
+    }
+
+    pub async fn post(&self, url: &str, content_type: &str, body: String) -> Result<HttpResponse, anyhow::Error> {
+        self.request("POST", url, &[("Content-Type", content_type)], Some(body)).await
+    }
+
+    // GET for files that aren't text, such as gzipped sitemaps. Status and the body untouched
+    pub async fn download(&self, url: &str) -> Result<(u16, Vec<u8>), anyhow::Error> {
+        check_url(url)?;
+        let host = Url::parse(url)?.host_str().unwrap_or_default().to_string();
+        let _permit = limiter().acquire(&host).await;
+
+        let response = self.inner.get(url).send().await?;
+        let status = response.status().as_u16();
+        Ok((status, response.bytes().await?.to_vec()))
+    }
+
+    // The same headers, auth and user agent as header lines for the raw suraw requests the modules build
+    // themselves, each terminated by CRLF. Cookies are left out, send_request takes them from the jar
+    // when the request goes out so a re-login reaches requests already being built
+    pub fn raw_headers(&self) -> Option<String> {
+        let mut lines = vec![format!("User-Agent: {}\r\n", self.options.user_agent)];
+        if let Some(auth) = &self.options.auth {
+            lines.push(format!("Authorization: {}\r\n", auth.header()));
+        }
+        lines.extend(self.options.headers.iter().map(|h| format!("{}\r\n", h)));
+        Some(lines.concat())
+    }
+
+    // Cookie header value the jar holds for url right now
+    pub fn cookie_header(&self, url: &str) -> Option<String> {
+        let cookies = self.jar.cookies(&Url::parse(url).ok()?)?;
+        Some(String::from_utf8_lossy(cookies.as_bytes()).to_string())
+    }
+
+    // Set-Cookie headers from a response we got some other way, so the jar stays current
+    pub fn store_cookies<'a>(&self, url: &str, set_cookies: impl Iterator<Item = &'a str>) {
+        let Ok(url) = Url::parse(url) else { return };
+        let values: Vec<HeaderValue> = set_cookies.filter_map(|value| HeaderValue::from_str(value).ok()).collect();
+        self.jar.set_cookies(&mut values.iter(), &url);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    use crate::sender::scope::ScopeOptions;
+
+    // Local server answering each connection with respond(path), the request heads it got come back in order
+    async fn server(respond: fn(&str) -> String) -> (String, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        let heads = Arc::new(Mutex::new(vec![]));
+        let seen = heads.clone();
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let mut head = vec![];
+                let mut byte = [0u8; 1];
+                while !head.ends_with(b"\r\n\r\n") && socket.read(&mut byte).await.unwrap_or(0) == 1 {
+                    head.push(byte[0]);
+                }
+                let head = String::from_utf8_lossy(&head).to_string();
+                let path = head.split_whitespace().nth(1).unwrap_or("/").to_string();
+                seen.lock().unwrap().push(head);
+                let _ = socket.write_all(respond(&path).as_bytes()).await;
+            }
+        });
+        (base, heads)
+    }
+
+    fn redirecting(path: &str) -> String {
+        match path {
+            "/a" => "HTTP/1.1 302 Found\r\nLocation: /b\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string(),
+            "/b" => "HTTP/1.1 302 Found\r\nLocation: /c\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string(),
+            _ => "HTTP/1.1 200 OK\r\nContent-Length: 4\r\nConnection: close\r\n\r\ndone".to_string(),
+        }
+    }
+
+    fn with(options: ClientOptions, base: &str) -> HttpClient {
+        HttpClient::new(base, options).unwrap()
+    }
+
+    #[tokio::test]
+    async fn redirects_stop_at_the_limit() {
+        let (base, _) = server(redirecting).await;
+        let url = format!("{}/a", base);
+
+        let followed = with(ClientOptions::default(), &base).fetch("GET", &url, &[], None).await.unwrap();
+        assert_eq!((followed.status, followed.body.as_str()), (200, "done"));
+        assert!(followed.url.ends_with("/c"));
+
+        let once = with(ClientOptions { redirects: 1, ..ClientOptions::default() }, &base);
+        let response = once.fetch("GET", &url, &[], None).await.unwrap();
+        assert_eq!(response.status, 302);
+        assert!(response.url.ends_with("/b"));
+
+        let never = with(ClientOptions { redirects: 0, ..ClientOptions::default() }, &base);
+        let response = never.fetch("GET", &url, &[], None).await.unwrap();
+        assert_eq!(response.status, 302);
+        assert!(response.url.ends_with("/a"));
+    }
+
+    #[test]
+    fn redirects_never_leave_scope() {
+        let scope = Scope::new("target.example", &ScopeOptions::default()).unwrap();
+        let inside = Url::parse("https://target.example/next").unwrap();
+        let outside = Url::parse("https://evil.example/next").unwrap();
+        assert!(follows(0, 5, &inside, Some(&scope)));
+        assert!(!follows(0, 5, &outside, Some(&scope)));
+        assert!(!follows(5, 5, &inside, Some(&scope)));
+        assert!(follows(0, 5, &outside, None));
+    }
+
+    #[tokio::test]
+    async fn default_and_auth_headers_merge_with_request_headers() {
+        let (base, heads) = server(|_| "HTTP/1.1 200 OK\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string()).await;
+        let options = ClientOptions {
+            headers: vec!["X-Team: red".to_string(), "X-Trace: 1".to_string()],
+            auth: Some(AUTH::BASIC("bob".to_string(), "pw".to_string())),
+            user_agent: "korik-test".to_string(),
+            ..ClientOptions::default()
+        };
+        let client = with(options, &base);
+        client.fetch("GET", &format!("{}/one", base), &[("X-Extra", "2")], None).await.unwrap();
+        // A request header replaces the default of the same name
+        client.fetch("GET", &format!("{}/two", base), &[("Authorization", "Bearer t")], None).await.unwrap();
+
+        let heads = heads.lock().unwrap().iter().map(|head| head.to_lowercase()).collect::<Vec<_>>();
+        assert!(heads[0].contains("x-team: red\r\n") && heads[0].contains("x-trace: 1\r\n"));
+        assert!(heads[0].contains("x-extra: 2\r\n"));
+        assert!(heads[0].contains("user-agent: korik-test\r\n"));
+        // base64 of bob:pw
+        assert!(heads[0].contains("authorization: basic Ym9iOnB3\r\n"));
+        assert!(heads[1].contains("authorization: bearer t\r\n"));
+        assert!(!heads[1].contains("basic"));
+
+        assert_eq!(
+            client.raw_headers().unwrap(),
+            "User-Agent: korik-test\r\nAuthorization: Basic Ym9iOnB3\r\nX-Team: red\r\nX-Trace: 1\r\n"
+        );
+    }
+
+    #[test]
+    fn cookie_jar_is_seeded_and_kept_current() {
+        let options = ClientOptions { cookie: Some("a=1; b=2".to_string()), ..ClientOptions::default() };
+        let client = with(options, "http://target.example/");
+        let cookies = client.cookie_header("http://target.example/page").unwrap();
+        assert!(cookies.contains("a=1") && cookies.contains("b=2"), "{}", cookies);
+        assert_eq!(client.cookie_header("http://other.example/"), None);
+
+        client.store_cookies("http://target.example/login", ["a=9; Path=/", "session=xyz; Path=/; HttpOnly"].into_iter());
+        let cookies = client.cookie_header("http://target.example/page").unwrap();
+        assert!(cookies.contains("a=9") && cookies.contains("session=xyz"), "{}", cookies);
+        assert!(!cookies.contains("a=1"));
+    }
+}
@@ -1,14 +1,19 @@
-use futures::stream::StreamExt;
+use base64::Engine;
+use futures::stream::{self, StreamExt};
 use regex::Regex;
-use reqwest;
 use scraper::{Html, Selector};
-use std::collections::HashSet;
+use std::collections::{HashSet, VecDeque};
 use std::fs::File;
 use std::io::{self, BufRead, BufReader};
-use std::sync::{Arc, Mutex};
-use std::time::Duration;
-use crate::sender::suraw::jiber;
-use hyper::HeaderMap;
+use url::Url;
+use crate::sender::client::client;
+use crate::sender::fingerprint::not_found;
+use crate::sender::limiter::limiter;
+use crate::sender::scope::check_url;
+use crate::sender::seeds::{self, Robots, Seed, SOURCE};
+use crate::sender::session::is_logout;
+use crate::method_parser::javascript::{self, Endpoints};
+use crate::method_parser::method_parser::Method;
 
 
 #[derive(Debug)]
@@ -17,101 +22,271 @@ pub struct CrawlLink {
     pub outer: Vec<String>,
 }
 
-#[derive(Debug, PartialEq, Eq, Hash, Clone)]
-pub struct ResponseData {
-    status_code: String,
-    endpoint: String,
-    body_length: usize,
+// Everything the crawl phase hands to the vulnerability modules
+#[derive(Debug, Clone, Default)]
+pub struct Surface {
+    pub urls: Vec<String>,
+    pub methods: Vec<Method>,
+    // Pages found through robots.txt or a sitemap rather than a link, and which one
+    pub seeds: Vec<(String, SOURCE)>,
+    // Url and body of every HTML page and script the crawl fetched, so modules reading them needn't ask again
+    pub pages: Vec<(String, String)>,
 }
 
-pub trait Similar {
-    fn is_similar_to(&self, other: &Self, tolerance: u32) -> bool;
+// Breadth-first crawl limits, --crawl-depth and --max-pages override them
+pub const DEFAULT_CRAWL_DEPTH: usize = 3;
+pub const DEFAULT_MAX_PAGES: usize = 300;
+
+#[derive(Debug, Clone)]
+pub struct Crawler {
+    // Link hops from the seeds, 0 fetches the seeds alone
+    pub max_depth: usize,
+    pub max_pages: usize,
+    // Leave out what robots.txt disallows, robots.txt paths are crawled like any other seed otherwise
+    pub obey_robots: bool,
+    // Found before the crawl, by forced browsing, and crawled along with the start urls
+    pub seeds: Vec<Seed>,
 }
 
-impl Similar for ResponseData {
-    fn is_similar_to(&self, other: &ResponseData, tolerance: u32) -> bool {
-        let diff = if self.body_length > other.body_length {
-            self.body_length - other.body_length
-        } else {
-            other.body_length - self.body_length
-        };
-        diff <= tolerance as usize
+impl Default for Crawler {
+    fn default() -> Self {
+        Crawler {
+            max_depth: DEFAULT_CRAWL_DEPTH,
+            max_pages: DEFAULT_MAX_PAGES,
+            obey_robots: false,
+            seeds: vec![],
+        }
     }
 }
 
-impl CrawlLink {
-    pub async fn scrape_all(&self, url: &str) -> Vec<String> {
-        if self.inner != vec![""] {
-            self.inner.iter().map(|link| format!("{}{}", url.to_string(), link)).collect()
-        } else {
-            vec!["".to_string()]
+#[derive(Debug, Clone)]
+pub struct Page {
+    // Final url after redirects, links on the page resolve against it
+    pub url: String,
+    pub depth: usize,
+    pub source: SOURCE,
+    pub status: u16,
+    pub content_type: String,
+    pub body: String,
+    // Requests and urls in the page's scripts, resolved against the page
+    pub scripted: Endpoints,
+}
+
+impl Page {
+    pub fn is_html(&self) -> bool {
+        if self.content_type.is_empty() {
+            return self.body.trim_start().starts_with('<');
         }
+        self.content_type.contains("html")
     }
-    pub fn combine(links: Vec<CrawlLink>) -> CrawlLink {
-        let mut combined_inner = Vec::new();
-        let mut combined_outer = Vec::new();
 
-        for link in links {
-            combined_inner.extend(link.inner);
-            combined_outer.extend(link.outer);
+    pub fn is_script(&self) -> bool {
+        let path = self.url.split(['?', '#']).next().unwrap_or_default();
+        self.content_type.contains("javascript")
+            || self.content_type.contains("ecmascript")
+            || path.ends_with(".js")
+            || path.ends_with(".mjs")
+    }
+}
+
+// Original sources out of a script's source map, inline data: maps included
+async fn source_map(script: &Url, location: &str) -> Vec<String> {
+    let map = match location.strip_prefix("data:") {
+        Some(data) => {
+            let Some((_, encoded)) = data.split_once("base64,") else { return vec![] };
+            let Ok(bytes) = base64::engine::general_purpose::STANDARD.decode(encoded) else { return vec![] };
+            String::from_utf8_lossy(&bytes).to_string()
+        }
+        None => {
+            let Ok(url) = script.join(location) else { return vec![] };
+            match client().get(url.as_str()).await {
+                Ok(response) if response.status == 200 => response.body,
+                _ => return vec![],
+            }
         }
+    };
+    javascript::map_sources(&map)
+}
+
+// Endpoints in a page's inline scripts, or in a script and its source map. Relative urls resolve against
+// the page; for an external script that is its own url, as close as we get without the page that loaded it
+async fn scripted(page: &Page, map: Option<String>) -> Endpoints {
+    let Ok(page_url) = Url::parse(&page.url) else { return Endpoints::default() };
+    let mut sources = vec![];
+    if page.is_script() {
+        if let Some(map) = map.or_else(|| javascript::source_map_url(&page.body)) {
+            sources.extend(source_map(&page_url, &map).await);
+        }
+        sources.push(page.body.clone());
+    } else if page.is_html() {
+        sources = javascript::inline_scripts(&page.body);
+    }
 
-        // Return a new CrawlLink with all combined inner and outer links
-        CrawlLink {
-            inner: combined_inner,
-            outer: combined_outer,
+    let mut found = Endpoints::default();
+    for source in sources {
+        let endpoints = javascript::extract(&source);
+        for mut method in endpoints.methods {
+            let Ok(url) = page_url.join(&method.url) else { continue };
+            method.url = url.to_string();
+            found.methods.push(method);
+        }
+        for url in endpoints.urls {
+            let Ok(url) = page_url.join(&url) else { continue };
+            if !found.urls.contains(&url.to_string()) {
+                found.urls.push(url.to_string());
+            }
         }
     }
-    pub async fn depth(&self, url: &str) -> CrawlLink {
-        let inners = Arc::new(Mutex::new(Vec::new()));
-        let outers = Arc::new(Mutex::new(Vec::new()));
-
-        //firstly get from /robots.txt
-        let robots = jiber(&format!("{}/robots.txt", url)).await.unwrap_or(("".to_string(),HeaderMap::new(),"".to_string()))
-        ;
-        let re = Regex::new(r#"\s*(/[^ \n]*)"#).unwrap();
-        let endpoints: Vec<_> = re
-            .captures_iter(&robots.0)
-            .map(|endpoint| {
-                endpoint[1].to_string()
-            })
+    found
+}
+
+// One spelling per resource: no fragment, query parameters sorted, repeated slashes in the path collapsed.
+// Scheme and host case and default ports are already normalised by Url
+pub fn canonical(url: &Url) -> String {
+    let mut url = url.clone();
+    url.set_fragment(None);
+    if url.path().contains("//") {
+        let path = Regex::new("/{2,}").unwrap().replace_all(url.path(), "/").to_string();
+        url.set_path(&path);
+    }
+    match url.query() {
+        Some("") => url.set_query(None),
+        Some(_) => {
+            let mut pairs: Vec<(String, String)> = url.query_pairs().into_owned().collect();
+            pairs.sort();
+            url.query_pairs_mut().clear().extend_pairs(pairs);
+        }
+        None => {}
+    }
+    url.to_string()
+}
+
+// Every http(s) link on the page, resolved against <base href> when there is one, else the page url
+pub fn links(page: &Url, body: &str) -> Vec<Url> {
+    let document = Html::parse_document(body);
+    let base = Selector::parse("base[href]").unwrap();
+    let base = document
+        .select(&base)
+        .next()
+        .and_then(|element| element.value().attr("href"))
+        .and_then(|href| page.join(href.trim()).ok())
+        .unwrap_or_else(|| page.clone());
+
+    let found = parse(&body.to_string());
+    found
+        .inner
+        .iter()
+        .chain(found.outer.iter())
+        .filter_map(|link| base.join(link.trim()).ok())
+        .filter(|url| url.scheme() == "http" || url.scheme() == "https")
+        .collect()
+}
+
+impl Crawler {
+    // Breadth-first from the start urls through every in-scope link, each canonical url fetched once.
+    // self.seeds, robots.txt paths and sitemap urls of the first start url's site join the start urls at depth 0.
+    // A level is fetched concurrently but finished before the next one starts
+    pub async fn crawl(&self, start: &[String]) -> Vec<Page> {
+        let mut seeds: Vec<(Url, SOURCE)> = start
+            .iter()
+            .filter_map(|url| Url::parse(url).ok())
+            .map(|url| (url, SOURCE::START))
+            .chain(self.seeds.iter().map(|seed| (seed.url.clone(), seed.source.clone())))
             .collect();
-        inners.lock().unwrap().extend(endpoints);
-
-        let _requests = futures::stream::iter(self.scrape_all(url).await.iter().map(|link| {
-            let link = link.to_string();
-            let inners = Arc::clone(&inners);
-            let outers = Arc::clone(&outers);
-
-            tokio::spawn(async move {
-                let res = match jiber(&link).await {
-                    Ok(res) => res,
-                    Err(err) => {
-                        return;
+        let robots = match seeds.first() {
+            Some((root, _)) => {
+                let (found, robots) = seeds::discover(root).await;
+                seeds.extend(found.into_iter().map(|seed| (seed.url, seed.source)));
+                robots
+            }
+            None => Robots::default(),
+        };
+        let mut disallowed = 0;
+        let mut allowed = |url: &Url| {
+            let allowed = !self.obey_robots || robots.allows(url);
+            if !allowed {
+                disallowed += 1;
+            }
+            allowed
+        };
+
+        let mut visited = HashSet::new();
+        let mut frontier: VecDeque<(Url, usize, SOURCE)> = VecDeque::new();
+        for (url, source) in seeds {
+            if check_url(url.as_str()).is_err() {
+                continue;
+            }
+            if visited.insert(canonical(&url)) && allowed(&url) {
+                frontier.push_back((url, 0, source));
+            }
+        }
+
+        let mut pages: Vec<Page> = vec![];
+        while let Some((_, depth, _)) = frontier.front() {
+            let depth = *depth;
+            if pages.len() >= self.max_pages {
+                break;
+            }
+            let mut level = vec![];
+            while frontier.front().is_some_and(|(_, d, _)| *d == depth) {
+                level.extend(frontier.pop_front().map(|(url, _, source)| (url, source)));
+            }
+            level.truncate(self.max_pages - pages.len());
+
+            let fetched: Vec<Page> = stream::iter(level)
+                .map(|(url, source)| async move {
+                    let response = client().get(url.as_str()).await.ok()?;
+                    // Unlike links, robots.txt, sitemap and script urls are often stale or guesses, their
+                    // soft-404 pages are left out
+                    if !matches!(source, SOURCE::START | SOURCE::LINK) && !not_found().exists(&url, &response).await {
+                        return None;
+                    }
+                    let map = response.header("sourcemap").or(response.header("x-sourcemap")).map(String::from);
+                    let mut page = Page {
+                        content_type: response.header("content-type").unwrap_or_default().to_lowercase(),
+                        url: response.url,
+                        depth,
+                        source,
+                        status: response.status,
+                        body: response.body,
+                        scripted: Endpoints::default(),
+                    };
+                    page.scripted = scripted(&page, map).await;
+                    Some(page)
+                })
+                .buffer_unordered(limiter().profile.max_in_flight)
+                .filter_map(|page| async { page })
+                .collect()
+                .await;
+
+            for page in fetched {
+                let Ok(page_url) = Url::parse(&page.url) else { continue };
+                // Redirect targets count as visited too
+                visited.insert(canonical(&page_url));
+                if depth < self.max_depth {
+                    let mut found = vec![];
+                    if page.is_html() {
+                        found.extend(links(&page_url, &page.body).into_iter().map(|link| (link, SOURCE::LINK)));
                     }
-                };
-                let parsed_links = parse(&res.0);
-                inners.lock().unwrap().extend(parsed_links.inner);
-                outers.lock().unwrap().extend(parsed_links.outer);
-            })
-        }))
-        .buffer_unordered(10)
-        .collect::<Vec<_>>()
-        .await;
-
-        let inners = Arc::try_unwrap(inners)
-            .expect("Failed to unwrap Arc")
-            .into_inner()
-            .expect("Failed to get inner data from Mutex");
-        let outers = Arc::try_unwrap(outers)
-            .expect("Failed to unwrap Arc")
-            .into_inner()
-            .expect("Failed to get inner data from Mutex");
-
-
-        CrawlLink { inner: inners, outer: outers }
+                    found.extend(page.scripted.urls.iter().filter_map(|url| Url::parse(url).ok()).map(|url| (url, SOURCE::SCRIPT)));
+                    for (link, source) in found {
+                        if is_logout(link.as_str()) || check_url(link.as_str()).is_err() {
+                            continue;
+                        }
+                        // Marked visited either way, so a disallowed link on every page counts once
+                        if visited.insert(canonical(&link)) && allowed(&link) {
+                            frontier.push_back((link, depth + 1, source));
+                        }
+                    }
+                }
+                pages.push(page);
+            }
+        }
+        if disallowed > 0 {
+            eprintln!("Skipped {} urls disallowed by robots.txt", disallowed);
+        }
+        pages
     }
-
 }
 
 
@@ -128,31 +303,6 @@ pub async fn parse_file(filename: &str) -> io::Result<Vec<String>> {
     Ok(lines)
 }
 
-pub fn remove_duplicates_with_tolerance<T: Eq + std::hash::Hash + Clone + Similar>(
-    vec: Vec<T>,
-    tolerance: u32,
-) -> Vec<T> {
-    let mut set: HashSet<T> = HashSet::new();
-    let mut vec_without_duplicates = Vec::new();
-
-    for item in vec {
-        let mut is_duplicate = false;
-        for existing in &set {
-            if existing.is_similar_to(&item, tolerance) {
-                is_duplicate = true;
-                break;
-            }
-        }
-        if !is_duplicate {
-            set.insert(item.clone()); 
-            vec_without_duplicates.push(item);
-        }
-    }
-
-    vec_without_duplicates
-}
-
-
 pub fn parse(body: &String) -> CrawlLink {
     if body.is_empty() {
         return CrawlLink {
@@ -174,14 +324,29 @@ pub fn parse(body: &String) -> CrawlLink {
 
     // Helper function to determine if the link is internal (endpoint)
     let is_internal = |link: &str| -> bool {
+        // Protocol-relative //host/path points off-site like a full url does
+        if link.starts_with("//") {
+            return false;
+        }
         // Internal if it starts with /, ./, ../, or has no protocol (no http:// or https://)
         link.starts_with("/") || link.starts_with("./") || link.starts_with("../") || !(link.starts_with("http://") || link.starts_with("https://"))
     };
 
+    // mailto:, javascript:, tel:, data: and bare #fragments are nothing the crawler can request
+    let scheme = Regex::new(r"^[A-Za-z][A-Za-z0-9+.-]*:").unwrap();
+    let navigable = |link: &String| -> bool {
+        let link = link.trim();
+        let lower = link.to_lowercase();
+        !link.is_empty()
+            && !link.starts_with('#')
+            && (!scheme.is_match(link) || lower.starts_with("http://") || lower.starts_with("https://"))
+    };
+
     // Collect href attributes from <a> and <link> tags (internal or external)
     document
         .select(&a_selector)
         .filter_map(|element| element.value().attr("href").map(String::from))
+        .filter(navigable)
         .for_each(|link| {
             if is_internal(&link) {
                 inner_links.push(link);
@@ -193,6 +358,7 @@ pub fn parse(body: &String) -> CrawlLink {
     document
         .select(&link_selector)
         .filter_map(|element| element.value().attr("href").map(String::from))
+        .filter(navigable)
         .for_each(|link| {
             if is_internal(&link) {
                 inner_links.push(link);
@@ -205,6 +371,7 @@ pub fn parse(body: &String) -> CrawlLink {
     document
         .select(&form_selector)
         .filter_map(|element| element.value().attr("action").map(String::from))
+        .filter(navigable)
         .for_each(|link| {
             if is_internal(&link) {
                 inner_links.push(link);
@@ -217,6 +384,7 @@ pub fn parse(body: &String) -> CrawlLink {
     document
         .select(&script_selector)
         .filter_map(|element| element.value().attr("src").map(String::from))
+        .filter(navigable)
         .for_each(|link| {
             if is_internal(&link) {
                 inner_links.push(link);
@@ -230,4 +398,52 @@ pub fn parse(body: &String) -> CrawlLink {
         inner: inner_links,
         outer: outer_links,
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn canon(url: &str) -> String {
+        canonical(&Url::parse(url).unwrap())
+    }
+
+    #[test]
+    fn canonical_spellings_collapse() {
+        assert_eq!(canon("HTTPS://Target.Example:443//a///b?z=1&a=2#top"), "https://target.example/a/b?a=2&z=1");
+        assert_eq!(canon("http://target.example/a?"), "http://target.example/a");
+        assert_eq!(canon("http://target.example/a?b=2&a=1"), canon("http://target.example/a?a=1&b=2"));
+        assert_ne!(canon("http://target.example/a/"), canon("http://target.example/a"));
+        assert_ne!(canon("http://target.example/a?a=1"), canon("http://target.example/a?a=2"));
+    }
+
+    #[test]
+    fn links_resolve_against_the_page() {
+        let page = Url::parse("https://target.example/shop/list.php").unwrap();
+        let body = r#"<a href="item.php?id=1">x</a><a href="/about">x</a><a href="../up">x</a>
+            <form action="search.php"></form><script src="/js/app.js"></script><link href="style.css">
+            <a href="https://other.example/">x</a><a href="//cdn.example/lib.js">x</a>"#;
+        let found: Vec<String> = links(&page, body).iter().map(Url::to_string).collect();
+        for expected in [
+            "https://target.example/shop/item.php?id=1",
+            "https://target.example/about",
+            "https://target.example/up",
+            "https://target.example/shop/search.php",
+            "https://target.example/js/app.js",
+            "https://target.example/shop/style.css",
+            "https://other.example/",
+            "https://cdn.example/lib.js",
+        ] {
+            assert!(found.contains(&expected.to_string()), "{} missing from {:?}", expected, found);
+        }
+        assert_eq!(found.len(), 8);
+    }
+
+    #[test]
+    fn links_follow_base_href_and_skip_other_schemes() {
+        let page = Url::parse("https://target.example/a/b").unwrap();
+        let body = r#"<base href="/static/"><a href="x">x</a><a href="mailto:a@b">x</a><a href="javascript:void(0)">x</a>
+            <a href="#top">x</a><a href="tel:123">x</a><a href="data:text/html,hi">x</a>"#;
+        let found: Vec<String> = links(&page, body).iter().map(Url::to_string).collect();
+        assert_eq!(found, vec!["https://target.example/static/x".to_string()]);
+    }
+}
@@ -0,0 +1,286 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, Mutex, OnceLock};
+
+use rand::Rng;
+use regex::Regex;
+use tokio::sync::OnceCell;
+use url::Url;
+
+use crate::sender::client::{client, HttpResponse};
+
+// Random paths requested per baseline, two are enough to see which parts of a not-found page move
+const PROBES: usize = 2;
+
+// Simhash bits two bodies may differ in and still be the same page, out of 64
+const MAX_DISTANCE: u32 = 6;
+
+// Headers whose values or presence change from one response to the next
+const VOLATILE: &[&str] = &[
+    "date", "expires", "age", "etag", "last-modified", "set-cookie", "content-length", "x-request-id",
+    "x-correlation-id", "x-amzn-requestid", "x-amz-cf-id", "cf-ray", "x-runtime", "server-timing", "report-to", "nel",
+];
+
+// What a response looks like once the parts that change between two requests for the same thing are gone
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fingerprint {
+    pub status: u16,
+    // Hash of the normalised body
+    pub hash: u64,
+    // Simhash of the normalised body's words, near-identical pages land a few bits apart
+    pub simhash: u64,
+    // Stable header names, the content type and where a redirect ended up
+    pub headers: String,
+}
+
+// Lowercased body without the requested path echoed back, numbers, tokens and whitespace runs
+fn normalise(body: &str, path: &str) -> String {
+    static NOISE: OnceLock<Regex> = OnceLock::new();
+    static DIGITS: OnceLock<Regex> = OnceLock::new();
+    let mut text = body.to_lowercase();
+
+    let path = path.to_lowercase();
+    let decoded = urlencoding::decode(&path).map(|p| p.to_string()).unwrap_or_else(|_| path.clone());
+    let segment = decoded.rsplit('/').find(|s| !s.is_empty()).unwrap_or_default().to_string();
+    let stem = segment.split('.').next().unwrap_or_default().to_string();
+    for variant in [path.clone(), decoded.clone(), segment, stem] {
+        if variant.len() >= 3 {
+            text = text.replace(&variant, " ");
+        }
+    }
+
+    // CSRF tokens, nonces, session and request ids, then timestamps and counters
+    let noise = NOISE.get_or_init(|| Regex::new(r"[0-9a-f]{16,}|[a-z0-9+/_-]{32,}={0,2}").unwrap());
+    let digits = DIGITS.get_or_init(|| Regex::new(r"[0-9]+").unwrap());
+    let text = noise.replace_all(&text, " ");
+    let text = digits.replace_all(&text, "0");
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+fn hash64<T: Hash + ?Sized>(value: &T) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+fn simhash(text: &str) -> u64 {
+    let mut weights = [0i32; 64];
+    for token in text.split(|c: char| !c.is_alphanumeric()).filter(|token| !token.is_empty()) {
+        let hash = hash64(token);
+        for (bit, weight) in weights.iter_mut().enumerate() {
+            *weight += if (hash >> bit) & 1 == 1 { 1 } else { -1 };
+        }
+    }
+    weights
+        .iter()
+        .enumerate()
+        .filter(|(_, weight)| **weight > 0)
+        .fold(0, |simhash, (bit, _)| simhash | (1 << bit))
+}
+
+fn words(text: &str) -> HashMap<&str, usize> {
+    let mut counts = HashMap::new();
+    for token in text.split(|c: char| !c.is_alphanumeric()).filter(|token| !token.is_empty()) {
+        *counts.entry(token).or_insert(0) += 1;
+    }
+    counts
+}
+
+// Share of words two bodies have in common once normalised, 1.0 for the same page. Unlike the length it
+// tells apart two pages that say different things in as many bytes
+pub fn similarity(a: &str, b: &str) -> f64 {
+    let (a, b) = (normalise(a, ""), normalise(b, ""));
+    if a == b {
+        return 1.0;
+    }
+    let (a, b) = (words(&a), words(&b));
+    let common: usize = a.iter().map(|(word, count)| (*count).min(*b.get(word).unwrap_or(&0))).sum();
+    let total = a.values().sum::<usize>() + b.values().sum::<usize>() - common;
+    if total == 0 {
+        return 1.0;
+    }
+    common as f64 / total as f64
+}
+
+impl Fingerprint {
+    // requested is what was asked for, response.url where redirects ended
+    pub fn new(requested: &Url, response: &HttpResponse) -> Self {
+        let path = requested.path();
+        let body = normalise(&response.body, path);
+
+        let mut names: Vec<String> = response
+            .headers
+            .iter()
+            .map(|(name, _)| name.to_lowercase())
+            .filter(|name| !VOLATILE.contains(&name.as_str()))
+            .collect();
+        names.sort();
+        names.dedup();
+        let content_type = response.header("content-type").unwrap_or_default().split(';').next().unwrap_or_default().trim().to_lowercase();
+        let landed = match Url::parse(&response.url) {
+            Ok(url) if url.path() != path => normalise(url.path(), path),
+            _ => String::new(),
+        };
+
+        Fingerprint {
+            status: response.status,
+            hash: hash64(&body),
+            simhash: simhash(&body),
+            headers: format!("{}|{}|{}", names.join(","), content_type, landed),
+        }
+    }
+
+    pub fn matches(&self, other: &Fingerprint) -> bool {
+        self.status == other.status
+            && self.headers == other.headers
+            && (self.hash == other.hash || (self.simhash ^ other.simhash).count_ones() <= MAX_DISTANCE)
+    }
+}
+
+// Not-found baselines learnt so far, one per origin, directory and extension since servers often answer
+// /x.php, /x/ and /api/x differently
+pub struct NotFound {
+    baselines: Mutex<HashMap<String, Arc<OnceCell<Vec<Fingerprint>>>>>,
+}
+
+static NOT_FOUND: OnceLock<NotFound> = OnceLock::new();
+
+pub fn not_found() -> &'static NotFound {
+    NOT_FOUND.get_or_init(|| NotFound { baselines: Mutex::new(HashMap::new()) })
+}
+
+// Directory and extension of url, what a random probe has to share with it. A trailing slash counts as
+// an extension of its own, /admin/ and /admin answer differently
+fn shape(url: &Url) -> (String, String) {
+    let path = url.path();
+    let trimmed = path.strip_suffix('/').filter(|trimmed| !trimmed.is_empty());
+    let (directory, name) = trimmed.unwrap_or(path).rsplit_once('/').unwrap_or(("", path));
+    let extension = match (trimmed, name.rsplit_once('.')) {
+        (Some(_), _) => "/".to_string(),
+        (None, Some((stem, extension))) if !stem.is_empty() => format!(".{}", extension),
+        _ => String::new(),
+    };
+    (format!("{}/", directory), extension)
+}
+
+impl NotFound {
+    async fn baseline(&self, url: &Url) -> Vec<Fingerprint> {
+        let (directory, extension) = shape(url);
+        let key = format!("{}{}*{}", url.origin().ascii_serialization(), directory, extension);
+        let cell = self.baselines.lock().unwrap().entry(key).or_default().clone();
+        cell.get_or_init(|| async {
+            let mut fingerprints = vec![];
+            for _ in 0..PROBES {
+                let token = format!("krk{:012x}", rand::thread_rng().gen::<u64>() & 0xffff_ffff_ffff);
+                let path = match extension.as_str() {
+                    "/" => format!("{}{}/", directory, token),
+                    extension => format!("{}{}{}", directory, token, extension),
+                };
+                let Ok(probe) = url.join(&path) else { continue };
+                if let Ok(response) = client().get(probe.as_str()).await {
+                    fingerprints.push(Fingerprint::new(&probe, &response));
+                }
+            }
+            fingerprints
+        })
+        .await
+        .clone()
+    }
+
+    // Whether response, what requested answered, is a real resource rather than the server's way of saying
+    // there is none: a 404 or 410, or a page that looks like what random paths next to it get
+    pub async fn exists(&self, requested: &Url, response: &HttpResponse) -> bool {
+        if matches!(response.status, 404 | 410) {
+            return false;
+        }
+        let fingerprint = Fingerprint::new(requested, response);
+        !self.baseline(requested).await.iter().any(|baseline| baseline.matches(&fingerprint))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn response(url: &str, status: u16, headers: &[(&str, &str)], body: &str) -> HttpResponse {
+        HttpResponse {
+            url: url.to_string(),
+            status,
+            headers: headers.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect(),
+            body: body.to_string(),
+            elapsed: Duration::from_millis(10),
+        }
+    }
+
+    fn fingerprint(url: &str, status: u16, headers: &[(&str, &str)], body: &str) -> Fingerprint {
+        Fingerprint::new(&Url::parse(url).unwrap(), &response(url, status, headers, body))
+    }
+
+    #[test]
+    fn echoed_path_and_volatile_headers_are_ignored() {
+        let a = fingerprint(
+            "http://example.com/krk1a2b3c",
+            200,
+            &[("Content-Type", "text/html; charset=utf-8"), ("Date", "Mon, 01 Jan 2024 00:00:00 GMT"), ("Content-Length", "48")],
+            "<p>The page /krk1a2b3c was not found</p>",
+        );
+        let b = fingerprint(
+            "http://example.com/backup-old",
+            200,
+            &[("content-type", "text/html"), ("date", "Tue, 02 Jan 2024 10:00:00 GMT"), ("content-length", "49"), ("Set-Cookie", "s=1")],
+            "<p>The page /backup-old was not found</p>",
+        );
+        assert!(a.matches(&b));
+    }
+
+    #[test]
+    fn status_and_stable_headers_tell_pages_apart() {
+        let page = fingerprint("http://example.com/a", 200, &[("content-type", "text/html")], "<p>hello</p>");
+        assert!(!page.matches(&fingerprint("http://example.com/a", 403, &[("content-type", "text/html")], "<p>hello</p>")));
+        assert!(!page.matches(&fingerprint("http://example.com/a", 200, &[("content-type", "application/json")], "<p>hello</p>")));
+        assert!(!page.matches(&fingerprint(
+            "http://example.com/a",
+            200,
+            &[("content-type", "text/html"), ("x-frame-options", "DENY")],
+            "<p>hello</p>"
+        )));
+    }
+
+    #[test]
+    fn redirects_compare_where_they_landed() {
+        let requested = Url::parse("http://example.com/secret").unwrap();
+        let login = Fingerprint::new(&requested, &response("http://example.com/login", 200, &[], "<form>sign in</form>"));
+        let home = Fingerprint::new(&requested, &response("http://example.com/home", 200, &[], "<form>sign in</form>"));
+        let other = Url::parse("http://example.com/other").unwrap();
+        assert!(login.matches(&Fingerprint::new(&other, &response("http://example.com/login", 200, &[], "<form>sign in</form>"))));
+        assert!(!login.matches(&home));
+    }
+
+    #[test]
+    fn shape_is_directory_and_extension() {
+        let shape_of = |url: &str| shape(&Url::parse(url).unwrap());
+        assert_eq!(shape_of("http://example.com/a/b.php"), ("/a/".to_string(), ".php".to_string()));
+        assert_eq!(shape_of("http://example.com/admin/"), ("/".to_string(), "/".to_string()));
+        assert_eq!(shape_of("http://example.com/api/users"), ("/api/".to_string(), String::new()));
+        assert_eq!(shape_of("http://example.com/.htaccess"), ("/".to_string(), String::new()));
+        assert_eq!(shape_of("http://example.com/"), ("/".to_string(), String::new()));
+    }
+
+    #[test]
+    fn similarity_ignores_numbers_and_tokens() {
+        let a = "<p>Welcome back, 3 items in your cart</p><input name=csrf value=9f86d081884c7d659a2feaa0c55ad015>";
+        let b = "<p>Welcome back, 17 items in your cart</p><input name=csrf value=2c26b46b68ffc68ff99b453c1d304134>";
+        assert_eq!(similarity(a, b), 1.0);
+    }
+
+    #[test]
+    fn similarity_tells_apart_pages_of_the_same_length() {
+        let found = "<h1>Results</h1><p>one product matches your search</p>";
+        let empty = "<h1>Results</h1><p>nothing at all matched the search</p>";
+        assert!(similarity(found, empty) < 0.7);
+        assert_eq!(similarity("", ""), 1.0);
+    }
+}
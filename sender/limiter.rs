@@ -0,0 +1,210 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use rand::Rng;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::RATE;
+
+// Concrete numbers behind a RATE
+#[derive(Debug, Clone)]
+pub struct RateProfile {
+    pub per_host_rps: u32,
+    pub max_in_flight: usize,
+    pub jitter_ms: u64,
+    pub timeout: Duration,
+    pub connect_timeout: Duration,
+}
+
+pub fn profile(rate: &RATE) -> RateProfile {
+    match rate {
+        RATE::BLAZE => RateProfile {
+            per_host_rps: 200,
+            max_in_flight: 200,
+            jitter_ms: 0,
+            timeout: Duration::from_secs(5),
+            connect_timeout: Duration::from_secs(3),
+        },
+        RATE::FAST => RateProfile {
+            per_host_rps: 50,
+            max_in_flight: 50,
+            jitter_ms: 50,
+            timeout: Duration::from_secs(8),
+            connect_timeout: Duration::from_secs(4),
+        },
+        RATE::MODERATE => RateProfile {
+            per_host_rps: 10,
+            max_in_flight: 10,
+            jitter_ms: 150,
+            timeout: Duration::from_secs(10),
+            connect_timeout: Duration::from_secs(5),
+        },
+        RATE::SLOW => RateProfile {
+            per_host_rps: 2,
+            max_in_flight: 2,
+            jitter_ms: 500,
+            timeout: Duration::from_secs(15),
+            connect_timeout: Duration::from_secs(8),
+        },
+        RATE::CUSTOM(rps, in_flight) => RateProfile {
+            per_host_rps: (*rps).max(1),
+            max_in_flight: (*in_flight).max(1),
+            jitter_ms: 0,
+            timeout: Duration::from_secs(10),
+            connect_timeout: Duration::from_secs(5),
+        },
+    }
+}
+
+struct Bucket {
+    tokens: f64,
+    last: Instant,
+}
+
+pub struct Limiter {
+    pub profile: RateProfile,
+    in_flight: Arc<Semaphore>,
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+static LIMITER: OnceLock<Limiter> = OnceLock::new();
+
+// Set the scanner-wide profile, only the first call wins
+pub fn install(rate: &RATE) {
+    let _ = LIMITER.set(Limiter::new(profile(rate)));
+}
+
+pub fn limiter() -> &'static Limiter {
+    LIMITER.get_or_init(|| Limiter::new(profile(&RATE::MODERATE)))
+}
+
+impl Limiter {
+    pub fn new(profile: RateProfile) -> Self {
+        Limiter {
+            in_flight: Arc::new(Semaphore::new(profile.max_in_flight)),
+            profile,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Time until the host's bucket has a token, taking it when there is one
+    fn take_token(&self, host: &str) -> Duration {
+        let rate = self.profile.per_host_rps as f64;
+        let mut buckets = self.buckets.lock().unwrap();
+        let bucket = buckets.entry(host.to_string()).or_insert(Bucket {
+            tokens: rate,
+            last: Instant::now(),
+        });
+
+        let now = Instant::now();
+        bucket.tokens = (bucket.tokens + now.duration_since(bucket.last).as_secs_f64() * rate).min(rate);
+        bucket.last = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64((1.0 - bucket.tokens) / rate)
+        }
+    }
+
+    // Wait for a token for host and the profile's jitter, without taking a connection slot. The port
+    // scanners bound their own probes with --concurrency and only need the per-host pace
+    pub async fn pace(&self, host: &str) {
+        loop {
+            let wait = self.take_token(host);
+            if wait.is_zero() {
+                break;
+            }
+            tokio::time::sleep(wait).await;
+        }
+
+        if self.profile.jitter_ms > 0 {
+            let jitter = rand::thread_rng().gen_range(0..=self.profile.jitter_ms);
+            tokio::time::sleep(Duration::from_millis(jitter)).await;
+        }
+    }
+
+    // Wait for a connection slot and a token for host, hold the permit until the request is done
+    pub async fn acquire(&self, host: &str) -> OwnedSemaphorePermit {
+        let permit = self.in_flight.clone().acquire_owned().await.unwrap();
+        self.pace(host).await;
+        permit
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn custom(rps: u32, in_flight: usize) -> Limiter {
+        Limiter::new(profile(&RATE::CUSTOM(rps, in_flight)))
+    }
+
+    #[test]
+    fn profiles_follow_the_rate() {
+        let rates = [RATE::BLAZE, RATE::FAST, RATE::MODERATE, RATE::SLOW];
+        let rps: Vec<u32> = rates.iter().map(|rate| profile(rate).per_host_rps).collect();
+        assert_eq!(rps, vec![200, 50, 10, 2]);
+        let jitter: Vec<u64> = rates.iter().map(|rate| profile(rate).jitter_ms).collect();
+        assert_eq!(jitter, vec![0, 50, 150, 500]);
+        assert!(profile(&RATE::SLOW).timeout > profile(&RATE::BLAZE).timeout);
+    }
+
+    #[test]
+    fn custom_rate_overrides_the_numbers() {
+        let custom = profile(&RATE::CUSTOM(20, 5));
+        assert_eq!(custom.per_host_rps, 20);
+        assert_eq!(custom.max_in_flight, 5);
+        assert_eq!(custom.jitter_ms, 0);
+
+        let zero = profile(&RATE::CUSTOM(0, 0));
+        assert_eq!(zero.per_host_rps, 1);
+        assert_eq!(zero.max_in_flight, 1);
+    }
+
+    #[test]
+    fn bucket_allows_a_burst_of_one_second() {
+        let limiter = custom(5, 5);
+        for _ in 0..5 {
+            assert_eq!(limiter.take_token("a.example"), Duration::ZERO);
+        }
+        let wait = limiter.take_token("a.example");
+        assert!(wait > Duration::from_millis(150) && wait <= Duration::from_millis(200), "{:?}", wait);
+
+        // Buckets are per host
+        assert_eq!(limiter.take_token("b.example"), Duration::ZERO);
+    }
+
+    #[test]
+    fn bucket_refills_with_time_up_to_the_rate() {
+        let limiter = custom(10, 10);
+        for _ in 0..10 {
+            limiter.take_token("a.example");
+        }
+        assert!(!limiter.take_token("a.example").is_zero());
+
+        // Half a second gives five tokens back
+        limiter.buckets.lock().unwrap().get_mut("a.example").unwrap().last -= Duration::from_millis(500);
+        for _ in 0..5 {
+            assert_eq!(limiter.take_token("a.example"), Duration::ZERO);
+        }
+        assert!(!limiter.take_token("a.example").is_zero());
+
+        // A long pause never stores more than one second's worth
+        limiter.buckets.lock().unwrap().get_mut("a.example").unwrap().last -= Duration::from_secs(60);
+        for _ in 0..10 {
+            assert_eq!(limiter.take_token("a.example"), Duration::ZERO);
+        }
+        assert!(!limiter.take_token("a.example").is_zero());
+    }
+
+    #[tokio::test]
+    async fn pace_does_not_hold_a_slot() {
+        let limiter = custom(100, 1);
+        let _permit = limiter.acquire("a.example").await;
+        // The only slot is taken, pacing still goes through
+        tokio::time::timeout(Duration::from_secs(1), limiter.pace("a.example")).await.unwrap();
+    }
+}
@@ -1,2 +1,10 @@
 pub mod suraw;
-pub mod crawler;
\ No newline at end of file
+pub mod crawler;
+pub mod limiter;
+pub mod response;
+pub mod client;
+pub mod proxy;
+pub mod session;
+pub mod scope;
+pub mod seeds;
+pub mod fingerprint;
\ No newline at end of file
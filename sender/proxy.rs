@@ -0,0 +1,294 @@
+use anyhow::anyhow;
+use base64::Engine;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use url::Url;
+
+// Proxy schemes the raw sender can tunnel through, reqwest takes the same ones
+pub const SCHEMES: &[&str] = &["http", "socks5", "socks5h"];
+
+// TCP connection to host:port by way of proxy. TLS, when the target wants it, goes on top of this
+pub async fn tunnel(proxy: &Url, host: &str, port: u16) -> Result<TcpStream, anyhow::Error> {
+    let address = format!(
+        "{}:{}",
+        proxy.host_str().ok_or_else(|| anyhow!("Proxy has no host"))?,
+        proxy.port_or_known_default().unwrap_or(1080)
+    );
+    let mut stream = TcpStream::connect(&address).await?;
+    match proxy.scheme() {
+        "http" => connect(&mut stream, proxy, host, port).await?,
+        "socks5" | "socks5h" => socks5(&mut stream, proxy, host, port).await?,
+        other => return Err(anyhow!("Unsupported proxy scheme '{}'", other)),
+    }
+    Ok(stream)
+}
+
+fn credentials(proxy: &Url) -> Option<(String, String)> {
+    if proxy.username().is_empty() {
+        return None;
+    }
+    Some((proxy.username().to_string(), proxy.password().unwrap_or_default().to_string()))
+}
+
+// HTTP CONNECT, plain http targets go through the tunnel too so the proxy sees every request the same way
+async fn connect(stream: &mut TcpStream, proxy: &Url, host: &str, port: u16) -> Result<(), anyhow::Error> {
+    let authority = format!("{}:{}", host, port);
+    let mut request = format!("CONNECT {a} HTTP/1.1\r\nHost: {a}\r\n", a = authority);
+    if let Some((user, password)) = credentials(proxy) {
+        let token = base64::engine::general_purpose::STANDARD.encode(format!("{}:{}", user, password));
+        request.push_str(&format!("Proxy-Authorization: Basic {}\r\n", token));
+    }
+    request.push_str("\r\n");
+    stream.write_all(request.as_bytes()).await?;
+
+    // Byte at a time so nothing the target sends after the proxy's answer gets swallowed
+    let mut head = Vec::with_capacity(256);
+    let mut byte = [0u8; 1];
+    while !head.ends_with(b"\r\n\r\n") {
+        if stream.read(&mut byte).await? == 0 {
+            return Err(anyhow!("Proxy closed the connection during CONNECT"));
+        }
+        head.push(byte[0]);
+        if head.len() > 16 * 1024 {
+            return Err(anyhow!("Proxy CONNECT response too large"));
+        }
+    }
+    let head = String::from_utf8_lossy(&head);
+    let status = head.lines().next().unwrap_or_default();
+    match status.split_whitespace().nth(1) {
+        Some(code) if code.starts_with('2') => Ok(()),
+        _ => Err(anyhow!("Proxy refused CONNECT to {}: {}", authority, status)),
+    }
+}
+
+// RFC 1928 with the username/password method from RFC 1929. The target is always sent as a domain name,
+// the proxy resolves it
+async fn socks5(stream: &mut TcpStream, proxy: &Url, host: &str, port: u16) -> Result<(), anyhow::Error> {
+    let login = credentials(proxy);
+    let greeting: &[u8] = if login.is_some() { &[5, 2, 0, 2] } else { &[5, 1, 0] };
+    stream.write_all(greeting).await?;
+
+    let mut choice = [0u8; 2];
+    stream.read_exact(&mut choice).await?;
+    match (choice, &login) {
+        ([5, 0], _) => {}
+        ([5, 2], Some((user, password))) => {
+            if user.len() > 255 || password.len() > 255 {
+                return Err(anyhow!("SOCKS5 username or password longer than 255 bytes"));
+            }
+            let mut auth = vec![1, user.len() as u8];
+            auth.extend_from_slice(user.as_bytes());
+            auth.push(password.len() as u8);
+            auth.extend_from_slice(password.as_bytes());
+            stream.write_all(&auth).await?;
+            let mut status = [0u8; 2];
+            stream.read_exact(&mut status).await?;
+            if status[1] != 0 {
+                return Err(anyhow!("SOCKS5 proxy rejected the credentials"));
+            }
+        }
+        _ => return Err(anyhow!("SOCKS5 proxy offers no usable authentication method")),
+    }
+
+    if host.len() > 255 {
+        return Err(anyhow!("Host name too long for SOCKS5"));
+    }
+    let mut request = vec![5, 1, 0, 3, host.len() as u8];
+    request.extend_from_slice(host.as_bytes());
+    request.extend_from_slice(&port.to_be_bytes());
+    stream.write_all(&request).await?;
+
+    let mut reply = [0u8; 4];
+    stream.read_exact(&mut reply).await?;
+    if reply[1] != 0 {
+        return Err(anyhow!("SOCKS5 proxy failed to connect to {}:{} (code {})", host, port, reply[1]));
+    }
+    // Bound address and port, not needed but they have to come off the stream
+    let skip = match reply[3] {
+        1 => 4 + 2,
+        4 => 16 + 2,
+        3 => {
+            let mut length = [0u8; 1];
+            stream.read_exact(&mut length).await?;
+            length[0] as usize + 2
+        }
+        other => return Err(anyhow!("SOCKS5 reply with unknown address type {}", other)),
+    };
+    let mut bound = vec![0u8; skip];
+    stream.read_exact(&mut bound).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+    use tokio::task::JoinHandle;
+
+    // Local proxy answering one connection with script, url is what tunnel() is given. The handle returns
+    // whatever the script read from the client
+    async fn proxy<F, Fut>(scheme: &str, login: &str, script: F) -> (Url, JoinHandle<Vec<u8>>)
+    where
+        F: FnOnce(TcpStream) -> Fut + Send + 'static,
+        Fut: std::future::Future<Output = Vec<u8>> + Send,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let handle = tokio::spawn(async move {
+            let (socket, _) = listener.accept().await.unwrap();
+            script(socket).await
+        });
+        (Url::parse(&format!("{}://{}127.0.0.1:{}", scheme, login, port)).unwrap(), handle)
+    }
+
+    async fn read_head(socket: &mut TcpStream) -> Vec<u8> {
+        let mut head = vec![];
+        let mut byte = [0u8; 1];
+        while !head.ends_with(b"\r\n\r\n") && socket.read(&mut byte).await.unwrap() == 1 {
+            head.push(byte[0]);
+        }
+        head
+    }
+
+    async fn read_n(socket: &mut TcpStream, n: usize) -> Vec<u8> {
+        let mut bytes = vec![0u8; n];
+        socket.read_exact(&mut bytes).await.unwrap();
+        bytes
+    }
+
+    // SOCKS5 connect request for a domain, as tunnel() writes it
+    async fn read_socks_request(socket: &mut TcpStream) -> Vec<u8> {
+        let mut request = read_n(socket, 5).await;
+        let rest = read_n(socket, request[4] as usize + 2).await;
+        request.extend(rest);
+        request
+    }
+
+    fn socks_request(host: &str, port: u16) -> Vec<u8> {
+        let mut request = vec![5, 1, 0, 3, host.len() as u8];
+        request.extend_from_slice(host.as_bytes());
+        request.extend_from_slice(&port.to_be_bytes());
+        request
+    }
+
+    #[tokio::test]
+    async fn connect_keeps_what_follows_the_proxy_answer() {
+        let (url, handle) = proxy("http", "user:secret@", |mut socket| async move {
+            let head = read_head(&mut socket).await;
+            socket.write_all(b"HTTP/1.1 200 Connection established\r\nVia: test\r\n\r\nhello").await.unwrap();
+            head
+        })
+        .await;
+        let mut stream = tunnel(&url, "target.example", 443).await.unwrap();
+        let mut greeting = [0u8; 5];
+        stream.read_exact(&mut greeting).await.unwrap();
+        assert_eq!(&greeting, b"hello");
+
+        let head = String::from_utf8(handle.await.unwrap()).unwrap();
+        assert!(head.starts_with("CONNECT target.example:443 HTTP/1.1\r\nHost: target.example:443\r\n"));
+        // base64 of user:secret
+        assert!(head.contains("Proxy-Authorization: Basic dXNlcjpzZWNyZXQ=\r\n"));
+    }
+
+    #[tokio::test]
+    async fn socks5_without_authentication() {
+        let (url, handle) = proxy("socks5", "", |mut socket| async move {
+            let mut seen = read_n(&mut socket, 3).await;
+            socket.write_all(&[5, 0]).await.unwrap();
+            seen.extend(read_socks_request(&mut socket).await);
+            socket.write_all(&[5, 0, 0, 1, 127, 0, 0, 1, 0, 80]).await.unwrap();
+            socket.write_all(b"hi").await.unwrap();
+            seen
+        })
+        .await;
+        let mut stream = tunnel(&url, "target.example", 80).await.unwrap();
+        let mut greeting = [0u8; 2];
+        stream.read_exact(&mut greeting).await.unwrap();
+        assert_eq!(&greeting, b"hi");
+
+        let mut expected = vec![5, 1, 0];
+        expected.extend(socks_request("target.example", 80));
+        assert_eq!(handle.await.unwrap(), expected);
+    }
+
+    #[tokio::test]
+    async fn socks5_with_username_and_password() {
+        let (url, handle) = proxy("socks5h", "bob:pw@", |mut socket| async move {
+            let mut seen = read_n(&mut socket, 4).await;
+            socket.write_all(&[5, 2]).await.unwrap();
+            seen.extend(read_n(&mut socket, 1 + 1 + 3 + 1 + 2).await);
+            socket.write_all(&[1, 0]).await.unwrap();
+            seen.extend(read_socks_request(&mut socket).await);
+            // Bound address given as a domain name this time
+            let mut reply = vec![5, 0, 0, 3, 5];
+            reply.extend_from_slice(b"proxy");
+            reply.extend_from_slice(&[0x1f, 0x90]);
+            reply.extend_from_slice(b"ok");
+            socket.write_all(&reply).await.unwrap();
+            seen
+        })
+        .await;
+        let mut stream = tunnel(&url, "target.example", 8080).await.unwrap();
+        let mut greeting = [0u8; 2];
+        stream.read_exact(&mut greeting).await.unwrap();
+        assert_eq!(&greeting, b"ok");
+
+        let mut expected = vec![5, 2, 0, 2, 1, 3, b'b', b'o', b'b', 2, b'p', b'w'];
+        expected.extend(socks_request("target.example", 8080));
+        assert_eq!(handle.await.unwrap(), expected);
+    }
+
+    #[tokio::test]
+    async fn refused_tunnels_are_errors() {
+        let (url, _) = proxy("http", "", |mut socket| async move {
+            read_head(&mut socket).await;
+            socket.write_all(b"HTTP/1.1 403 Forbidden\r\nContent-Length: 0\r\n\r\n").await.unwrap();
+            vec![]
+        })
+        .await;
+        let error = tunnel(&url, "target.example", 443).await.unwrap_err().to_string();
+        assert!(error.contains("403"), "{}", error);
+
+        let (url, _) = proxy("http", "", |mut socket| async move {
+            read_head(&mut socket).await;
+            socket.write_all(b"HTTP/1.1 200").await.unwrap();
+            vec![]
+        })
+        .await;
+        let error = tunnel(&url, "target.example", 443).await.unwrap_err().to_string();
+        assert!(error.contains("closed"), "{}", error);
+
+        // No acceptable method
+        let (url, _) = proxy("socks5", "", |mut socket| async move {
+            read_n(&mut socket, 3).await;
+            socket.write_all(&[5, 0xff]).await.unwrap();
+            vec![]
+        })
+        .await;
+        assert!(tunnel(&url, "target.example", 80).await.is_err());
+
+        // Bad credentials
+        let (url, _) = proxy("socks5", "bob:wrong@", |mut socket| async move {
+            read_n(&mut socket, 4).await;
+            socket.write_all(&[5, 2]).await.unwrap();
+            read_n(&mut socket, 1 + 1 + 3 + 1 + 5).await;
+            socket.write_all(&[1, 1]).await.unwrap();
+            vec![]
+        })
+        .await;
+        let error = tunnel(&url, "target.example", 80).await.unwrap_err().to_string();
+        assert!(error.contains("credentials"), "{}", error);
+
+        // Connection refused by the target, reply code 5
+        let (url, _) = proxy("socks5", "", |mut socket| async move {
+            read_n(&mut socket, 3).await;
+            socket.write_all(&[5, 0]).await.unwrap();
+            read_socks_request(&mut socket).await;
+            socket.write_all(&[5, 5, 0, 1, 0, 0, 0, 0, 0, 0]).await.unwrap();
+            vec![]
+        })
+        .await;
+        let error = tunnel(&url, "target.example", 80).await.unwrap_err().to_string();
+        assert!(error.contains("code 5"), "{}", error);
+    }
+}
@@ -0,0 +1,237 @@
+use std::fmt;
+use std::io::Read;
+use std::time::Duration;
+
+use flate2::read::{GzDecoder, ZlibDecoder, DeflateDecoder};
+
+// How long each phase of one exchange took
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Timing {
+    pub connect: Duration,
+    // From the request being written to the first response byte
+    pub ttfb: Duration,
+    pub total: Duration,
+}
+
+// One parsed HTTP/1.x response, body already de-chunked and decompressed
+#[derive(Debug, Clone, Default)]
+pub struct RawResponse {
+    pub version: String,
+    pub status: u16,
+    pub reason: String,
+    // In the order the server sent them, repeated headers stay separate entries
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+    pub timing: Timing,
+    // Body stopped at max_body, or the read timed out before the framing said it was done
+    pub truncated: bool,
+}
+
+impl RawResponse {
+    // First value of header, name is case-insensitive
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn header_all(&self, name: &str) -> Vec<&str> {
+        self.headers
+            .iter()
+            .filter(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+            .collect()
+    }
+
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.body).to_string()
+    }
+
+    pub fn is_chunked(&self) -> bool {
+        self.header_all("transfer-encoding")
+            .iter()
+            .any(|value| value.to_ascii_lowercase().contains("chunked"))
+    }
+
+    pub fn content_length(&self) -> Option<usize> {
+        self.header("content-length").and_then(|value| value.trim().parse().ok())
+    }
+}
+
+// Status line, headers, blank line and the decoded body, the shape callers used to get from read_to_end
+impl fmt::Display for RawResponse {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {} {}\r\n", self.version, self.status, self.reason)?;
+        for (name, value) in &self.headers {
+            write!(f, "{}: {}\r\n", name, value)?;
+        }
+        write!(f, "\r\n{}", self.text())
+    }
+}
+
+// Offset just past the blank line ending the head, bare LF line endings included
+pub fn head_end(buffer: &[u8]) -> Option<usize> {
+    let crlf = buffer.windows(4).position(|w| w == b"\r\n\r\n").map(|i| i + 4);
+    let lf = buffer.windows(2).position(|w| w == b"\n\n").map(|i| i + 2);
+    match (crlf, lf) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    }
+}
+
+// Status line and headers, folded continuation lines joined onto the header before them
+pub fn parse_head(head: &[u8]) -> Option<RawResponse> {
+    let head = String::from_utf8_lossy(head);
+    let mut lines = head.split('\n').map(|line| line.trim_end_matches('\r'));
+
+    let status_line = lines.next()?;
+    let mut parts = status_line.splitn(3, ' ');
+    let version = parts.next()?.to_string();
+    if !version.starts_with("HTTP/") {
+        return None;
+    }
+    let status = parts.next()?.trim().parse().ok()?;
+    let reason = parts.next().unwrap_or("").to_string();
+
+    let mut headers: Vec<(String, String)> = vec![];
+    for line in lines {
+        if line.is_empty() {
+            break;
+        }
+        if line.starts_with(' ') || line.starts_with('\t') {
+            if let Some((_, value)) = headers.last_mut() {
+                value.push(' ');
+                value.push_str(line.trim());
+            }
+            continue;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            headers.push((name.trim().to_string(), value.trim().to_string()));
+        }
+    }
+
+    Some(RawResponse {
+        version,
+        status,
+        reason,
+        headers,
+        ..Default::default()
+    })
+}
+
+// Whether the server lets us send another request on this connection
+pub fn keep_alive(response: &RawResponse) -> bool {
+    let connection = response.header_all("connection").join(",").to_ascii_lowercase();
+    // 101 hands the socket over to another protocol
+    if connection.contains("close") || response.status == 101 {
+        return false;
+    }
+    response.version == "HTTP/1.1" || connection.contains("keep-alive")
+}
+
+// Responses that never carry a body whatever their headers say
+pub fn bodiless(method: &str, status: u16) -> bool {
+    method.eq_ignore_ascii_case("HEAD") || (100..200).contains(&status) || status == 204 || status == 304
+}
+
+// Undo Content-Encoding, an unknown or broken encoding leaves the body as it came
+pub fn decode(body: Vec<u8>, encoding: Option<&str>, max_body: usize) -> (Vec<u8>, bool) {
+    let encoding = encoding.unwrap_or("").trim().to_ascii_lowercase();
+    let mut decoded = Vec::new();
+    let result = match encoding.as_str() {
+        "gzip" | "x-gzip" => GzDecoder::new(&body[..]).take(max_body as u64 + 1).read_to_end(&mut decoded),
+        // Servers disagree whether deflate means zlib-wrapped or raw
+        "deflate" => ZlibDecoder::new(&body[..])
+            .take(max_body as u64 + 1)
+            .read_to_end(&mut decoded)
+            .or_else(|_| {
+                decoded.clear();
+                DeflateDecoder::new(&body[..]).take(max_body as u64 + 1).read_to_end(&mut decoded)
+            }),
+        _ => return (body, false),
+    };
+    match result {
+        Ok(_) if decoded.len() > max_body => {
+            decoded.truncate(max_body);
+            (decoded, true)
+        }
+        Ok(_) => (decoded, false),
+        Err(_) => (body, false),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+
+    #[test]
+    fn head_with_repeated_and_folded_headers() {
+        let head = b"HTTP/1.1 302 Found\r\nLocation: /login\r\nSet-Cookie: a=1\r\nset-cookie: b=2\r\nX-Long: one\r\n\ttwo\r\n\r\n";
+        let response = parse_head(head).unwrap();
+        assert_eq!(response.version, "HTTP/1.1");
+        assert_eq!(response.status, 302);
+        assert_eq!(response.reason, "Found");
+        assert_eq!(response.header("location"), Some("/login"));
+        assert_eq!(response.header_all("Set-Cookie"), vec!["a=1", "b=2"]);
+        assert_eq!(response.header("x-long"), Some("one two"));
+    }
+
+    #[test]
+    fn head_with_bare_line_feeds_and_no_reason() {
+        let buffer = b"HTTP/1.0 204\nServer: x\n\nleftover";
+        let end = head_end(buffer).unwrap();
+        assert_eq!(&buffer[end..], b"leftover");
+        let response = parse_head(&buffer[..end]).unwrap();
+        assert_eq!(response.status, 204);
+        assert_eq!(response.reason, "");
+        assert_eq!(response.header("server"), Some("x"));
+        assert!(!keep_alive(&response));
+    }
+
+    #[test]
+    fn not_a_response() {
+        assert!(parse_head(b"<html>\r\n\r\n").is_none());
+        assert!(parse_head(b"HTTP/1.1 abc OK\r\n\r\n").is_none());
+        assert!(head_end(b"HTTP/1.1 200 OK\r\nServer: x\r\n").is_none());
+    }
+
+    #[test]
+    fn keep_alive_and_bodiless() {
+        let response = parse_head(b"HTTP/1.1 200 OK\r\nConnection: close\r\n\r\n").unwrap();
+        assert!(!keep_alive(&response));
+        let response = parse_head(b"HTTP/1.0 200 OK\r\nConnection: Keep-Alive\r\n\r\n").unwrap();
+        assert!(keep_alive(&response));
+        assert!(bodiless("HEAD", 200));
+        assert!(bodiless("GET", 304));
+        assert!(!bodiless("GET", 200));
+    }
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn decode_gzip_and_deflate() {
+        assert_eq!(decode(gzip(b"hello"), Some("gzip"), 100), (b"hello".to_vec(), false));
+        let mut zlib = flate2::write::ZlibEncoder::new(Vec::new(), Compression::default());
+        zlib.write_all(b"hello").unwrap();
+        assert_eq!(decode(zlib.finish().unwrap(), Some(" Deflate "), 100), (b"hello".to_vec(), false));
+        let mut raw = flate2::write::DeflateEncoder::new(Vec::new(), Compression::default());
+        raw.write_all(b"hello").unwrap();
+        assert_eq!(decode(raw.finish().unwrap(), Some("deflate"), 100), (b"hello".to_vec(), false));
+    }
+
+    #[test]
+    fn decode_caps_and_leaves_what_it_cannot_undo() {
+        assert_eq!(decode(gzip(&[b'a'; 64]), Some("gzip"), 10), (vec![b'a'; 10], true));
+        assert_eq!(decode(b"plain".to_vec(), None, 100), (b"plain".to_vec(), false));
+        assert_eq!(decode(b"plain".to_vec(), Some("br"), 100), (b"plain".to_vec(), false));
+        assert_eq!(decode(b"not gzip".to_vec(), Some("gzip"), 100), (b"not gzip".to_vec(), false));
+    }
+}
@@ -0,0 +1,225 @@
+use std::collections::HashSet;
+use std::net::IpAddr;
+use std::sync::{Mutex, OnceLock};
+
+use anyhow::anyhow;
+use regex::Regex;
+use url::Url;
+
+// Scope as given on the command line, Scope::new compiles it
+#[derive(Debug, Clone, Default)]
+pub struct ScopeOptions {
+    // Host globs such as *.example.com, the target host alone when empty
+    pub include_hosts: Vec<String>,
+    pub exclude_hosts: Vec<String>,
+    // Regexes on the path and query
+    pub include_paths: Vec<String>,
+    pub exclude_paths: Vec<String>,
+    // http and https when empty
+    pub schemes: Vec<String>,
+    // Any port when empty
+    pub ports: Vec<u16>,
+    // Most path segments a url may have
+    pub max_depth: Option<usize>,
+}
+
+pub struct Scope {
+    include_hosts: Vec<Regex>,
+    exclude_hosts: Vec<Regex>,
+    include_paths: Vec<Regex>,
+    exclude_paths: Vec<Regex>,
+    schemes: Vec<String>,
+    ports: Vec<u16>,
+    max_depth: Option<usize>,
+    // Out-of-scope targets already reported, so a link on every page is logged once
+    logged: Mutex<HashSet<String>>,
+}
+
+static SCOPE: OnceLock<Scope> = OnceLock::new();
+
+// Set the scanner-wide scope, only the first call wins. Without one every request is allowed
+pub fn install(scope: Scope) {
+    let _ = SCOPE.set(scope);
+}
+
+pub fn scope() -> Option<&'static Scope> {
+    SCOPE.get()
+}
+
+// *.example.com covers sub.example.com and a.b.example.com, not example.com itself
+fn glob(pattern: &str) -> Result<Regex, anyhow::Error> {
+    let pattern = regex::escape(&pattern.to_lowercase()).replace(r"\*", ".*");
+    Ok(Regex::new(&format!("^{}$", pattern))?)
+}
+
+fn regexes(patterns: &[String]) -> Result<Vec<Regex>, anyhow::Error> {
+    patterns
+        .iter()
+        .map(|pattern| Regex::new(pattern).map_err(|err| anyhow!("invalid scope regex '{}': {}", pattern, err)))
+        .collect()
+}
+
+impl Scope {
+    pub fn new(target_host: &str, options: &ScopeOptions) -> Result<Self, anyhow::Error> {
+        let include_hosts = if options.include_hosts.is_empty() {
+            vec![glob(target_host)?]
+        } else {
+            options.include_hosts.iter().map(|host| glob(host)).collect::<Result<_, _>>()?
+        };
+        let schemes = if options.schemes.is_empty() {
+            vec!["http".to_string(), "https".to_string()]
+        } else {
+            options.schemes.iter().map(|scheme| scheme.to_lowercase()).collect()
+        };
+        Ok(Scope {
+            include_hosts,
+            exclude_hosts: options.exclude_hosts.iter().map(|host| glob(host)).collect::<Result<_, _>>()?,
+            include_paths: regexes(&options.include_paths)?,
+            exclude_paths: regexes(&options.exclude_paths)?,
+            schemes,
+            ports: options.ports.clone(),
+            max_depth: options.max_depth,
+            logged: Mutex::new(HashSet::new()),
+        })
+    }
+
+    fn host(&self, host: &str) -> Result<(), String> {
+        let host = host.trim_start_matches('[').trim_end_matches(']').to_lowercase();
+        if !self.include_hosts.iter().any(|re| re.is_match(&host)) {
+            return Err(format!("host {} not included", host));
+        }
+        if self.exclude_hosts.iter().any(|re| re.is_match(&host)) {
+            return Err(format!("host {} excluded", host));
+        }
+        Ok(())
+    }
+
+    fn port(&self, port: u16) -> Result<(), String> {
+        if !self.ports.is_empty() && !self.ports.contains(&port) {
+            return Err(format!("port {} not included", port));
+        }
+        Ok(())
+    }
+
+    // Why url is out of scope, Ok when it isn't
+    pub fn url(&self, url: &Url) -> Result<(), String> {
+        if !self.schemes.iter().any(|scheme| scheme == url.scheme()) {
+            return Err(format!("scheme {} not included", url.scheme()));
+        }
+        self.host(url.host_str().unwrap_or_default())?;
+        self.port(url.port_or_known_default().unwrap_or(0))?;
+
+        let target = match url.query() {
+            Some(query) => format!("{}?{}", url.path(), query),
+            None => url.path().to_string(),
+        };
+        if !self.include_paths.is_empty() && !self.include_paths.iter().any(|re| re.is_match(&target)) {
+            return Err("path not included".to_string());
+        }
+        if self.exclude_paths.iter().any(|re| re.is_match(&target)) {
+            return Err("path excluded".to_string());
+        }
+        if let Some(max) = self.max_depth {
+            let depth = url.path_segments().map(|segments| segments.filter(|s| !s.is_empty()).count()).unwrap_or(0);
+            if depth > max {
+                return Err(format!("path depth {} over {}", depth, max));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn socket(&self, ip: IpAddr, port: u16) -> Result<(), String> {
+        self.host(&ip.to_string())?;
+        self.port(port)
+    }
+
+    // url against the scope, refusals logged once per url
+    pub fn check(&self, url: &str) -> Result<(), anyhow::Error> {
+        let parsed = Url::parse(url)?;
+        self.url(&parsed).map_err(|reason| {
+            self.log(url, &reason);
+            anyhow!("{} is out of scope: {}", url, reason)
+        })
+    }
+
+    fn log(&self, target: &str, reason: &str) {
+        if self.logged.lock().unwrap().insert(target.to_string()) {
+            eprintln!("Out of scope, not sent: {} ({})", target, reason);
+        }
+    }
+}
+
+// Gate in front of every HTTP request, out-of-scope urls are logged and refused
+pub fn check_url(url: &str) -> Result<(), anyhow::Error> {
+    scope().map_or(Ok(()), |scope| scope.check(url))
+}
+
+// Same for the port scanners, without logging since a full scan would print thousands of lines.
+// They report how many ports they skipped instead
+pub fn allows_socket(ip: IpAddr, port: u16) -> bool {
+    scope().map_or(true, |scope| scope.socket(ip, port).is_ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn allows(scope: &Scope, url: &str) -> bool {
+        scope.url(&Url::parse(url).unwrap()).is_ok()
+    }
+
+    #[test]
+    fn target_host_alone_by_default() {
+        let scope = Scope::new("target.example", &ScopeOptions::default()).unwrap();
+        assert!(allows(&scope, "https://target.example/a"));
+        assert!(allows(&scope, "http://TARGET.example:8080/"));
+        assert!(!allows(&scope, "https://api.target.example/"));
+        assert!(!allows(&scope, "https://target.example.evil.test/"));
+        assert!(!allows(&scope, "ftp://target.example/"));
+    }
+
+    #[test]
+    fn host_globs() {
+        let options = ScopeOptions {
+            include_hosts: vec!["*.target.example".to_string()],
+            exclude_hosts: vec!["admin.*".to_string()],
+            ..Default::default()
+        };
+        let scope = Scope::new("target.example", &options).unwrap();
+        assert!(allows(&scope, "https://api.target.example/"));
+        assert!(allows(&scope, "https://a.b.target.example/"));
+        assert!(!allows(&scope, "https://target.example/"));
+        assert!(!allows(&scope, "https://admin.target.example/"));
+        // Glob characters other than * are literal
+        let scope = Scope::new("a.b", &ScopeOptions { include_hosts: vec!["a.b".to_string()], ..Default::default() }).unwrap();
+        assert!(!allows(&scope, "https://axb/"));
+    }
+
+    #[test]
+    fn paths_ports_and_depth() {
+        let options = ScopeOptions {
+            include_paths: vec!["^/app".to_string()],
+            exclude_paths: vec![r"logout|\.pdf$".to_string()],
+            ports: vec![443],
+            max_depth: Some(2),
+            ..Default::default()
+        };
+        let scope = Scope::new("target.example", &options).unwrap();
+        assert!(allows(&scope, "https://target.example/app/users?id=1"));
+        assert!(allows(&scope, "https://target.example/app/users/"));
+        assert!(!allows(&scope, "https://target.example/app/users/1"));
+        assert!(!allows(&scope, "https://target.example/static/app"));
+        assert!(!allows(&scope, "https://target.example/app?do=logout"));
+        assert!(!allows(&scope, "https://target.example/app/a.pdf"));
+        assert!(!allows(&scope, "http://target.example/app"));
+    }
+
+    #[test]
+    fn sockets_and_bad_patterns() {
+        let scope = Scope::new("10.0.0.5", &ScopeOptions { ports: vec![22, 80], ..Default::default() }).unwrap();
+        assert!(scope.socket("10.0.0.5".parse().unwrap(), 22).is_ok());
+        assert!(scope.socket("10.0.0.5".parse().unwrap(), 443).is_err());
+        assert!(scope.socket("10.0.0.6".parse().unwrap(), 22).is_err());
+        assert!(Scope::new("t.example", &ScopeOptions { include_paths: vec!["(".to_string()], ..Default::default() }).is_err());
+    }
+}
@@ -0,0 +1,336 @@
+use std::collections::{HashSet, VecDeque};
+use std::io::Read;
+
+use flate2::read::GzDecoder;
+use regex::Regex;
+use url::Url;
+
+use crate::sender::client::client;
+
+// Sitemaps fetched per crawl, an index can point at thousands of them
+const MAX_SITEMAPS: usize = 20;
+// Urls taken from all sitemaps together
+const MAX_SITEMAP_URLS: usize = 1000;
+// Uncompressed size a sitemap may have, what the sitemaps protocol allows
+const MAX_SITEMAP_SIZE: u64 = 50 * 1024 * 1024;
+
+// Where a crawled url came from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SOURCE {
+    // Given on the command line
+    START,
+    // Allow or Disallow path in robots.txt
+    ROBOTS,
+    // Listed in the sitemap at this url
+    SITEMAP(String),
+    // Link on a crawled page
+    LINK,
+    // Url in a page's scripts or their source maps
+    SCRIPT,
+    // Found by forced browsing before the crawl
+    WORDLIST,
+}
+
+impl std::fmt::Display for SOURCE {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SOURCE::START => write!(f, "start url"),
+            SOURCE::ROBOTS => write!(f, "robots.txt"),
+            SOURCE::SITEMAP(sitemap) => write!(f, "sitemap {}", sitemap),
+            SOURCE::LINK => write!(f, "link"),
+            SOURCE::SCRIPT => write!(f, "a script"),
+            SOURCE::WORDLIST => write!(f, "a wordlist"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Seed {
+    pub url: Url,
+    pub source: SOURCE,
+}
+
+#[derive(Debug, Clone)]
+struct Rule {
+    allow: bool,
+    path: String,
+    re: Regex,
+}
+
+// The part of robots.txt that applies to us: the rules of the groups naming our user agent, else the * groups.
+// Sitemap lines belong to no group and are all kept
+#[derive(Debug, Clone, Default)]
+pub struct Robots {
+    rules: Vec<Rule>,
+    pub sitemaps: Vec<String>,
+}
+
+// * matches anything, a trailing $ anchors the end, everything else is a path prefix
+fn rule(allow: bool, path: &str) -> Option<Rule> {
+    let (body, anchored) = match path.strip_suffix('$') {
+        Some(body) => (body, true),
+        None => (path, false),
+    };
+    let pattern = regex::escape(body).replace(r"\*", ".*");
+    let re = Regex::new(&format!("^{}{}", pattern, if anchored { "$" } else { "" })).ok()?;
+    Some(Rule { allow, path: path.to_string(), re })
+}
+
+// RFC 9309. agent is the User-Agent we send, groups are matched on its product token
+pub fn parse_robots(body: &str, agent: &str) -> Robots {
+    let token = agent.split('/').next().unwrap_or_default().trim().to_lowercase();
+    let mut groups: Vec<(Vec<String>, Vec<Rule>)> = vec![];
+    let mut sitemaps = vec![];
+    // Consecutive User-agent lines share one group
+    let mut agents_open = false;
+
+    for line in body.lines() {
+        let line = line.split('#').next().unwrap_or_default().trim();
+        let Some((key, value)) = line.split_once(':') else { continue };
+        let key = key.trim().to_lowercase();
+        let value = value.trim();
+        match key.as_str() {
+            "user-agent" => {
+                if !agents_open {
+                    groups.push((vec![], vec![]));
+                    agents_open = true;
+                }
+                if let Some((agents, _)) = groups.last_mut() {
+                    agents.push(value.to_lowercase());
+                }
+            }
+            "allow" | "disallow" => {
+                agents_open = false;
+                // An empty Disallow allows everything, and rules before any User-agent line belong to nobody
+                let Some((_, rules)) = groups.last_mut() else { continue };
+                if value.is_empty() {
+                    continue;
+                }
+                rules.extend(rule(key == "allow", value));
+            }
+            "sitemap" if !value.is_empty() => sitemaps.push(value.to_string()),
+            _ => {}
+        }
+    }
+
+    let named = |name: &str| groups.iter().any(|(agents, _)| agents.iter().any(|a| a == name));
+    let wanted = if !token.is_empty() && named(&token) { token } else { "*".to_string() };
+    let rules = groups
+        .into_iter()
+        .filter(|(agents, _)| agents.contains(&wanted))
+        .flat_map(|(_, rules)| rules)
+        .collect();
+    Robots { rules, sitemaps }
+}
+
+impl Robots {
+    // Longest matching rule decides, Allow wins a tie. Nothing matching means allowed
+    pub fn allows(&self, url: &Url) -> bool {
+        let target = match url.query() {
+            Some(query) => format!("{}?{}", url.path(), query),
+            None => url.path().to_string(),
+        };
+        if target == "/robots.txt" {
+            return true;
+        }
+        self.rules
+            .iter()
+            .filter(|rule| rule.re.is_match(&target))
+            .max_by_key(|rule| (rule.path.len(), rule.allow))
+            .map_or(true, |rule| rule.allow)
+    }
+
+    // Rule paths that name one place rather than a pattern, worth a request of their own
+    pub fn paths(&self) -> Vec<&str> {
+        self.rules
+            .iter()
+            .map(|rule| rule.path.trim_end_matches('$'))
+            .filter(|path| path.starts_with('/') && !path.contains('*') && *path != "/")
+            .collect()
+    }
+}
+
+fn unescape(text: &str) -> String {
+    let text = text.trim();
+    let text = text
+        .strip_prefix("<![CDATA[")
+        .and_then(|inner| inner.strip_suffix("]]>"))
+        .unwrap_or(text);
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+// Page urls and nested sitemap urls of a sitemap, either a <urlset>, a <sitemapindex> or a plain text list
+pub fn parse_sitemap(body: &str) -> (Vec<String>, Vec<String>) {
+    if !body.contains('<') {
+        let urls = body
+            .lines()
+            .map(str::trim)
+            .filter(|line| line.starts_with("http://") || line.starts_with("https://"))
+            .map(String::from)
+            .collect();
+        return (urls, vec![]);
+    }
+
+    let loc = Regex::new(r"(?is)<(?:[a-z0-9]+:)?loc>(.*?)</(?:[a-z0-9]+:)?loc>").unwrap();
+    let locs: Vec<String> = loc.captures_iter(body).map(|cap| unescape(&cap[1])).filter(|l| !l.is_empty()).collect();
+    if Regex::new(r"(?i)<(?:[a-z0-9]+:)?sitemapindex[\s>]").unwrap().is_match(body) {
+        (vec![], locs)
+    } else {
+        (locs, vec![])
+    }
+}
+
+// Gzipped sitemaps are told apart by their magic bytes, .gz urls are sometimes served already decoded
+fn sitemap_text(bytes: Vec<u8>) -> Option<String> {
+    if bytes.starts_with(&[0x1f, 0x8b]) {
+        let mut text = String::new();
+        GzDecoder::new(bytes.as_slice()).take(MAX_SITEMAP_SIZE).read_to_string(&mut text).ok()?;
+        return Some(text);
+    }
+    Some(String::from_utf8_lossy(&bytes).to_string())
+}
+
+async fn fetch_robots(root: &Url) -> Robots {
+    let Ok(url) = root.join("/robots.txt") else { return Robots::default() };
+    match client().get(url.as_str()).await {
+        // Soft 404 pages come back as 200 too, a real robots.txt isn't HTML
+        Ok(response)
+            if response.status == 200
+                && !response.header("content-type").unwrap_or_default().contains("html") =>
+        {
+            parse_robots(&response.body, &client().options.user_agent)
+        }
+        _ => Robots::default(),
+    }
+}
+
+// Seeds the site gives away about itself: robots.txt paths and sitemap urls, with robots.txt parsed so the
+// crawler can obey it. Sitemaps are the ones robots.txt names plus /sitemap.xml, indexes followed
+pub async fn discover(root: &Url) -> (Vec<Seed>, Robots) {
+    let robots = fetch_robots(root).await;
+    let mut seeds: Vec<Seed> = robots
+        .paths()
+        .into_iter()
+        .filter_map(|path| root.join(path).ok())
+        .map(|url| Seed { url, source: SOURCE::ROBOTS })
+        .collect();
+
+    let mut queue: VecDeque<String> = robots.sitemaps.iter().cloned().collect();
+    if let Ok(default) = root.join("/sitemap.xml") {
+        queue.push_back(default.to_string());
+    }
+    let mut seen = HashSet::new();
+    let mut urls = 0;
+    let mut fetched = 0;
+    while let Some(sitemap) = queue.pop_front() {
+        if fetched >= MAX_SITEMAPS || urls >= MAX_SITEMAP_URLS {
+            break;
+        }
+        let Ok(sitemap_url) = root.join(&sitemap) else { continue };
+        if !seen.insert(sitemap_url.to_string()) {
+            continue;
+        }
+        fetched += 1;
+        let Ok((200, bytes)) = client().download(sitemap_url.as_str()).await else { continue };
+        let Some(text) = sitemap_text(bytes) else { continue };
+
+        let (pages, nested) = parse_sitemap(&text);
+        queue.extend(nested);
+        for page in pages.into_iter().take(MAX_SITEMAP_URLS - urls) {
+            if let Ok(url) = sitemap_url.join(&page) {
+                urls += 1;
+                seeds.push(Seed { url, source: SOURCE::SITEMAP(sitemap_url.to_string()) });
+            }
+        }
+    }
+    (seeds, robots)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROBOTS: &str = "# comment
+User-agent: *
+Disallow: /private/
+Allow: /private/open
+Disallow: /*.pdf$
+Disallow:
+
+User-agent: Korik
+User-agent: other
+Disallow: /korik-only # trailing comment
+
+Sitemap: https://target.example/sitemap_index.xml
+";
+
+    fn allows(robots: &Robots, path: &str) -> bool {
+        robots.allows(&Url::parse(&format!("https://target.example{}", path)).unwrap())
+    }
+
+    #[test]
+    fn star_group_when_not_named() {
+        let robots = parse_robots(ROBOTS, "Mozilla/5.0 (Windows NT 10.0)");
+        assert!(!allows(&robots, "/private/x"));
+        assert!(allows(&robots, "/private/open/x"));
+        assert!(!allows(&robots, "/docs/a.pdf"));
+        assert!(allows(&robots, "/docs/a.pdf?download=1"));
+        assert!(allows(&robots, "/korik-only"));
+        assert_eq!(robots.sitemaps, vec!["https://target.example/sitemap_index.xml"]);
+    }
+
+    #[test]
+    fn named_group_replaces_the_star_group() {
+        let robots = parse_robots(ROBOTS, "korik/1.0");
+        assert!(!allows(&robots, "/korik-only/page"));
+        assert!(allows(&robots, "/private/x"));
+    }
+
+    #[test]
+    fn longest_rule_wins_and_allow_wins_ties() {
+        let robots = parse_robots("User-agent: *\nDisallow: /a\nAllow: /a\nDisallow: /b/c\nAllow: /b", "x");
+        assert!(allows(&robots, "/a/x"));
+        assert!(allows(&robots, "/b/x"));
+        assert!(!allows(&robots, "/b/c/d"));
+        let everything = parse_robots("User-agent: *\nDisallow: /", "x");
+        assert!(!allows(&everything, "/anything"));
+        assert!(allows(&everything, "/robots.txt"));
+    }
+
+    #[test]
+    fn rule_paths_worth_requesting() {
+        let robots = parse_robots(ROBOTS, "x");
+        assert_eq!(robots.paths(), vec!["/private/", "/private/open"]);
+    }
+
+    #[test]
+    fn sitemap_urlset_and_index() {
+        let urlset = r#"<?xml version="1.0"?><urlset xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">
+            <url><loc>https://target.example/a?x=1&amp;y=2</loc></url>
+            <url><loc> <![CDATA[https://target.example/b]]> </loc></url></urlset>"#;
+        assert_eq!(
+            parse_sitemap(urlset),
+            (vec!["https://target.example/a?x=1&y=2".to_string(), "https://target.example/b".to_string()], vec![])
+        );
+        let index = r#"<sm:sitemapindex xmlns:sm="http://www.sitemaps.org/schemas/sitemap/0.9">
+            <sm:sitemap><sm:loc>https://target.example/s1.xml.gz</sm:loc></sm:sitemap></sm:sitemapindex>"#;
+        assert_eq!(parse_sitemap(index), (vec![], vec!["https://target.example/s1.xml.gz".to_string()]));
+    }
+
+    #[test]
+    fn sitemap_text_lists_and_gzip() {
+        let text = "https://target.example/a\n\nnot a url\nhttp://target.example/b\n";
+        assert_eq!(parse_sitemap(text).0, vec!["https://target.example/a", "http://target.example/b"]);
+
+        use flate2::write::GzEncoder;
+        use std::io::Write;
+        let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(text.as_bytes()).unwrap();
+        assert_eq!(sitemap_text(encoder.finish().unwrap()).as_deref(), Some(text));
+        assert_eq!(sitemap_text(text.as_bytes().to_vec()).as_deref(), Some(text));
+    }
+}
@@ -0,0 +1,271 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::OnceLock;
+
+use anyhow::anyhow;
+use regex::Regex;
+use tokio::sync::Mutex;
+use url::{form_urlencoded, Url};
+
+use crate::method_parser::method_parser::{get_methods, Method};
+use crate::sender::client::client;
+
+// Requests between two outright session checks, logged-out responses trigger one right away
+const CHECK_EVERY: usize = 200;
+
+// Links and forms that would end the session we logged in, the crawler never follows them
+const LOGOUT: &str = r"(?i)(log[-_]?out|sign[-_]?out|log[-_]?off|sign[-_]?off|end[-_]?session|destroy[-_]?session)";
+
+// Field names guessed when --login-fields isn't given
+const USER_FIELDS: &str = r"(?i)(user|login|email|mail|name|account|uid)";
+const PASS_FIELDS: &str = r"(?i)(pass|pwd|secret)";
+
+#[derive(Debug, Clone)]
+pub struct LoginOptions {
+    // Page holding the login form
+    pub url: String,
+    pub username: String,
+    pub password: String,
+    // Username and password field names, guessed from the form when None
+    pub fields: Option<(String, String)>,
+    // Regex only a logged-in page matches
+    pub marker: Option<String>,
+    // Page the marker is checked on, the target root when None
+    pub check: Option<String>,
+}
+
+pub struct Session {
+    login: LoginOptions,
+    check: String,
+    marker: Option<Regex>,
+    requests: AtomicUsize,
+    // Bumped on every successful login, lets waiting requests see someone else already logged in again
+    generation: AtomicUsize,
+    relogin: Mutex<()>,
+}
+
+static SESSION: OnceLock<Session> = OnceLock::new();
+
+pub fn session() -> Option<&'static Session> {
+    SESSION.get()
+}
+
+pub fn is_logout(url: &str) -> bool {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| Regex::new(LOGOUT).unwrap()).is_match(url)
+}
+
+// Log in through the form at login.url and keep the session alive for the rest of the run
+pub async fn install(base: &str, login: LoginOptions) -> Result<(), anyhow::Error> {
+    let marker = login.marker.as_deref().map(Regex::new).transpose()?;
+    let check = login.check.clone().unwrap_or_else(|| format!("{}/", base.trim_end_matches('/')));
+    let session = SESSION.get_or_init(|| Session {
+        login,
+        check,
+        marker,
+        requests: AtomicUsize::new(0),
+        generation: AtomicUsize::new(0),
+        relogin: Mutex::new(()),
+    });
+    session.log_in().await?;
+    if !session.valid().await {
+        return Err(anyhow!("login form submitted but {} doesn't look logged in", session.check));
+    }
+    Ok(())
+}
+
+impl Session {
+    // Form with the password field, login.fields when given, else the first field named like one
+    fn form<'a>(&self, forms: &'a [Method]) -> Option<(&'a Method, String, String)> {
+        let user_re = Regex::new(USER_FIELDS).unwrap();
+        let pass_re = Regex::new(PASS_FIELDS).unwrap();
+        forms.iter().find_map(|form| {
+            let names: Vec<&str> = form
+                .parameters
+                .iter()
+                .filter_map(|param| param.split_once('=').map(|(name, _)| name))
+                .collect();
+            let (user, pass) = match &self.login.fields {
+                Some((user, pass)) => (
+                    names.iter().copied().find(|n| *n == user.as_str())?,
+                    names.iter().copied().find(|n| *n == pass.as_str())?,
+                ),
+                None => {
+                    let pass = names.iter().copied().find(|n| pass_re.is_match(n))?;
+                    let user = names.iter().copied().find(|n| *n != pass && user_re.is_match(n))?;
+                    (user, pass)
+                }
+            };
+            Some((form, user.to_string(), pass.to_string()))
+        })
+    }
+
+    async fn log_in(&self) -> Result<(), anyhow::Error> {
+        let page = client().fetch("GET", &self.login.url, &[], None).await?;
+        let forms = get_methods(page.body.clone());
+        let (form, user, pass) = self
+            .form(&forms)
+            .ok_or_else(|| anyhow!("no login form found on {}", self.login.url))?;
+
+        // Hidden fields such as CSRF tokens go along with their current values
+        let mut body = form_urlencoded::Serializer::new(String::new());
+        for param in form.parameters.iter().filter(|param| param.as_str() != "#") {
+            let (name, value) = param.split_once('=').unwrap_or((param, ""));
+            let value = if name == user {
+                self.login.username.as_str()
+            } else if name == pass {
+                self.login.password.as_str()
+            } else {
+                value
+            };
+            body.append_pair(name, value);
+        }
+
+        let action = Url::parse(&page.url)?.join(&form.url)?;
+        let response = match form.name.as_str() {
+            "GET" => {
+                let mut action = action;
+                action.set_query(Some(&body.finish()));
+                client().fetch("GET", action.as_str(), &[], None).await?
+            }
+            method => {
+                client()
+                    .fetch(method, action.as_str(), &[("Content-Type", "application/x-www-form-urlencoded")], Some(body.finish()))
+                    .await?
+            }
+        };
+        if response.status >= 400 {
+            return Err(anyhow!("login form at {} answered {}", action, response.status));
+        }
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        Ok(())
+    }
+
+    // Logged in per the marker, or without one, the check page loads without bouncing to the login page
+    pub async fn valid(&self) -> bool {
+        let Ok(response) = client().fetch("GET", &self.check, &[], None).await else {
+            return false;
+        };
+        match &self.marker {
+            Some(marker) => marker.is_match(&response.body),
+            None => response.status < 400 && !self.points_to_login(&response.url),
+        }
+    }
+
+    fn points_to_login(&self, location: &str) -> bool {
+        match Url::parse(&self.login.url) {
+            Ok(login) if login.path() != "/" => location.contains(login.path()),
+            _ => false,
+        }
+    }
+
+    // Seen after every scanner request with the response's status and where it sent us. Logs in again
+    // when the response says the session is gone, and checks outright every CHECK_EVERY requests.
+    // True when a new login happened, so the caller can repeat its request
+    pub async fn observe(&self, status: u16, location: Option<&str>) -> bool {
+        let count = self.requests.fetch_add(1, Ordering::Relaxed) + 1;
+        let logged_out = status == 401 || location.is_some_and(|location| self.points_to_login(location));
+        if !logged_out && count % CHECK_EVERY != 0 {
+            return false;
+        }
+
+        let generation = self.generation.load(Ordering::SeqCst);
+        let _guard = self.relogin.lock().await;
+        if self.generation.load(Ordering::SeqCst) != generation {
+            return true;
+        }
+        if self.valid().await {
+            return false;
+        }
+        match self.log_in().await {
+            Ok(()) => {
+                eprintln!("Session expired, logged in again at {}", self.login.url);
+                true
+            }
+            Err(err) => {
+                eprintln!("Re-login failed: {}", err);
+                false
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn with_login(url: &str, fields: Option<(&str, &str)>) -> Session {
+        Session {
+            login: LoginOptions {
+                url: url.to_string(),
+                username: "admin".to_string(),
+                password: "secret".to_string(),
+                fields: fields.map(|(user, pass)| (user.to_string(), pass.to_string())),
+                marker: None,
+                check: None,
+            },
+            check: "https://target.example/".to_string(),
+            marker: None,
+            requests: AtomicUsize::new(0),
+            generation: AtomicUsize::new(0),
+            relogin: Mutex::new(()),
+        }
+    }
+
+    fn form(name: &str, url: &str, parameters: &[&str]) -> Method {
+        Method { name: name.to_string(), parameters: parameters.iter().map(|p| p.to_string()).collect(), url: url.to_string() }
+    }
+
+    fn forms() -> Vec<Method> {
+        vec![
+            form("GET", "/search", &["q=", "#"]),
+            form("POST", "/login", &["csrf=abc123", "password=", "email=", "remember=1"]),
+            form("POST", "/pin", &["acct=", "pin="]),
+        ]
+    }
+
+    #[test]
+    fn login_form_is_guessed_by_field_names() {
+        let session = with_login("https://target.example/login", None);
+        let forms = forms();
+        let (found, user, pass) = session.form(&forms).unwrap();
+        assert_eq!(found.url, "/login");
+        assert_eq!((user.as_str(), pass.as_str()), ("email", "password"));
+
+        // A form without a password field is no login form
+        assert!(session.form(&forms[..1]).is_none());
+    }
+
+    #[test]
+    fn login_fields_pick_the_form() {
+        let session = with_login("https://target.example/login", Some(("acct", "pin")));
+        let forms = forms();
+        let (found, user, pass) = session.form(&forms).unwrap();
+        assert_eq!(found.url, "/pin");
+        assert_eq!((user.as_str(), pass.as_str()), ("acct", "pin"));
+
+        let session = with_login("https://target.example/login", Some(("user", "pin")));
+        assert!(session.form(&forms).is_none());
+    }
+
+    #[test]
+    fn redirects_to_the_login_page() {
+        let session = with_login("https://target.example/account/login", None);
+        assert!(session.points_to_login("https://target.example/account/login?next=%2Fadmin"));
+        assert!(session.points_to_login("/account/login"));
+        assert!(!session.points_to_login("https://target.example/dashboard"));
+
+        // A login form on the root would match every url
+        let session = with_login("https://target.example/", None);
+        assert!(!session.points_to_login("https://target.example/"));
+    }
+
+    #[test]
+    fn logout_links() {
+        for url in ["/logout", "/user/sign-out", "/Auth/LogOff", "/signout?next=/", "/session/end_session", "/logout.php"] {
+            assert!(is_logout(url), "{}", url);
+        }
+        for url in ["/login", "/catalog/outdoor", "/blog", "/account/settings"] {
+            assert!(!is_logout(url), "{}", url);
+        }
+    }
+}
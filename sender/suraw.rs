@@ -1,29 +1,167 @@
 use tokio::{
-    io::{AsyncWriteExt, BufWriter, AsyncReadExt, BufReader},
+    io::{AsyncWriteExt, AsyncReadExt},
     net::TcpStream,
 };
 use tokio_rustls::{rustls, TlsConnector};
 use std::{
-    io, sync::Arc
+    collections::HashMap, io, sync::{Arc, Mutex, OnceLock}
 };
 use std::net::ToSocketAddrs;
 use tokio::io::{stdin as tokio_stdin, stdout as tokio_stdout};
-use rustls::pki_types::ServerName;
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::{DigitallySignedStruct, SignatureScheme};
 use tokio_rustls::client::TlsStream;
 use tokio::io::AsyncBufReadExt;
 use std::str;
 use anyhow::anyhow;
 use url::Url;
-use reqwest::header::HeaderMap;
 use time::Duration;
 use rustls::{ClientConfig, RootCertStore};
 use webpki_roots::TLS_SERVER_ROOTS;
+use tokio::time::timeout;
+use crate::sender::client::client;
+use crate::sender::limiter::limiter;
+use crate::sender::proxy::tunnel;
+use crate::sender::scope::check_url;
+use crate::sender::session::session;
+use crate::sender::response::{bodiless, decode, head_end, keep_alive, parse_head, RawResponse};
 
 enum Stream {
     Plain(TcpStream),
     Tls(TlsStream<TcpStream>),
 }
 
+impl Stream {
+    async fn send(&mut self, content: &[u8]) -> io::Result<()> {
+        match self {
+            Stream::Plain(s) => {
+                s.write_all(content).await?;
+                s.flush().await
+            }
+            Stream::Tls(s) => {
+                s.write_all(content).await?;
+                s.flush().await
+            }
+        }
+    }
+
+    // Append whatever arrives next to buffer, Ok(0) once the server closed. Err(TimedOut) past deadline
+    async fn fill(&mut self, buffer: &mut Vec<u8>, deadline: std::time::Instant) -> io::Result<usize> {
+        let mut chunk = [0u8; 8192];
+        let left = deadline.saturating_duration_since(std::time::Instant::now());
+        let read = match self {
+            Stream::Plain(s) => timeout(left, s.read(&mut chunk)).await,
+            Stream::Tls(s) => timeout(left, s.read(&mut chunk)).await,
+        };
+        let n = read.map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "read timed out"))??;
+        buffer.extend_from_slice(&chunk[..n]);
+        Ok(n)
+    }
+
+    // Keep filling until buffer holds want bytes, false if the server closed or the deadline passed first
+    async fn need(&mut self, buffer: &mut Vec<u8>, want: usize, deadline: std::time::Instant) -> bool {
+        while buffer.len() < want {
+            match self.fill(buffer, deadline).await {
+                Ok(0) | Err(_) => return false,
+                Ok(_) => {}
+            }
+        }
+        true
+    }
+
+    // Offset just past the next LF at or after from, reading more as needed
+    async fn line(&mut self, buffer: &mut Vec<u8>, from: usize, deadline: std::time::Instant) -> Option<usize> {
+        loop {
+            if let Some(n) = buffer[from..].iter().position(|b| *b == b'\n') {
+                return Some(from + n + 1);
+            }
+            let want = buffer.len() + 1;
+            if !self.need(buffer, want, deadline).await {
+                return None;
+            }
+        }
+    }
+}
+
+// Anything bigger than this is cut off and marked truncated
+pub const MAX_BODY: usize = 10 * 1024 * 1024;
+
+// Longest response head we wait for before giving up on the server
+const MAX_HEAD: usize = 64 * 1024;
+
+#[derive(Debug, Clone)]
+pub struct RawOptions {
+    // For the whole response, from the request being written to the last body byte
+    pub read_timeout: std::time::Duration,
+    pub max_body: usize,
+}
+
+impl Default for RawOptions {
+    fn default() -> Self {
+        RawOptions {
+            read_timeout: limiter().profile.timeout,
+            max_body: MAX_BODY,
+        }
+    }
+}
+
+// Servers drop idle keep-alive connections after a while, don't hand out ones older than this
+const IDLE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
+
+// Idle connections kept per host:port:tls
+const MAX_IDLE: usize = 8;
+
+// Keep-alive connections not in use right now, keyed by pool_key
+struct Pool {
+    idle: Mutex<HashMap<String, Vec<(Stream, std::time::Instant)>>>,
+}
+
+static POOL: OnceLock<Pool> = OnceLock::new();
+
+fn pool() -> &'static Pool {
+    POOL.get_or_init(|| Pool { idle: Mutex::new(HashMap::new()) })
+}
+
+impl Pool {
+    fn take(&self, key: &str) -> Option<Stream> {
+        let mut idle = self.idle.lock().unwrap();
+        let streams = idle.get_mut(key)?;
+        while let Some((stream, since)) = streams.pop() {
+            if since.elapsed() < IDLE_TIMEOUT {
+                return Some(stream);
+            }
+        }
+        None
+    }
+
+    fn put(&self, key: String, stream: Stream) {
+        let mut idle = self.idle.lock().unwrap();
+        let streams = idle.entry(key).or_default();
+        streams.retain(|(_, since)| since.elapsed() < IDLE_TIMEOUT);
+        if streams.len() < MAX_IDLE {
+            streams.push((stream, std::time::Instant::now()));
+        }
+    }
+}
+
+fn pool_key(parsed_url: &Url) -> String {
+    format!(
+        "{}:{}:{}",
+        parsed_url.host_str().unwrap_or_default(),
+        parsed_url.port_or_known_default().unwrap_or(80),
+        parsed_url.scheme() == "https"
+    )
+}
+
+// Idle pooled connection if there is one, else a fresh one. The bool says it was reused
+async fn checkout(parsed_url: &Url, tls_connector: Option<TlsConnector>) -> Result<(Stream, bool), anyhow::Error> {
+    match pool().take(&pool_key(parsed_url)) {
+        Some(stream) => Ok((stream, true)),
+        None => Ok((open(parsed_url, tls_connector).await?, false)),
+    }
+}
+
 
 pub fn prepare_cookie(cookies: Option<String>) -> String {
     let mut toqen = "".to_string();
@@ -39,85 +177,340 @@ pub fn prepare_cookie(cookies: Option<String>) -> String {
     toqen
 }
 
-pub async fn smuggle(
-    url: String,
-    endpoint: &str,
-    http_method: &str,
-    cookie: Option<String>,
-    payload: Option<String>,
-    tls_connector: Option<TlsConnector>, // Pass in the TLS connector to avoid recreating it
-) -> Result<(String, String), anyhow::Error> {
-    // Parse URL
-    let parsed_url = Url::parse(&url)?;
+// TCP or TLS connection to the url's host, port from the url or its scheme
+async fn open(parsed_url: &Url, tls_connector: Option<TlsConnector>) -> Result<Stream, anyhow::Error> {
     let host = parsed_url
         .host_str()
         .ok_or_else(|| anyhow!("Invalid host"))?
         .to_string();
-
-    // Determine port based on scheme
     let port = parsed_url
         .port_or_known_default()
         .unwrap_or_else(|| if parsed_url.scheme() == "https" { 443 } else { 80 });
-
     let address = format!("{}:{}", host, port);
+    let profile = &limiter().profile;
 
-    // Prepare request payload
-    let payload_value = payload.unwrap_or_default();
-    let cookie_pie = prepare_cookie(cookie);
-    let content = format!(
-        "{} {} HTTP/1.1\r\nHost: {}\r\n{}Content-Length: {}\r\nUser-Agent: Mozilla/5.0\r\n\r\n{}",
-        http_method.to_uppercase(),
-        endpoint,
-        host,
-        cookie_pie,
-        payload_value.len(),
-        payload_value
-    );
-
-    println!("Request being sent:\n{}", content);
-
-    // Handle HTTP and HTTPS connections
-    let mut stream = if parsed_url.scheme() == "https" {
-        // Reuse the provided TLS connector to avoid re-creating it every time
-        let tls_connector = tls_connector.ok_or_else(|| anyhow!("TLS connector not provided"))?;
-        let tcp_stream = TcpStream::connect(&address).await?;
+    // Through the --proxy tunnel when there is one, TLS is negotiated end to end inside it
+    let proxy = client().options.proxy.as_deref().map(Url::parse).transpose()?;
+    let tcp_stream = match &proxy {
+        Some(proxy) => timeout(profile.connect_timeout, tunnel(proxy, &host, port)).await??,
+        None => timeout(profile.connect_timeout, TcpStream::connect(&address)).await??,
+    };
+
+    if parsed_url.scheme() == "https" {
+        let tls_connector = tls_connector.unwrap_or_else(create_tls_connector);
         let domain = ServerName::try_from(host.clone())
             .map_err(|_| anyhow::anyhow!("Invalid DNS name"))?;
         let tls_stream = tls_connector.connect(domain, tcp_stream).await?;
-        Stream::Tls(tls_stream)
+        Ok(Stream::Tls(tls_stream))
     } else {
-        let tcp_stream = TcpStream::connect(&address).await?;
-        Stream::Plain(tcp_stream)
+        Ok(Stream::Plain(tcp_stream))
+    }
+}
+
+// Write content exactly as given, no Host or Content-Length added, and read until the server closes
+// or read_timeout passes. Returns the response, how long it took and whether the read timed out
+pub async fn raw(
+    url: &str,
+    content: &str,
+    read_timeout: std::time::Duration,
+    tls_connector: Option<TlsConnector>,
+) -> Result<(String, std::time::Duration, bool), anyhow::Error> {
+    check_url(url)?;
+    let parsed_url = Url::parse(url)?;
+    let host = parsed_url.host_str().unwrap_or_default().to_string();
+    let _permit = limiter().acquire(&host).await;
+    let mut stream = open(&parsed_url, tls_connector).await?;
+
+    let started = std::time::Instant::now();
+    let deadline = started + read_timeout;
+    let mut response = Vec::with_capacity(4096);
+    stream.send(content.as_bytes()).await?;
+    let timed_out = loop {
+        match stream.fill(&mut response, deadline).await {
+            Err(err) if err.kind() == io::ErrorKind::TimedOut => break true,
+            Ok(0) | Err(_) => break false,
+            Ok(_) => {}
+        }
     };
 
-    // Send the request using a buffered writer
-    match stream {
-        Stream::Plain(ref mut s) => {
-            let mut writer = BufWriter::new(s);
-            writer.write_all(content.as_bytes()).await?;
-            writer.flush().await?;
+    Ok((String::from_utf8_lossy(&response).to_string(), started.elapsed(), timed_out))
+}
+
+// Read one response off stream, framed by its own headers so keep-alive servers don't hang us.
+// buffer carries bytes already read past the previous response, whatever follows this one stays in it.
+// The bool is false when the body ran to close or was cut short, so the connection can't carry another
+async fn read_response(
+    stream: &mut Stream,
+    buffer: &mut Vec<u8>,
+    http_method: &str,
+    options: &RawOptions,
+    sent: std::time::Instant,
+) -> Result<(RawResponse, bool), anyhow::Error> {
+    let deadline = sent + options.read_timeout;
+    let mut first_byte = if buffer.is_empty() { None } else { Some(sent) };
+
+    // Interim 1xx heads (100 Continue, 103 Early Hints) come before the real one
+    let (mut response, head_len) = loop {
+        let end = loop {
+            if let Some(end) = head_end(&buffer) {
+                break end;
+            }
+            if buffer.len() > MAX_HEAD {
+                return Err(anyhow!("Response head larger than {} bytes", MAX_HEAD));
+            }
+            match stream.fill(buffer, deadline).await? {
+                0 if buffer.is_empty() => return Err(anyhow!("Connection closed without a response")),
+                0 => return Err(anyhow!("Connection closed inside the response head")),
+                _ => {
+                    first_byte.get_or_insert_with(std::time::Instant::now);
+                }
+            }
+        };
+        let response = parse_head(&buffer[..end]).ok_or_else(|| anyhow!("Malformed status line"))?;
+        if (100..200).contains(&response.status) && response.status != 101 {
+            buffer.drain(..end);
+            continue;
         }
-        Stream::Tls(ref mut s) => {
-            let mut writer = BufWriter::new(s);
-            writer.write_all(content.as_bytes()).await?;
-            writer.flush().await?;
+        break (response, end);
+    };
+    response.timing.ttfb = first_byte.map(|at| at - sent).unwrap_or_default();
+
+    let max = options.max_body;
+    let mut body = vec![];
+    let mut truncated = false;
+    // End of this response in buffer
+    let mut consumed = head_len;
+    let mut framed = true;
+    if bodiless(http_method, response.status) {
+        // nothing to read
+    } else if response.is_chunked() {
+        let mut at = head_len;
+        loop {
+            let Some(line_end) = stream.line(buffer, at, deadline).await else {
+                truncated = true;
+                break;
+            };
+            let line = String::from_utf8_lossy(&buffer[at..line_end]).to_string();
+            let size = line.split(';').next().map(str::trim).and_then(|hex| usize::from_str_radix(hex, 16).ok());
+            let Some(size) = size else {
+                truncated = true;
+                break;
+            };
+            at = line_end;
+
+            if size == 0 {
+                // Trailer fields until the blank line, kept after the real headers
+                loop {
+                    let Some(line_end) = stream.line(buffer, at, deadline).await else { break };
+                    let line = String::from_utf8_lossy(&buffer[at..line_end]).trim().to_string();
+                    at = line_end;
+                    match line.split_once(':') {
+                        Some((name, value)) => response.headers.push((name.trim().to_string(), value.trim().to_string())),
+                        None => break,
+                    }
+                }
+                break;
+            }
+
+            let take = size.min(max - body.len());
+            if !stream.need(buffer, at + take, deadline).await {
+                body.extend_from_slice(&buffer[at..buffer.len().min(at + take)]);
+                truncated = true;
+                break;
+            }
+            body.extend_from_slice(&buffer[at..at + take]);
+            if body.len() >= max {
+                truncated = true;
+                break;
+            }
+            // chunk data plus its CRLF
+            at += size;
+            stream.need(buffer, at + 2, deadline).await;
+            at = (at + 2).min(buffer.len());
+        }
+        consumed = at;
+    } else if let Some(length) = response.content_length() {
+        let want = head_len + length.min(max);
+        let complete = stream.need(buffer, want, deadline).await;
+        body.extend_from_slice(&buffer[head_len..want.min(buffer.len())]);
+        truncated = !complete || length > max;
+        consumed = want.min(buffer.len());
+    } else {
+        framed = false;
+        // No framing at all, the body runs until the server closes
+        loop {
+            if buffer.len() - head_len >= max {
+                truncated = true;
+                break;
+            }
+            match stream.fill(buffer, deadline).await {
+                Ok(0) => break,
+                Err(_) => {
+                    truncated = true;
+                    break;
+                }
+                Ok(_) => {}
+            }
         }
+        body.extend_from_slice(&buffer[head_len..buffer.len().min(head_len + max)]);
+        consumed = buffer.len();
     }
+    buffer.drain(..consumed);
+
+    let (body, cut) = decode(body, response.header("content-encoding"), max);
+    response.body = body;
+    response.truncated = truncated || cut;
+    response.timing.total = sent.elapsed();
+    Ok((response, framed && !truncated))
+}
+
+// Request line, Host, the caller's header lines and Content-Length, the way every raw request here looks
+pub fn build_request(host: &str, http_method: &str, endpoint: &str, cookie: Option<String>, payload: &str) -> String {
+    let headers = prepare_cookie(cookie);
+    // The client's header block brings its own User-Agent
+    let user_agent = if headers.to_ascii_lowercase().contains("user-agent:") { "" } else { "User-Agent: Mozilla/5.0\r\n" };
+    format!(
+        "{} {} HTTP/1.1\r\nHost: {}\r\n{}Content-Length: {}\r\n{}\r\n{}",
+        http_method.to_uppercase(),
+        endpoint,
+        host,
+        headers,
+        payload.len(),
+        user_agent,
+        payload
+    )
+}
 
-    // Read response with buffered reader
-    let mut response = Vec::with_capacity(4096); // Pre-allocate buffer
-    match stream {
-        Stream::Plain(ref mut s) => {
-            let mut reader = BufReader::new(s);
-            reader.read_to_end(&mut response).await?;
+// Write requests back to back on stream, then read their responses in order. Stops at the first
+// response that can't be read. The bool says the connection is still good for another request
+async fn exchange(
+    stream: &mut Stream,
+    requests: &[String],
+    options: &RawOptions,
+) -> (Vec<RawResponse>, bool) {
+    if stream.send(requests.concat().as_bytes()).await.is_err() {
+        return (vec![], false);
+    }
+    let sent = std::time::Instant::now();
+    let mut buffer = Vec::with_capacity(8192);
+    let mut responses = vec![];
+    for request in requests {
+        let method = request.split_whitespace().next().unwrap_or("GET");
+        match read_response(stream, &mut buffer, method, options, sent).await {
+            Ok((response, framed)) => {
+                let reusable = framed && keep_alive(&response);
+                responses.push(response);
+                if !reusable {
+                    return (responses, false);
+                }
+            }
+            Err(_) => return (responses, false),
         }
-        Stream::Tls(ref mut s) => {
-            let mut reader = BufReader::new(s);
-            reader.read_to_end(&mut response).await?;
+    }
+    // Anything left over is a response we never asked for, don't hand that to the next caller
+    (responses, buffer.is_empty())
+}
+
+// Send raw requests pipelined on one pooled connection and parse each response. Fewer responses than
+// requests means the server closed or stalled partway. A reused connection that turns out to be dead
+// gets one retry on a fresh one
+pub async fn pipeline(
+    url: &str,
+    requests: &[String],
+    options: &RawOptions,
+    tls_connector: Option<TlsConnector>,
+) -> Result<Vec<RawResponse>, anyhow::Error> {
+    check_url(url)?;
+    let parsed_url = Url::parse(url)?;
+    let host = parsed_url
+        .host_str()
+        .ok_or_else(|| anyhow!("Invalid host"))?
+        .to_string();
+    let _permit = limiter().acquire(&host).await;
+
+    let started = std::time::Instant::now();
+    let (mut stream, reused) = checkout(&parsed_url, tls_connector.clone()).await?;
+    let mut connect = started.elapsed();
+    let (mut responses, mut reusable) = exchange(&mut stream, requests, options).await;
+
+    if responses.is_empty() && reused {
+        let started = std::time::Instant::now();
+        stream = open(&parsed_url, tls_connector).await?;
+        connect = started.elapsed();
+        (responses, reusable) = exchange(&mut stream, requests, options).await;
+    }
+    if responses.is_empty() {
+        return Err(anyhow!("No response from {}", host));
+    }
+
+    for response in responses.iter_mut() {
+        response.timing.connect = connect;
+        response.timing.total += connect;
+    }
+    if reusable {
+        pool().put(pool_key(&parsed_url), stream);
+    }
+    Ok(responses)
+}
+
+// Send one request over a pooled keep-alive connection and parse what comes back. Returns the
+// request exactly as written and the response
+pub async fn send_request(
+    url: String,
+    endpoint: &str,
+    http_method: &str,
+    cookie: Option<String>,
+    payload: Option<String>,
+    options: &RawOptions,
+    tls_connector: Option<TlsConnector>,
+) -> Result<(String, RawResponse), anyhow::Error> {
+    let target = Url::parse(&url)?.join(endpoint)?;
+    check_url(target.as_str())?;
+    let host = target
+        .host_str()
+        .ok_or_else(|| anyhow!("Invalid host"))?
+        .to_string();
+    let payload = payload.unwrap_or_default();
+    // Session cookies come from the jar as the request leaves, unless the caller set its own
+    let build = || {
+        let mut headers = prepare_cookie(cookie.clone());
+        if !headers.to_ascii_lowercase().contains("cookie:") {
+            if let Some(cookies) = client().cookie_header(&url) {
+                headers.push_str(&format!("Cookie: {}\r\n", cookies));
+            }
         }
+        build_request(&host, http_method, endpoint, Some(headers), &payload)
+    };
+
+    // Cookies the target sets on raw requests go into the jar, the next request sends them
+    let store = |response: &RawResponse| client().store_cookies(target.as_str(), response.header_all("set-cookie").into_iter());
+
+    let content = build();
+    let response = pipeline(&url, std::slice::from_ref(&content), options, tls_connector.clone()).await?.remove(0);
+    store(&response);
+    match session() {
+        Some(session) if session.observe(response.status, response.header("location")).await => {
+            let content = build();
+            let response = pipeline(&url, std::slice::from_ref(&content), options, tls_connector).await?.remove(0);
+            store(&response);
+            Ok((content, response))
+        }
+        _ => Ok((content, response)),
     }
+}
 
-    Ok((content, String::from_utf8_lossy(&response).to_string()))
+// send_request with default options, the response flattened back to text
+pub async fn smuggle(
+    url: String,
+    endpoint: &str,
+    http_method: &str,
+    cookie: Option<String>,
+    payload: Option<String>,
+    tls_connector: Option<TlsConnector>, // Pass in the TLS connector to avoid recreating it
+) -> Result<(String, String), anyhow::Error> {
+    let (request, response) =
+        send_request(url, endpoint, http_method, cookie, payload, &RawOptions::default(), tls_connector).await?;
+    Ok((request, response.to_string()))
 }
 
 // Helper function to build the root certificate store
@@ -127,38 +520,267 @@ fn build_root_cert_store() -> RootCertStore {
     root_cert_store
 }
 
-// Create the TLS connector once and pass it to smuggle
+static TLS_CONNECTOR: OnceLock<TlsConnector> = OnceLock::new();
+
+// With --insecure the raw sender takes any certificate the way reqwest's danger_accept_invalid_certs
+// does, so both transports agree on a target
+#[derive(Debug)]
+struct AcceptAny;
+
+impl ServerCertVerifier for AcceptAny {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        _message: &[u8],
+        _cert: &CertificateDer<'_>,
+        _dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        Ok(HandshakeSignatureValid::assertion())
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        _message: &[u8],
+        _cert: &CertificateDer<'_>,
+        _dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        Ok(HandshakeSignatureValid::assertion())
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        vec![
+            SignatureScheme::ECDSA_NISTP256_SHA256,
+            SignatureScheme::ECDSA_NISTP384_SHA384,
+            SignatureScheme::ED25519,
+            SignatureScheme::RSA_PSS_SHA256,
+            SignatureScheme::RSA_PSS_SHA384,
+            SignatureScheme::RSA_PSS_SHA512,
+            SignatureScheme::RSA_PKCS1_SHA256,
+            SignatureScheme::RSA_PKCS1_SHA384,
+            SignatureScheme::RSA_PKCS1_SHA512,
+        ]
+    }
+}
+
+// The one TLS connector every raw connection shares, built on first use
 pub fn create_tls_connector() -> TlsConnector {
-    let config = Arc::new(
-        ClientConfig::builder()
-            .with_root_certificates(build_root_cert_store())
-            .with_no_client_auth()
-    );
-    TlsConnector::from(config)
-}
-pub async fn jiber(url: &str) -> Result<(String, HeaderMap, String), Box<reqwest::Error>> {
-    let client = reqwest::Client::builder()  .danger_accept_invalid_certs(true) .build()?;
-
-    let response = match client.get(url).header("User-Agent","Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/119.0.0.0 Safari/537.36").send().await {
-        Ok(res) => res,
-        Err(err) => {
-            //e//("Error fetching {}: {}", url, err);
-            return Err(Box::new(err));
+    TLS_CONNECTOR
+        .get_or_init(|| {
+            let config = if client().options.insecure {
+                ClientConfig::builder()
+                    .dangerous()
+                    .with_custom_certificate_verifier(Arc::new(AcceptAny))
+                    .with_no_client_auth()
+            } else {
+                ClientConfig::builder()
+                    .with_root_certificates(build_root_cert_store())
+                    .with_no_client_auth()
+            };
+            TlsConnector::from(Arc::new(config))
+        })
+        .clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    // Stream to a local server that writes reply and hangs up
+    async fn serving(reply: Vec<u8>) -> Stream {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            socket.write_all(&reply).await.unwrap();
+        });
+        Stream::Plain(TcpStream::connect(address).await.unwrap())
+    }
+
+    fn options(max_body: usize) -> RawOptions {
+        RawOptions { read_timeout: std::time::Duration::from_secs(5), max_body }
+    }
+
+    async fn read(stream: &mut Stream, buffer: &mut Vec<u8>, method: &str, max_body: usize) -> (RawResponse, bool) {
+        read_response(stream, buffer, method, &options(max_body), std::time::Instant::now()).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn content_length_leaves_the_next_response() {
+        let reply = b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhelloHTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n";
+        let mut stream = serving(reply.to_vec()).await;
+        let mut buffer = vec![];
+        let (first, reusable) = read(&mut stream, &mut buffer, "GET", MAX_BODY).await;
+        assert_eq!((first.status, first.body.as_slice(), reusable), (200, &b"hello"[..], true));
+        let (second, reusable) = read(&mut stream, &mut buffer, "GET", MAX_BODY).await;
+        assert_eq!((second.status, second.body.len(), reusable), (404, 0, true));
+        assert!(buffer.is_empty());
+    }
+
+    #[tokio::test]
+    async fn chunked_with_extensions_and_trailers() {
+        let reply = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n5;ext=1\r\nhello\r\n6\r\n world\r\n0\r\nX-Trailer: yes\r\n\r\n";
+        let mut stream = serving(reply.to_vec()).await;
+        let (response, reusable) = read(&mut stream, &mut vec![], "GET", MAX_BODY).await;
+        assert_eq!(response.text(), "hello world");
+        assert_eq!(response.header("x-trailer"), Some("yes"));
+        assert!(reusable && !response.truncated);
+    }
+
+    #[tokio::test]
+    async fn interim_responses_are_skipped() {
+        let reply = b"HTTP/1.1 100 Continue\r\n\r\nHTTP/1.1 201 Created\r\nContent-Length: 2\r\n\r\nok";
+        let mut stream = serving(reply.to_vec()).await;
+        let (response, _) = read(&mut stream, &mut vec![], "POST", MAX_BODY).await;
+        assert_eq!((response.status, response.text().as_str()), (201, "ok"));
+    }
+
+    #[tokio::test]
+    async fn unframed_body_runs_to_close() {
+        let mut stream = serving(b"HTTP/1.0 200 OK\r\n\r\nuntil close".to_vec()).await;
+        let (response, reusable) = read(&mut stream, &mut vec![], "GET", MAX_BODY).await;
+        assert_eq!(response.text(), "until close");
+        assert!(!reusable);
+    }
+
+    #[tokio::test]
+    async fn head_responses_have_no_body() {
+        let mut stream = serving(b"HTTP/1.1 200 OK\r\nContent-Length: 50\r\n\r\n".to_vec()).await;
+        let (response, reusable) = read(&mut stream, &mut vec![], "HEAD", MAX_BODY).await;
+        assert!(response.body.is_empty() && reusable);
+    }
+
+    #[tokio::test]
+    async fn bodies_past_max_body_are_truncated() {
+        let mut stream = serving(b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\n0123456789".to_vec()).await;
+        let (response, reusable) = read(&mut stream, &mut vec![], "GET", 4).await;
+        assert_eq!(response.text(), "0123");
+        assert!(response.truncated && !reusable);
+    }
+
+    #[tokio::test]
+    async fn closed_before_a_response_is_an_error() {
+        let mut stream = serving(vec![]).await;
+        assert!(read_response(&mut stream, &mut vec![], "GET", &options(MAX_BODY), std::time::Instant::now()).await.is_err());
+    }
+
+    #[test]
+    fn requests_carry_host_and_length() {
+        let request = build_request("t.example", "post", "/login", Some("Cookie: a=1".to_string()), "x=1");
+        assert!(request.starts_with("POST /login HTTP/1.1\r\nHost: t.example\r\nCookie: a=1\r\nContent-Length: 3\r\n"));
+        assert!(request.ends_with("\r\n\r\nx=1"));
+    }
+
+    // Keep-alive server on localhost answering every request with its path. Each connection serves at
+    // most per_connection requests and then hangs up, the counter says how many connections it took
+    async fn keep_alive_server(per_connection: usize) -> (String, Arc<std::sync::atomic::AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let connections = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let counter = connections.clone();
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                tokio::spawn(async move {
+                    for _ in 0..per_connection {
+                        let mut head = vec![];
+                        let mut byte = [0u8; 1];
+                        while !head.ends_with(b"\r\n\r\n") {
+                            if socket.read(&mut byte).await.unwrap_or(0) == 0 {
+                                return;
+                            }
+                            head.push(byte[0]);
+                        }
+                        let path = String::from_utf8_lossy(&head).split_whitespace().nth(1).unwrap_or("/").to_string();
+                        let reply = format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{}", path.len(), path);
+                        if socket.write_all(reply.as_bytes()).await.is_err() {
+                            return;
+                        }
+                    }
+                });
+            }
+        });
+        (url, connections)
+    }
+
+    fn get(path: &str) -> String {
+        format!("GET {} HTTP/1.1\r\nHost: 127.0.0.1\r\n\r\n", path)
+    }
+
+    #[tokio::test]
+    async fn keep_alive_connections_are_reused() {
+        let (url, connections) = keep_alive_server(usize::MAX).await;
+        let first = pipeline(&url, &[get("/a")], &options(MAX_BODY), None).await.unwrap();
+        let second = pipeline(&url, &[get("/b"), get("/c")], &options(MAX_BODY), None).await.unwrap();
+        assert_eq!(first[0].text(), "/a");
+        let bodies: Vec<String> = second.iter().map(|response| response.text()).collect();
+        assert_eq!(bodies, vec!["/b", "/c"]);
+        assert_eq!(connections.load(std::sync::atomic::Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn stale_pooled_connection_is_retried_once() {
+        // The server closes every connection after one response without saying so
+        let (url, connections) = keep_alive_server(1).await;
+        let first = pipeline(&url, &[get("/a")], &options(MAX_BODY), None).await.unwrap();
+        assert_eq!(first[0].text(), "/a");
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+        let second = pipeline(&url, &[get("/b")], &options(MAX_BODY), None).await.unwrap();
+        assert_eq!(second[0].text(), "/b");
+        assert_eq!(connections.load(std::sync::atomic::Ordering::SeqCst), 2);
+    }
+
+    // Connections to a listener nobody reads from, enough to fill a pool
+    async fn idle_streams(n: usize) -> (TcpListener, Vec<Stream>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let mut streams = vec![];
+        for _ in 0..n {
+            streams.push(Stream::Plain(TcpStream::connect(address).await.unwrap()));
         }
-    };
+        (listener, streams)
+    }
 
-    let headers = response.headers().clone();
-    let status = response.status();
-    // let setter = response.headers();
-    ////("{:?}",setter["set-cookie"]);
+    #[tokio::test]
+    async fn pool_keeps_at_most_max_idle() {
+        let pool = Pool { idle: Mutex::new(HashMap::new()) };
+        let (_listener, streams) = idle_streams(MAX_IDLE + 2).await;
+        for stream in streams {
+            pool.put("h:80:false".to_string(), stream);
+        }
+        assert_eq!(pool.idle.lock().unwrap()["h:80:false"].len(), MAX_IDLE);
+        for _ in 0..MAX_IDLE {
+            assert!(pool.take("h:80:false").is_some());
+        }
+        assert!(pool.take("h:80:false").is_none());
+        assert!(pool.take("other:80:false").is_none());
+    }
 
-    let body = response.text().await?;
+    #[tokio::test]
+    async fn pool_drops_connections_idle_too_long() {
+        let pool = Pool { idle: Mutex::new(HashMap::new()) };
+        let (_listener, mut streams) = idle_streams(2).await;
+        let stale = std::time::Instant::now() - IDLE_TIMEOUT - std::time::Duration::from_secs(1);
+        pool.idle.lock().unwrap().insert("h:80:false".to_string(), vec![(streams.pop().unwrap(), stale)]);
+        assert!(pool.take("h:80:false").is_none());
 
-    /*let headers_str = headers.clone()
-        .into_iter()
-        .map(|(name, value)| format!("{:?}: {:?}", name, value))?;
-        .collect::<Vec<_>>()
-        .join("\n");
-    */
-    Ok((body, headers, status.to_string()))
+        // Putting a fresh one clears out the stale ones first
+        pool.idle.lock().unwrap().insert("h:80:false".to_string(), vec![]);
+        let (_other, mut more) = idle_streams(1).await;
+        pool.idle.lock().unwrap().get_mut("h:80:false").unwrap().push((more.pop().unwrap(), stale));
+        pool.put("h:80:false".to_string(), streams.pop().unwrap());
+        assert_eq!(pool.idle.lock().unwrap()["h:80:false"].len(), 1);
+        assert!(pool.take("h:80:false").is_some());
+    }
 }
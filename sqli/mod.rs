@@ -1 +1,6 @@
-pub mod sqli;
\ No newline at end of file
+pub mod sqli;
+pub mod union;
+pub mod blind;
+pub mod xml;
+pub mod error;
+pub mod extract;
\ No newline at end of file
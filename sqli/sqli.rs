@@ -1,16 +1,409 @@
+use std::time::Duration;
+
+use futures::stream::{self, StreamExt};
+use regex::Regex;
+use serde_json::Value;
+use tokio_rustls::TlsConnector;
+use url::Url;
+
 use crate::SQL;
 use crate::RATE;
+use crate::CUSTOMER;
+use crate::payload_builder::payload_builder::{json_sikkish, make_payloads_post, make_payloads_url, xml_sikkish_each, XML_ELEMENT};
+use crate::report::report::Finding;
+use crate::sender::crawler::Surface;
+use crate::sender::fingerprint::similarity;
+use crate::sender::limiter::limiter;
+use crate::sender::suraw::{create_tls_connector, send_request, RawOptions};
+use crate::sqli::{blind, error, union, xml};
+use crate::sqli::error::DBMS;
+
+// Appended to every parameter once to learn which parameters payload_builder can reach
+const PROBE: &str = "krkprobe";
 
 pub struct sql_scanner<'a>{
     pub technique: SQL,
-    pub depth: &'a RATE
+    pub depth: &'a RATE,
+    pub target: &'a CUSTOMER,
+    pub surface: &'a Surface,
+    pub headers: Option<String>,
+    // Row limit for data extraction on confirmed points, None unless --extract was given
+    pub extract: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PLACE {
+    GET,
+    POST,
+    JSON,
+    XML,
+}
+
+// One parameter of one request that payloads get appended to
+#[derive(Debug, Clone)]
+pub struct InjectionPoint {
+    pub url: String,
+    pub method: String,
+    pub place: PLACE,
+    pub params: String,
+    pub name: String,
+    index: usize,
+}
+
+impl InjectionPoint {
+    // Url and body of the request with payload appended to this point's parameter
+    pub fn inject(&self, payload: &str) -> (String, String) {
+        match self.place {
+            PLACE::GET => (
+                make_payloads_url(&self.url, payload)
+                    .get(self.index)
+                    .cloned()
+                    .unwrap_or_else(|| self.url.clone()),
+                String::new(),
+            ),
+            PLACE::POST => (
+                self.url.clone(),
+                make_payloads_post(&self.params, payload)
+                    .get(self.index)
+                    .cloned()
+                    .unwrap_or_else(|| self.params.clone()),
+            ),
+            PLACE::JSON => (
+                self.url.clone(),
+                serde_json::from_str::<Value>(&self.params)
+                    .ok()
+                    .and_then(|body| {
+                        let original = match body.get(&self.name) {
+                            Some(Value::String(value)) => value.clone(),
+                            Some(Value::Null) | None => String::new(),
+                            Some(value) => value.to_string(),
+                        };
+                        json_sikkish(body, &format!("{}{}", original, payload))
+                            .get(self.index)
+                            .map(|body| body.to_string())
+                    })
+                    .unwrap_or_else(|| self.params.clone()),
+            ),
+            PLACE::XML => (
+                self.url.clone(),
+                xml_sikkish_each(&self.params, payload)
+                    .get(self.index)
+                    .cloned()
+                    .unwrap_or_else(|| self.params.clone()),
+            ),
+        }
+    }
+
+    // Same endpoint with body sent exactly as given, for payloads that are a whole document
+    pub fn with_body(&self, body: &str) -> InjectionPoint {
+        InjectionPoint {
+            params: body.to_string(),
+            index: usize::MAX,
+            ..self.clone()
+        }
+    }
+
+    // Same point with the parameter's whole value set to value, for payloads that can't follow the original
+    pub fn with_value(&self, value: &str) -> InjectionPoint {
+        let replace = |pairs: &str| {
+            pairs
+                .split('&')
+                .map(|pair| match pair.split_once('=') {
+                    Some((name, _)) if name == self.name => format!("{}={}", name, urlencoding::encode(value)),
+                    _ => pair.to_string(),
+                })
+                .collect::<Vec<_>>()
+                .join("&")
+        };
+        let mut point = self.clone();
+        match self.place {
+            PLACE::GET => {
+                if let Some((path, query)) = self.url.split_once('?') {
+                    point.url = format!("{}?{}", path, replace(query));
+                }
+            }
+            PLACE::POST => point.params = replace(&self.params),
+            PLACE::JSON => {
+                if let Ok(Value::Object(mut map)) = serde_json::from_str::<Value>(&self.params) {
+                    map.insert(self.name.clone(), Value::String(value.to_string()));
+                    point.params = Value::Object(map).to_string();
+                }
+            }
+            PLACE::XML => {}
+        }
+        point
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SqlResponse {
+    pub status: u16,
+    pub body: String,
+    pub elapsed: Duration,
+    pub request: String,
+    pub response: String,
+}
+
+fn probed_name(variant: &str) -> Option<String> {
+    variant
+        .split(|c| c == '?' || c == '&')
+        .find(|param| param.contains(PROBE))
+        .and_then(|param| param.split_once('='))
+        .map(|(name, _)| name.to_string())
+}
+
+// Form parameters without a value can't be reached by make_payloads_url, give them one
+fn fill_empty(params: &[String]) -> String {
+    params
+        .iter()
+        .filter(|param| !param.starts_with('#') && !param.starts_with('='))
+        .map(|param| {
+            if param.ends_with('=') {
+                format!("{}1", param)
+            } else {
+                param.to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+pub fn injection_points(base: &str, surface: &Surface) -> Vec<InjectionPoint> {
+    let mut points: Vec<InjectionPoint> = vec![];
+    let base_url = Url::parse(&format!("{}/", base)).ok();
+
+    let mut get_urls: Vec<String> = surface.urls.iter().filter(|url| url.contains('?')).cloned().collect();
+    let mut forms = vec![];
+
+    for method in &surface.methods {
+        if let [body] = method.parameters.as_slice() {
+            if let Ok(Value::Object(map)) = serde_json::from_str::<Value>(body.trim()) {
+                let action = match base_url.as_ref().and_then(|base| base.join(&method.url).ok()) {
+                    Some(action) => action.to_string(),
+                    None => continue,
+                };
+                for (index, name) in map.keys().enumerate() {
+                    points.push(InjectionPoint {
+                        url: action.clone(),
+                        method: method.name.clone(),
+                        place: PLACE::JSON,
+                        params: body.trim().to_string(),
+                        name: name.clone(),
+                        index,
+                    });
+                }
+                continue;
+            }
+        }
+
+        // XML bodies pulled out of scripts are SQL::XML's job
+        if method.url == "Found in JavaScript" || method.parameters.iter().any(|p| p.starts_with('<')) {
+            continue;
+        }
+        let action = match base_url.as_ref().and_then(|base| base.join(&method.url).ok()) {
+            Some(action) => action.to_string(),
+            None => continue,
+        };
+        let params = fill_empty(&method.parameters);
+        if params.is_empty() {
+            continue;
+        }
+        if method.name == "GET" {
+            get_urls.push(format!("{}?{}", action.split('?').next().unwrap_or(&action), params));
+        } else {
+            forms.push((action, method.name.clone(), params));
+        }
+    }
+
+    for url in &get_urls {
+        for (index, variant) in make_payloads_url(url, PROBE).iter().enumerate() {
+            if let Some(name) = probed_name(variant) {
+                points.push(InjectionPoint {
+                    url: url.clone(),
+                    method: "GET".to_string(),
+                    place: PLACE::GET,
+                    params: String::new(),
+                    name,
+                    index,
+                });
+            }
+        }
+    }
+
+    for (action, method, params) in &forms {
+        for (index, variant) in make_payloads_post(params, PROBE).iter().enumerate() {
+            if let Some(name) = probed_name(&format!("?{}", variant)) {
+                points.push(InjectionPoint {
+                    url: action.clone(),
+                    method: method.clone(),
+                    place: PLACE::POST,
+                    params: params.clone(),
+                    name,
+                    index,
+                });
+            }
+        }
+    }
+
+    // Same parameter of the same endpoint is only worth testing once
+    let mut seen = std::collections::HashSet::new();
+    points.retain(|point| {
+        let path = point.url.split('?').next().unwrap_or("").to_string();
+        seen.insert((path, point.method.clone(), point.name.clone()))
+    });
+    points
+}
+
+// XML bodies method_parser pulled out of xmlHttp.send(), one point per element
+pub fn xml_points(base: &str, surface: &Surface) -> Vec<InjectionPoint> {
+    let mut points = vec![];
+    let base_url = Url::parse(&format!("{}/", base)).ok();
+    let tag_regex = Regex::new(XML_ELEMENT).unwrap();
+
+    for method in &surface.methods {
+        let body = match method.parameters.as_slice() {
+            [body] if body.trim().starts_with('<') => body.replace("\\\"", "\"").replace("\\/", "/"),
+            _ => continue,
+        };
+        // Without the xmlHttp.open() url there is nothing to send the body to
+        if method.url == "Found in JavaScript" {
+            continue;
+        }
+        let action = match base_url.as_ref().and_then(|base| base.join(&method.url).ok()) {
+            Some(action) => action.to_string(),
+            None => continue,
+        };
+        for (index, caps) in tag_regex.captures_iter(&body).enumerate() {
+            points.push(InjectionPoint {
+                url: action.clone(),
+                method: method.name.clone(),
+                place: PLACE::XML,
+                params: body.clone(),
+                name: caps[1].to_string(),
+                index,
+            });
+        }
+    }
+    points
+}
+
+// Header lines that say what the body of point is
+pub fn body_headers(point: &InjectionPoint) -> &'static str {
+    match point.place {
+        PLACE::POST => "Content-Type: application/x-www-form-urlencoded\r\n",
+        PLACE::JSON => "Content-Type: application/json\r\n",
+        PLACE::XML if point.params.contains("Envelope") => "Content-Type: text/xml; charset=utf-8\r\nSOAPAction: \"\"\r\n",
+        PLACE::XML => "Content-Type: application/xml\r\n",
+        PLACE::GET => "",
+    }
+}
+
+// Send point with payload over smuggle so the raw request/response can go into the Finding
+pub async fn send(
+    point: &InjectionPoint,
+    payload: &str,
+    headers: &Option<String>,
+    tls: &TlsConnector,
+) -> Option<SqlResponse> {
+    let (url, body) = point.inject(payload);
+    let parsed = Url::parse(&url).ok()?;
+    let endpoint = match parsed.query() {
+        Some(query) => format!("{}?{}", parsed.path(), query),
+        None => parsed.path().to_string(),
+    };
+
+    let mut extra = headers.clone().unwrap_or_default();
+    extra.push_str(body_headers(point));
+
+    let (request, response) = send_request(
+        url.clone(),
+        &endpoint,
+        &point.method,
+        Some(extra),
+        Some(body),
+        &RawOptions::default(),
+        Some(tls.clone()),
+    )
+    .await
+    .ok()?;
+
+    Some(SqlResponse {
+        status: response.status,
+        body: response.text(),
+        // Connect to last byte, the limiter's wait for a slot doesn't count against time-based checks
+        elapsed: response.timing.total,
+        request,
+        response: response.to_string(),
+    })
+}
+
+// Share of words two bodies must have in common to be the same page
+const SAME_PAGE: f64 = 0.95;
+
+pub fn same_page(a: &SqlResponse, b: &SqlResponse) -> bool {
+    a.status == b.status && similarity(&a.body, &b.body) >= SAME_PAGE
 }
 
 impl sql_scanner<'_>{
-    pub async fn run(self) {
-        println!("Fuck you fuck You  {:?}",self.technique);
-    }    
+    // How many columns UNION probing goes up to
+    pub fn max_columns(&self) -> usize {
+        match self.depth {
+            RATE::SLOW | RATE::MODERATE => 30,
+            _ => 15,
+        }
+    }
+
+    // Database behind point according to its error messages, lets the other techniques skip foreign payloads.
+    // Worked out once per point however many techniques ask
+    pub async fn dbms(&self, point: &InjectionPoint, tls: &TlsConnector) -> Option<DBMS> {
+        error::detect_once(self, point, tls).await.map(|result| result.dbms)
+    }
+
+    pub async fn run(self) -> Vec<Finding> {
+        let tls = create_tls_connector();
+        let points = injection_points(&self.target.base_url(), self.surface);
+        let concurrency = limiter().profile.max_in_flight;
+
+        match self.technique {
+            SQL::ERROR => {
+                stream::iter(points.iter())
+                    .map(|point| error::scan_point(&self, point, &tls))
+                    .buffer_unordered(concurrency)
+                    .filter_map(|finding| async move { finding })
+                    .collect()
+                    .await
+            }
+            SQL::UNION => {
+                stream::iter(points.iter())
+                    .map(|point| async {
+                        let dbms = self.dbms(point, &tls).await;
+                        union::scan_point(&self, point, &tls, dbms).await
+                    })
+                    .buffer_unordered(concurrency)
+                    .filter_map(|finding| async move { finding })
+                    .collect()
+                    .await
+            }
+            SQL::BLIND => {
+                stream::iter(points.iter())
+                    .map(|point| async {
+                        let dbms = self.dbms(point, &tls).await;
+                        blind::scan_point(&self, point, &tls, dbms).await
+                    })
+                    .buffer_unordered(concurrency)
+                    .flat_map(stream::iter)
+                    .collect()
+                    .await
+            }
+            SQL::XML => {
+                let points = xml_points(&self.target.base_url(), self.surface);
+                stream::iter(points.iter())
+                    .map(|point| xml::scan_point(&self, point, &tls))
+                    .buffer_unordered(concurrency)
+                    .flat_map(stream::iter)
+                    .collect()
+                    .await
+            }
+        }
+    }
 }
-pub fn sql_scanner(scantype: SQL) {
-    println!("Fuck you fuck You  {:?}",scantype);
-}
\ No newline at end of file
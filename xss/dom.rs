@@ -0,0 +1,643 @@
+use std::collections::HashMap;
+
+use futures::stream::{self, StreamExt};
+use scraper::{Html, Selector};
+use tokio_rustls::TlsConnector;
+use url::Url;
+
+use crate::report::report::{Finding, SEVERITY, CONFIDENCE};
+use crate::sender::crawler::Surface;
+use crate::sender::limiter::limiter;
+use crate::xss::xss::fetch;
+
+// Member chains an attacker controls through the url, the window name or the referring page
+const SOURCES: &[&[&str]] = &[
+    &["location", "hash"],
+    &["location", "search"],
+    &["location", "href"],
+    &["location", "pathname"],
+    &["document", "URL"],
+    &["document", "documentURI"],
+    &["document", "baseURI"],
+    &["document", "referrer"],
+    &["window", "name"],
+];
+
+// Calls whose argument ends up parsed as HTML or run as code, matched against the end of the chain
+const CALL_SINKS: &[&[&str]] = &[
+    &["document", "write"],
+    &["document", "writeln"],
+    &["eval"],
+    &["setTimeout"],
+    &["setInterval"],
+    &["Function"],
+    &["html"],
+    &["insertAdjacentHTML"],
+];
+
+// Properties that parse whatever gets assigned to them as HTML
+const PROPERTY_SINKS: &[&str] = &["innerHTML", "outerHTML", "srcdoc"];
+
+// Calls that make a value safe for the sinks above
+const SANITIZERS: &[&str] = &["encodeURIComponent", "encodeURI", "escape", "DOMPurify", "sanitize", "parseInt", "Number"];
+
+// Tokens one expression may span. Minified bundles chain whole modules with commas, following those to
+// the end from every assignment makes propagate quadratic in the script's size
+const MAX_EXPRESSION: usize = 256;
+
+// Keywords after which a slash starts a regex literal rather than a division
+const REGEX_KEYWORDS: &[&str] = &["return", "typeof", "case", "do", "else", "in", "of", "new", "delete", "void", "throw"];
+
+#[derive(Debug, Clone, PartialEq)]
+enum TOKEN {
+    IDENT(String),
+    STRING(String),
+    PUNCT(String),
+    NUMBER,
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    kind: TOKEN,
+    line: usize,
+}
+
+fn is_ident(token: Option<&Token>, name: &str) -> bool {
+    matches!(token, Some(Token { kind: TOKEN::IDENT(ident), .. }) if ident == name)
+}
+
+fn is_punct(token: Option<&Token>, punct: &str) -> bool {
+    matches!(token, Some(Token { kind: TOKEN::PUNCT(p), .. }) if p == punct)
+}
+
+fn ident(token: Option<&Token>) -> Option<&str> {
+    match token {
+        Some(Token { kind: TOKEN::IDENT(ident), .. }) => Some(ident),
+        _ => None,
+    }
+}
+
+// Good enough lexer for data flow: strings, template literals, regex literals and comments are
+// recognised so their contents never look like code, everything else is identifiers and punctuation
+fn tokenize(source: &str) -> Vec<Token> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens: Vec<Token> = vec![];
+    let mut line = 1;
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).copied();
+
+        if c == '\n' {
+            line += 1;
+            i += 1;
+        } else if c.is_whitespace() {
+            i += 1;
+        } else if c == '/' && next == Some('/') {
+            while i < chars.len() && chars[i] != '\n' {
+                i += 1;
+            }
+        } else if c == '/' && next == Some('*') {
+            i += 2;
+            while i < chars.len() && !(chars[i] == '*' && chars.get(i + 1) == Some(&'/')) {
+                if chars[i] == '\n' {
+                    line += 1;
+                }
+                i += 1;
+            }
+            i += 2;
+        } else if c == '\'' || c == '"' {
+            let mut value = String::new();
+            i += 1;
+            while i < chars.len() && chars[i] != c && chars[i] != '\n' {
+                if chars[i] == '\\' {
+                    i += 1;
+                }
+                if let Some(&ch) = chars.get(i) {
+                    value.push(ch);
+                }
+                i += 1;
+            }
+            i += 1;
+            tokens.push(Token { kind: TOKEN::STRING(value), line });
+        } else if c == '`' {
+            // Template literal, ${} parts are tokenized as a parenthesised expression so taint flows through them
+            let start_line = line;
+            tokens.push(Token { kind: TOKEN::STRING(String::new()), line });
+            i += 1;
+            while i < chars.len() && chars[i] != '`' {
+                if chars[i] == '\\' {
+                    i += 2;
+                    continue;
+                }
+                if chars[i] == '$' && chars.get(i + 1) == Some(&'{') {
+                    let mut depth = 1;
+                    let start = i + 2;
+                    i += 2;
+                    while i < chars.len() && depth > 0 {
+                        match chars[i] {
+                            '{' => depth += 1,
+                            '}' => depth -= 1,
+                            '\n' => line += 1,
+                            _ => {}
+                        }
+                        i += 1;
+                    }
+                    let inner: String = chars[start..i.saturating_sub(1).max(start)].iter().collect();
+                    tokens.push(Token { kind: TOKEN::PUNCT("+".to_string()), line: start_line });
+                    tokens.push(Token { kind: TOKEN::PUNCT("(".to_string()), line: start_line });
+                    tokens.extend(tokenize(&inner).into_iter().map(|mut token| {
+                        token.line += start_line - 1;
+                        token
+                    }));
+                    tokens.push(Token { kind: TOKEN::PUNCT(")".to_string()), line: start_line });
+                    continue;
+                }
+                if chars[i] == '\n' {
+                    line += 1;
+                }
+                i += 1;
+            }
+            i += 1;
+        } else if c.is_ascii_digit() {
+            while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '.' || chars[i] == '_') {
+                i += 1;
+            }
+            tokens.push(Token { kind: TOKEN::NUMBER, line });
+        } else if c.is_alphabetic() || c == '_' || c == '$' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_' || chars[i] == '$') {
+                i += 1;
+            }
+            tokens.push(Token { kind: TOKEN::IDENT(chars[start..i].iter().collect()), line });
+        } else if c == '/' && starts_regex(tokens.last()) {
+            let mut class = false;
+            i += 1;
+            while i < chars.len() && chars[i] != '\n' {
+                match chars[i] {
+                    '\\' => i += 1,
+                    '[' => class = true,
+                    ']' => class = false,
+                    '/' if !class => break,
+                    _ => {}
+                }
+                i += 1;
+            }
+            i += 1;
+            while i < chars.len() && chars[i].is_ascii_alphabetic() {
+                i += 1;
+            }
+            tokens.push(Token { kind: TOKEN::STRING(String::new()), line });
+        } else {
+            let rest: String = chars[i..chars.len().min(i + 4)].iter().collect();
+            let punct = ["===", "!==", ">>>", "...", "**=", "==", "!=", "+=", "-=", "=>", "&&", "||", "<=", ">=", "++", "--", "?.", "??"]
+                .iter()
+                .find(|op| rest.starts_with(**op))
+                .map(|op| op.to_string())
+                .unwrap_or_else(|| c.to_string());
+            i += punct.chars().count();
+            tokens.push(Token { kind: TOKEN::PUNCT(punct), line });
+        }
+    }
+    tokens
+}
+
+fn starts_regex(previous: Option<&Token>) -> bool {
+    match previous.map(|token| &token.kind) {
+        None => true,
+        Some(TOKEN::PUNCT(p)) => !matches!(p.as_str(), ")" | "]" | "}"),
+        Some(TOKEN::IDENT(ident)) => REGEX_KEYWORDS.contains(&ident.as_str()),
+        _ => false,
+    }
+}
+
+// a.b?.c starting at i, with the index after it
+fn chain(tokens: &[Token], i: usize) -> (Vec<String>, usize) {
+    let mut names = vec![];
+    let mut j = i;
+    while let Some(name) = ident(tokens.get(j)) {
+        names.push(name.to_string());
+        if is_punct(tokens.get(j + 1), ".") || is_punct(tokens.get(j + 1), "?.") {
+            j += 2;
+        } else {
+            j += 1;
+            break;
+        }
+    }
+    (names, j)
+}
+
+fn contains_sequence(names: &[String], sequence: &[&str]) -> bool {
+    names.windows(sequence.len()).any(|window| window.iter().zip(sequence).all(|(a, b)| a == b))
+}
+
+fn ends_with_sequence(names: &[String], sequence: &[&str]) -> bool {
+    names.len() >= sequence.len() && names[names.len() - sequence.len()..].iter().zip(sequence).all(|(a, b)| a == b)
+}
+
+// Tokens of the expression starting at i, up to the ; or , or closing bracket that ends it, at most
+// MAX_EXPRESSION of them
+fn expression(tokens: &[Token], i: usize) -> usize {
+    let mut depth = 0i32;
+    let mut j = i;
+    while let Some(token) = tokens.get(j).filter(|_| j - i < MAX_EXPRESSION) {
+        if let TOKEN::PUNCT(p) = &token.kind {
+            match p.as_str() {
+                "(" | "[" | "{" => depth += 1,
+                ")" | "]" | "}" if depth == 0 => break,
+                ")" | "]" | "}" => depth -= 1,
+                ";" | "," if depth == 0 => break,
+                _ => {}
+            }
+        }
+        // A new line at depth 0 after a complete operand usually starts the next statement
+        if depth == 0 && j > i && token.line != tokens[j - 1].line {
+            let continues = matches!(&tokens[j - 1].kind, TOKEN::PUNCT(p) if p != ")" && p != "]")
+                || matches!(&token.kind, TOKEN::PUNCT(p) if p == "+" || p == "." || p == "?.");
+            if !continues {
+                break;
+            }
+        }
+        j += 1;
+    }
+    j
+}
+
+// Arguments of the call whose ( is at open, with the index of the matching )
+fn arguments(tokens: &[Token], open: usize) -> (usize, usize) {
+    let mut depth = 0;
+    for (j, token) in tokens.iter().enumerate().skip(open) {
+        if let TOKEN::PUNCT(p) = &token.kind {
+            match p.as_str() {
+                "(" | "[" | "{" => depth += 1,
+                ")" | "]" | "}" => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return (open + 1, j);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+    (open + 1, tokens.len())
+}
+
+// First top-level comma between start and end
+fn comma(tokens: &[Token], start: usize, end: usize) -> Option<usize> {
+    let mut depth = 0;
+    for (j, token) in tokens.iter().enumerate().take(end).skip(start) {
+        if let TOKEN::PUNCT(p) = &token.kind {
+            match p.as_str() {
+                "(" | "[" | "{" => depth += 1,
+                ")" | "]" | "}" => depth -= 1,
+                "," if depth == 0 => return Some(j),
+                _ => {}
+            }
+        }
+    }
+    None
+}
+
+// Source reaching tokens[start..end], and whether it did so without going through a variable
+fn tainted(tokens: &[Token], start: usize, end: usize, taint: &HashMap<String, String>) -> Option<(String, bool)> {
+    let range = &tokens[start..end.min(tokens.len())];
+    if range.iter().any(|token| matches!(&token.kind, TOKEN::IDENT(ident) if SANITIZERS.contains(&ident.as_str()))) {
+        return None;
+    }
+    let mut j = start;
+    while j < end.min(tokens.len()) {
+        if ident(tokens.get(j)).is_none() || (j > 0 && (is_punct(tokens.get(j - 1), ".") || is_punct(tokens.get(j - 1), "?."))) {
+            j += 1;
+            continue;
+        }
+        let (names, next) = chain(tokens, j);
+        if let Some(source) = SOURCES.iter().find(|source| contains_sequence(&names, source)) {
+            return Some((source.join("."), true));
+        }
+        if let Some(origin) = taint.get(&names[0]) {
+            return Some((origin.clone(), false));
+        }
+        j = next;
+    }
+    None
+}
+
+// Parameter name of the handler that starts at i: function (e), function name(e), (e) => or e =>
+fn handler_param(tokens: &[Token], i: usize) -> Option<String> {
+    let mut j = i;
+    if is_ident(tokens.get(j), "async") {
+        j += 1;
+    }
+    if is_ident(tokens.get(j), "function") {
+        j += 1;
+        if ident(tokens.get(j)).is_some() {
+            j += 1;
+        }
+    }
+    if is_punct(tokens.get(j), "(") {
+        return ident(tokens.get(j + 1)).map(String::from);
+    }
+    match ident(tokens.get(j)) {
+        Some(param) if is_punct(tokens.get(j + 1), "=>") => Some(param.to_string()),
+        _ => None,
+    }
+}
+
+// Variables holding attacker data, with the source they got it from, grown until nothing changes
+fn propagate(tokens: &[Token]) -> HashMap<String, String> {
+    let mut taint: HashMap<String, String> = HashMap::new();
+    for _ in 0..5 {
+        let before = taint.len();
+        for i in 0..tokens.len() {
+            // postMessage: addEventListener("message", handler) and onmessage = handler
+            if is_ident(tokens.get(i), "addEventListener")
+                && is_punct(tokens.get(i + 1), "(")
+                && matches!(tokens.get(i + 2), Some(Token { kind: TOKEN::STRING(event), .. }) if event == "message")
+                && is_punct(tokens.get(i + 3), ",")
+            {
+                if let Some(param) = handler_param(tokens, i + 4) {
+                    taint.entry(param).or_insert_with(|| "postMessage data".to_string());
+                }
+            }
+            if is_ident(tokens.get(i), "onmessage") && is_punct(tokens.get(i + 1), "=") {
+                if let Some(param) = handler_param(tokens, i + 2) {
+                    taint.entry(param).or_insert_with(|| "postMessage data".to_string());
+                }
+            }
+
+            let (name, value) = match (tokens.get(i).map(|t| &t.kind), ident(tokens.get(i + 1))) {
+                (Some(TOKEN::IDENT(keyword)), Some(name))
+                    if matches!(keyword.as_str(), "var" | "let" | "const") && is_punct(tokens.get(i + 2), "=") =>
+                {
+                    (name.to_string(), i + 3)
+                }
+                (Some(TOKEN::IDENT(name)), _)
+                    if (is_punct(tokens.get(i + 1), "=") || is_punct(tokens.get(i + 1), "+="))
+                        && !(i > 0 && is_punct(tokens.get(i - 1), ".")) =>
+                {
+                    (name.clone(), i + 2)
+                }
+                _ => continue,
+            };
+            if taint.contains_key(&name) {
+                continue;
+            }
+            if let Some((origin, _)) = tainted(tokens, value, expression(tokens, value), &taint) {
+                taint.insert(name, origin);
+            }
+        }
+        if taint.len() == before {
+            break;
+        }
+    }
+    taint
+}
+
+pub struct Flow {
+    pub source: String,
+    pub sink: String,
+    pub line: usize,
+    pub direct: bool,
+}
+
+pub fn analyse(script: &str) -> Vec<Flow> {
+    let tokens = tokenize(script);
+    let taint = propagate(&tokens);
+    let mut flows = vec![];
+
+    for i in 0..tokens.len() {
+        let name = match ident(tokens.get(i)) {
+            Some(name) => name,
+            None => continue,
+        };
+        let member = i > 0 && (is_punct(tokens.get(i - 1), ".") || is_punct(tokens.get(i - 1), "?."));
+
+        // el.innerHTML = value
+        if member
+            && PROPERTY_SINKS.contains(&name)
+            && (is_punct(tokens.get(i + 1), "=") || is_punct(tokens.get(i + 1), "+="))
+        {
+            if let Some((source, direct)) = tainted(&tokens, i + 2, expression(&tokens, i + 2), &taint) {
+                flows.push(Flow { source, sink: name.to_string(), line: tokens[i].line, direct });
+            }
+            continue;
+        }
+
+        // sink(value), reading the chain backwards so window.eval and $(el).html both match
+        if !is_punct(tokens.get(i + 1), "(") {
+            continue;
+        }
+        let mut start = i;
+        while start >= 2 && is_punct(tokens.get(start - 1), ".") && ident(tokens.get(start - 2)).is_some() {
+            start -= 2;
+        }
+        let (names, _) = chain(&tokens, start);
+        let sink = match CALL_SINKS.iter().find(|sink| ends_with_sequence(&names, sink)) {
+            Some(sink) => sink,
+            None => continue,
+        };
+        // jQuery's .html() is a method, bare html() is somebody else's function
+        if name == "html" && !member {
+            continue;
+        }
+        let (mut from, mut to) = arguments(&tokens, i + 1);
+        match name {
+            // setTimeout(function () {..}) is fine, only a string first argument gets evaluated
+            "setTimeout" | "setInterval" => {
+                if is_ident(tokens.get(from), "function") || handler_param(&tokens, from).is_some() {
+                    continue;
+                }
+                to = comma(&tokens, from, to).unwrap_or(to);
+            }
+            // insertAdjacentHTML(position, html)
+            "insertAdjacentHTML" => from = comma(&tokens, from, to).map(|c| c + 1).unwrap_or(to),
+            _ => {}
+        }
+        if let Some((source, direct)) = tainted(&tokens, from, to, &taint) {
+            flows.push(Flow { source, sink: names.join("."), line: tokens[i].line, direct });
+        }
+    }
+    flows
+}
+
+fn line_of(script: &str, line: usize) -> String {
+    let text = script.lines().nth(line.saturating_sub(1)).unwrap_or("").trim();
+    text.chars().take(200).collect()
+}
+
+fn flow_findings(script_url: &str, page: &str, script: &str) -> Vec<Finding> {
+    let mut seen = vec![];
+    analyse(script)
+        .into_iter()
+        .filter(|flow| {
+            let key = (flow.line, flow.sink.clone());
+            let new = !seen.contains(&key);
+            seen.push(key);
+            new
+        })
+        .map(|flow| Finding {
+            url: page.to_string(),
+            method: "GET".to_string(),
+            parameter: flow.source.clone(),
+            detail: format!(
+                "{} reaches {} at {} line {}{}",
+                flow.source,
+                flow.sink,
+                script_url,
+                flow.line,
+                if flow.direct { "" } else { " through a variable" }
+            ),
+            response: line_of(script, flow.line),
+            cwe: Some(79),
+            ..Finding::new(
+                "xss",
+                "DOM-based cross-site scripting",
+                SEVERITY::MEDIUM,
+                if flow.direct { CONFIDENCE::FIRM } else { CONFIDENCE::TENTATIVE },
+            )
+        })
+        .collect()
+}
+
+// Scheme, host and port all have to match, a prefix match lets https://target.example.evil.com through
+fn same_origin(base: &str, url: &Url) -> bool {
+    Url::parse(base).map(|base| base.origin() == url.origin()).unwrap_or(false)
+}
+
+// Inline scripts of every crawled page plus the external scripts they load from the target itself,
+// third-party libraries are not the target's code. Pages come from the crawl, only scripts it didn't
+// fetch are requested here
+pub async fn scan(base: &str, surface: &Surface, headers: &Option<String>, tls: &TlsConnector) -> Vec<Finding> {
+    let concurrency = limiter().profile.max_in_flight;
+    let script_selector = Selector::parse("script").unwrap();
+    let pages = &surface.pages;
+
+    let mut findings = vec![];
+    // External script url and the first page that loads it
+    let mut external: Vec<(String, String)> = vec![];
+    // Scripts the crawl already fetched as pages of their own
+    let crawled: Vec<&String> = pages
+        .iter()
+        .filter(|(page, _)| Url::parse(page).map(|url| url.path().ends_with(".js")).unwrap_or(false))
+        .map(|(page, _)| page)
+        .collect();
+    for (page, body) in pages {
+        if crawled.contains(&page) {
+            findings.extend(flow_findings(page, page, body));
+            continue;
+        }
+        let document = Html::parse_document(body);
+        for script in document.select(&script_selector) {
+            match script.value().attr("src") {
+                Some(src) => {
+                    let url = match Url::parse(page).and_then(|page| page.join(src)) {
+                        Ok(url) => url,
+                        Err(_) => continue,
+                    };
+                    if !same_origin(base, &url) {
+                        continue;
+                    }
+                    let url = url.to_string();
+                    if !crawled.contains(&&url) && !external.iter().any(|(s, _)| *s == url) {
+                        external.push((url, page.clone()));
+                    }
+                }
+                None => findings.extend(flow_findings(
+                    &format!("inline script of {}", page),
+                    page,
+                    &script.text().collect::<String>(),
+                )),
+            }
+        }
+    }
+
+    let scripts: Vec<Vec<Finding>> = stream::iter(external.iter())
+        .map(|(script, page)| async move {
+            match fetch(script, headers, tls).await {
+                Some((_, source)) => flow_findings(script, page, &source),
+                None => vec![],
+            }
+        })
+        .buffer_unordered(concurrency)
+        .collect()
+        .await;
+    findings.extend(scripts.into_iter().flatten());
+    findings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sinks(script: &str) -> Vec<(String, String, bool)> {
+        analyse(script).into_iter().map(|flow| (flow.source, flow.sink, flow.direct)).collect()
+    }
+
+    #[test]
+    fn source_straight_into_a_sink() {
+        assert_eq!(
+            sinks("document.getElementById('out').innerHTML = location.hash.slice(1);"),
+            vec![("location.hash".to_string(), "innerHTML".to_string(), true)]
+        );
+        assert_eq!(sinks("eval(window.name)"), vec![("window.name".to_string(), "eval".to_string(), true)]);
+        assert_eq!(
+            sinks("$('#out').html(document.referrer)"),
+            vec![("document.referrer".to_string(), "html".to_string(), true)]
+        );
+    }
+
+    #[test]
+    fn taint_through_variables_and_templates() {
+        let script = "var q = location.search;\nlet page = `<b>${q}</b>`;\ndocument.write(page);";
+        let flows = analyse(script);
+        assert_eq!(flows.len(), 1);
+        assert_eq!(flows[0].source, "location.search");
+        assert_eq!(flows[0].sink, "document.write");
+        assert_eq!(flows[0].line, 3);
+        assert!(!flows[0].direct);
+    }
+
+    #[test]
+    fn post_message_handlers() {
+        let script = "window.addEventListener('message', function (e) { out.innerHTML = e.data; });";
+        assert_eq!(sinks(script), vec![("postMessage data".to_string(), "innerHTML".to_string(), false)]);
+    }
+
+    #[test]
+    fn safe_code_has_no_flows() {
+        assert!(sinks("out.innerHTML = encodeURIComponent(location.hash);").is_empty());
+        assert!(sinks("out.textContent = location.hash;").is_empty());
+        assert!(sinks("setTimeout(function () { render(location.hash); }, 10);").is_empty());
+        assert!(sinks("html(location.hash)").is_empty());
+        assert!(sinks("var s = 'location.hash'; eval(s);").is_empty());
+        assert!(sinks("// eval(location.hash)\nvar r = /eval(location.hash)/;").is_empty());
+    }
+
+    #[test]
+    fn insert_adjacent_html_checks_the_markup_argument() {
+        assert!(sinks("el.insertAdjacentHTML(location.hash, '<b>')").is_empty());
+        assert_eq!(sinks("el.insertAdjacentHTML('beforeend', location.hash)").len(), 1);
+    }
+
+    #[test]
+    fn long_expressions_are_cut_off() {
+        let padding = vec!["a"; MAX_EXPRESSION].join(" + ");
+        assert!(sinks(&format!("out.innerHTML = {} + location.hash;", padding)).is_empty());
+        assert_eq!(sinks(&format!("out.innerHTML = location.hash + {};", padding)).len(), 1);
+    }
+
+    #[test]
+    fn external_scripts_must_share_the_origin() {
+        let base = "https://target.example";
+        let url = |url: &str| Url::parse(url).unwrap();
+        assert!(same_origin(base, &url("https://target.example/static/app.js")));
+        assert!(same_origin(base, &url("https://target.example:443/app.js")));
+        assert!(!same_origin(base, &url("https://target.example.evil.com/app.js")));
+        assert!(!same_origin(base, &url("https://target.example@evil.com/app.js")));
+        assert!(!same_origin(base, &url("http://target.example/app.js")));
+        assert!(!same_origin(base, &url("https://target.example:8443/app.js")));
+        assert!(!same_origin(base, &url("https://cdn.target.example/app.js")));
+    }
+}
@@ -1 +1,5 @@
-pub mod xss;
\ No newline at end of file
+pub mod xss;
+pub mod context;
+pub mod reflected;
+pub mod stored;
+pub mod dom;
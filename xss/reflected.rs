@@ -0,0 +1,38 @@
+use tokio_rustls::TlsConnector;
+
+use crate::report::report::{Finding, SEVERITY, CONFIDENCE};
+use crate::sqli::sqli::{send, InjectionPoint};
+use crate::xss::context::{breakouts, canary, contexts, executable};
+
+// Canary first to learn where the parameter is echoed, then only the breakouts that fit there
+pub async fn scan_point(point: &InjectionPoint, headers: &Option<String>, tls: &TlsConnector) -> Option<Finding> {
+    let probe = canary();
+    let response = send(point, &probe, headers, tls).await?;
+
+    for context in contexts(&response.body, &probe) {
+        let mark = canary();
+        for (payload, replace) in breakouts(&context, &mark) {
+            let response = if replace {
+                send(&point.with_value(&payload), "", headers, tls).await
+            } else {
+                send(point, &payload, headers, tls).await
+            };
+            let response = match response {
+                Some(response) if executable(&response.body, &mark) => response,
+                _ => continue,
+            };
+            return Some(Finding {
+                url: point.url.clone(),
+                method: point.method.clone(),
+                parameter: point.name.clone(),
+                payload,
+                detail: format!("reflected in {}, payload comes back as executable markup", context.describe()),
+                request: response.request,
+                response: response.response,
+                cwe: Some(79),
+                ..Finding::new("xss", "Reflected cross-site scripting", SEVERITY::HIGH, CONFIDENCE::CONFIRMED)
+            });
+        }
+    }
+    None
+}
@@ -0,0 +1,234 @@
+use std::collections::HashMap;
+
+use futures::stream::{self, StreamExt};
+use tokio_rustls::TlsConnector;
+use url::Url;
+
+use crate::oob::oob::{callback, inject, DELIVERY};
+use crate::payload_builder::payload_builder::oob_xss;
+use crate::report::report::{Finding, SEVERITY, CONFIDENCE};
+use crate::sender::crawler::{canonical, Surface};
+use crate::sender::limiter::limiter;
+use crate::sqli::sqli::{send, InjectionPoint, PLACE};
+use crate::xss::context::{breakouts, canary, contexts, executable};
+use crate::xss::xss::fetch;
+
+// Revisiting is a GET per page per round, keep it bounded on big sites
+const MAX_PAGES: usize = 200;
+
+// Pages stored input could show up on, every page the crawl reached
+fn sink_pages(surface: &Surface) -> Vec<String> {
+    let mut pages: Vec<String> = surface
+        .urls
+        .iter()
+        .filter_map(|page| Url::parse(page).ok())
+        .map(|url| canonical(&url))
+        .collect();
+    pages.sort();
+    pages.dedup();
+    pages.truncate(MAX_PAGES);
+    pages
+}
+
+// Points stored input can come in through: every form the crawl found, GET forms such as search boxes and
+// guestbooks included. Url parameters without a form are left to the reflected check
+pub fn form_points(base: &str, surface: &Surface, points: &[InjectionPoint]) -> Vec<InjectionPoint> {
+    let base_url = Url::parse(&format!("{}/", base)).ok();
+    let get_forms: Vec<String> = surface
+        .methods
+        .iter()
+        .filter(|method| method.name.eq_ignore_ascii_case("GET"))
+        .filter_map(|method| base_url.as_ref()?.join(&method.url).ok())
+        .map(|action| action.to_string().split('?').next().unwrap_or_default().to_string())
+        .collect();
+    points
+        .iter()
+        .filter(|point| match point.place {
+            PLACE::POST => true,
+            PLACE::GET => get_forms.iter().any(|action| point.url.split('?').next() == Some(action.as_str())),
+            _ => false,
+        })
+        .cloned()
+        .collect()
+}
+
+// Which field each sink page belongs to, by the tag that showed up on it. One sink per field is enough
+// to prove it
+fn correlate<'a>(tagged: &HashMap<String, &'a InjectionPoint>, mut sinks: Vec<(String, String)>) -> Vec<(&'a InjectionPoint, String, String)> {
+    sinks.sort();
+    sinks.dedup_by(|a, b| a.0 == b.0);
+    sinks
+        .into_iter()
+        .filter_map(|(tag, page)| tagged.get(&tag).map(|point| (*point, page, tag)))
+        .collect()
+}
+
+// Pages whose body contains one of the tags, as (tag, page)
+async fn revisit(pages: &[String], tags: &[String], headers: &Option<String>, tls: &TlsConnector) -> Vec<(String, String)> {
+    stream::iter(pages.iter())
+        .map(|page| async move {
+            let (_, body) = fetch(page, headers, tls).await?;
+            Some(
+                tags.iter()
+                    .filter(|tag| body.contains(tag.as_str()))
+                    .map(|tag| (tag.clone(), page.clone()))
+                    .collect::<Vec<_>>(),
+            )
+        })
+        .buffer_unordered(limiter().profile.max_in_flight)
+        .filter_map(|found| async move { found })
+        .flat_map(stream::iter)
+        .collect()
+        .await
+}
+
+// Breakouts for the contexts tag landed in on page, submitted through point and checked on page
+async fn confirm(
+    point: &InjectionPoint,
+    page: &str,
+    tag: &str,
+    headers: &Option<String>,
+    tls: &TlsConnector,
+) -> Option<Finding> {
+    let (_, body) = fetch(page, headers, tls).await?;
+    for context in contexts(&body, tag) {
+        let mark = canary();
+        for (payload, replace) in breakouts(&context, &mark) {
+            let submitted = if replace {
+                send(&point.with_value(&payload), "", headers, tls).await
+            } else {
+                send(point, &payload, headers, tls).await
+            };
+            let submitted = match submitted {
+                Some(submitted) => submitted,
+                None => continue,
+            };
+            let (response, body) = match fetch(page, headers, tls).await {
+                Some(sink) => sink,
+                None => continue,
+            };
+            if !executable(&body, &mark) {
+                continue;
+            }
+            return Some(Finding {
+                url: point.url.clone(),
+                method: point.method.clone(),
+                parameter: point.name.clone(),
+                payload,
+                detail: format!("submitted here, rendered on {} in {}", page, context.describe()),
+                request: submitted.request,
+                response,
+                cwe: Some(79),
+                ..Finding::new("xss", "Stored cross-site scripting", SEVERITY::HIGH, CONFIDENCE::CONFIRMED)
+            });
+        }
+    }
+    None
+}
+
+// Payloads that call home when rendered somewhere the scanner can't see, e.g. a back office
+async fn blind(point: &InjectionPoint, headers: &Option<String>, tls: &TlsConnector) {
+    for (index, payload) in oob_xss("").iter().enumerate() {
+        let delivery = if payload.starts_with("javascript:") { DELIVERY::REPLACE } else { DELIVERY::APPEND };
+        let build = |url: &str| oob_xss(url)[index].clone();
+        inject(point, "xss", "Blind cross-site scripting (out-of-band)", build, delivery, headers, tls).await;
+    }
+}
+
+// Submit a unique tag through every form field, look for the tags on every page, then confirm each
+// (field, page) pair with context breakouts
+pub async fn scan(
+    surface: &Surface,
+    points: &[InjectionPoint],
+    headers: &Option<String>,
+    tls: &TlsConnector,
+) -> Vec<Finding> {
+    let concurrency = limiter().profile.max_in_flight;
+    let tagged: HashMap<String, &InjectionPoint> = stream::iter(points.iter())
+        .map(|point| async move {
+            let tag = canary();
+            send(point, &tag, headers, tls).await.map(|_| (tag, point))
+        })
+        .buffer_unordered(concurrency)
+        .filter_map(|submitted| async move { submitted })
+        .collect()
+        .await;
+    if callback().is_some() {
+        stream::iter(points.iter())
+            .map(|point| blind(point, headers, tls))
+            .buffer_unordered(concurrency)
+            .collect::<Vec<_>>()
+            .await;
+    }
+    if tagged.is_empty() {
+        return vec![];
+    }
+
+    let pages = sink_pages(surface);
+    let tags: Vec<String> = tagged.keys().cloned().collect();
+    let sinks = revisit(&pages, &tags, headers, tls).await;
+    let pairs = correlate(&tagged, sinks);
+
+    stream::iter(pairs.iter())
+        .map(|(point, page, tag)| confirm(point, page, tag, headers, tls))
+        .buffer_unordered(concurrency)
+        .filter_map(|finding| async move { finding })
+        .collect()
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::method_parser::method_parser::Method;
+    use crate::sqli::sqli::injection_points;
+
+    fn method(name: &str, url: &str, parameters: &[&str]) -> Method {
+        Method { name: name.to_string(), parameters: parameters.iter().map(|p| p.to_string()).collect(), url: url.to_string() }
+    }
+
+    fn surface() -> Surface {
+        Surface {
+            urls: vec!["http://target.example/article?id=3".to_string()],
+            methods: vec![method("POST", "/comment", &["text="]), method("GET", "/guestbook/sign", &["name=", "message="])],
+            ..Surface::default()
+        }
+    }
+
+    #[test]
+    fn get_and_post_forms_are_stored_input() {
+        let surface = surface();
+        let points = injection_points("http://target.example", &surface);
+        let forms = form_points("http://target.example", &surface, &points);
+        let mut names: Vec<(String, String)> = forms.iter().map(|point| (point.method.clone(), point.name.clone())).collect();
+        names.sort();
+        assert_eq!(
+            names,
+            vec![
+                ("GET".to_string(), "message".to_string()),
+                ("GET".to_string(), "name".to_string()),
+                ("POST".to_string(), "text".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn tags_lead_back_to_their_field() {
+        let surface = surface();
+        let points = form_points("http://target.example", &surface, &injection_points("http://target.example", &surface));
+        let text = points.iter().find(|point| point.name == "text").unwrap();
+        let message = points.iter().find(|point| point.name == "message").unwrap();
+        let tagged: HashMap<String, &InjectionPoint> =
+            [("krktag1".to_string(), text), ("krktag2".to_string(), message)].into_iter().collect();
+
+        let sinks = vec![
+            ("krktag2".to_string(), "http://target.example/guestbook".to_string()),
+            ("krktag1".to_string(), "http://target.example/b".to_string()),
+            ("krktag1".to_string(), "http://target.example/a".to_string()),
+            ("unknown".to_string(), "http://target.example/c".to_string()),
+        ];
+        let pairs = correlate(&tagged, sinks);
+        let found: Vec<(&str, &str)> = pairs.iter().map(|(point, page, _)| (point.name.as_str(), page.as_str())).collect();
+        assert_eq!(found, vec![("text", "http://target.example/a"), ("message", "http://target.example/guestbook")]);
+    }
+}
@@ -1,14 +1,54 @@
+use futures::stream::{self, StreamExt};
+use tokio_rustls::TlsConnector;
+use url::Url;
+
 use crate::CUSTOMER;
- 
+use crate::report::report::Finding;
+use crate::sender::crawler::Surface;
+use crate::sender::limiter::limiter;
+use crate::sender::suraw::{create_tls_connector, send_request, RawOptions};
+use crate::sqli::sqli::{injection_points, PLACE};
+use crate::xss::{dom, reflected, stored};
+
 pub struct XSSCAN<'a> {
-    pub target: &'a CUSTOMER
+    pub target: &'a CUSTOMER,
+    pub surface: &'a Surface,
+    pub headers: Option<String>,
+}
+
+// Plain GET over send_request so cookies and --header lines go along, returns (response, body)
+pub async fn fetch(url: &str, headers: &Option<String>, tls: &TlsConnector) -> Option<(String, String)> {
+    let parsed = Url::parse(url).ok()?;
+    let endpoint = match parsed.query() {
+        Some(query) => format!("{}?{}", parsed.path(), query),
+        None => parsed.path().to_string(),
+    };
+    let extra = headers.clone().unwrap_or_default();
+    let (_, response) = send_request(url.to_string(), &endpoint, "GET", Some(extra), None, &RawOptions::default(), Some(tls.clone()))
+        .await
+        .ok()?;
+    Some((response.to_string(), response.text()))
 }
- 
 
 impl XSSCAN<'_> {
-    pub async fn run(self) {
+    pub async fn run(self) -> Vec<Finding> {
+        let tls = create_tls_connector();
+        // Form fields and url parameters, JSON bodies rarely end up in HTML
+        let points: Vec<_> = injection_points(&self.target.base_url(), self.surface)
+            .into_iter()
+            .filter(|point| point.place == PLACE::GET || point.place == PLACE::POST)
+            .collect();
 
-        println!("xss qotaqbastir ");
+        let mut findings: Vec<Finding> = stream::iter(points.iter())
+            .map(|point| reflected::scan_point(point, &self.headers, &tls))
+            .buffer_unordered(limiter().profile.max_in_flight)
+            .filter_map(|finding| async move { finding })
+            .collect()
+            .await;
 
+        let forms = stored::form_points(&self.target.base_url(), self.surface, &points);
+        findings.extend(stored::scan(self.surface, &forms, &self.headers, &tls).await);
+        findings.extend(dom::scan(&self.target.base_url(), self.surface, &self.headers, &tls).await);
+        findings
     }
-}
\ No newline at end of file
+}